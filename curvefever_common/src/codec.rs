@@ -0,0 +1,298 @@
+use std::io::{Read, Write};
+
+/// Binary encoding used for websocket messages, see the derive macro in `curvefever_derive` for
+/// the layout of structs and enums.
+pub trait Encode {
+    fn encode(&self, stream: &mut impl Write) -> std::io::Result<()>;
+}
+
+pub trait Decode: Sized {
+    fn decode(stream: &mut impl Read) -> std::io::Result<Self>;
+
+    /// Decodes a whole message, rejecting trailing bytes.
+    fn decode_exact(bytes: &[u8]) -> std::io::Result<Self> {
+        let mut stream = bytes;
+        let value = Self::decode(&mut stream)?;
+        if !stream.is_empty() {
+            let msg = format!("{} trailing bytes after message", stream.len());
+            return Err(invalid_data(msg));
+        }
+        Ok(value)
+    }
+}
+
+/// Types prefixed with a `u16` length, which can be limited using `#[cods(max_len = N)]`.
+pub trait LengthPrefixed: Encode + Decode {
+    fn len_prefix(&self) -> usize;
+
+    /// Decodes the value, without allocating more than `max_len` elements.
+    fn decode_bounded(stream: &mut impl Read, max_len: usize) -> std::io::Result<Self>;
+
+    fn encode_bounded(&self, stream: &mut impl Write, max_len: usize) -> std::io::Result<()> {
+        let len = self.len_prefix();
+        if len > max_len {
+            let msg = format!("length {len} exceeds the limit of {max_len}");
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+        }
+        self.encode(stream)
+    }
+}
+
+macro_rules! impl_le_bytes {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, stream: &mut impl Write) -> std::io::Result<()> {
+                    stream.write_all(&self.to_le_bytes())
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(stream: &mut impl Read) -> std::io::Result<Self> {
+                    let mut buf = [0; std::mem::size_of::<$ty>()];
+                    stream.read_exact(&mut buf)?;
+                    Ok(<$ty>::from_le_bytes(buf))
+                }
+            }
+        )*
+    };
+}
+
+impl_le_bytes!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl Encode for bool {
+    fn encode(&self, stream: &mut impl Write) -> std::io::Result<()> {
+        (*self as u8).encode(stream)
+    }
+}
+
+impl Decode for bool {
+    fn decode(stream: &mut impl Read) -> std::io::Result<Self> {
+        match u8::decode(stream)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(invalid_data(format!("invalid bool: {b}"))),
+        }
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, stream: &mut impl Write) -> std::io::Result<()> {
+        encode_len(self.len(), stream)?;
+        stream.write_all(self.as_bytes())
+    }
+}
+
+impl Decode for String {
+    fn decode(stream: &mut impl Read) -> std::io::Result<Self> {
+        Self::decode_bounded(stream, u16::MAX as usize)
+    }
+}
+
+impl LengthPrefixed for String {
+    fn len_prefix(&self) -> usize {
+        self.len()
+    }
+
+    fn decode_bounded(stream: &mut impl Read, max_len: usize) -> std::io::Result<Self> {
+        let len = decode_len(stream, max_len)?;
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf)?;
+        String::from_utf8(buf).map_err(invalid_data)
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, stream: &mut impl Write) -> std::io::Result<()> {
+        encode_len(self.len(), stream)?;
+        for e in self.iter() {
+            e.encode(stream)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(stream: &mut impl Read) -> std::io::Result<Self> {
+        decode_vec(stream, u16::MAX as usize)
+    }
+}
+
+impl<T: Encode + Decode> LengthPrefixed for Vec<T> {
+    fn len_prefix(&self) -> usize {
+        self.len()
+    }
+
+    fn decode_bounded(stream: &mut impl Read, max_len: usize) -> std::io::Result<Self> {
+        decode_vec(stream, max_len)
+    }
+}
+
+fn decode_vec<T: Decode>(stream: &mut impl Read, max_len: usize) -> std::io::Result<Vec<T>> {
+    let len = decode_len(stream, max_len)?;
+    // elements are pushed as they are decoded, so a bogus length can't cause a large
+    // allocation up front
+    let mut vec = Vec::new();
+    for _ in 0..len {
+        vec.push(T::decode(stream)?);
+    }
+    Ok(vec)
+}
+
+/// Arrays have a fixed length, so it isn't encoded.
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, stream: &mut impl Write) -> std::io::Result<()> {
+        for e in self.iter() {
+            e.encode(stream)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    fn decode(stream: &mut impl Read) -> std::io::Result<Self> {
+        let mut vec = Vec::with_capacity(N);
+        for _ in 0..N {
+            vec.push(T::decode(stream)?);
+        }
+        match vec.try_into() {
+            Ok(array) => Ok(array),
+            Err(_) => unreachable!("the vec has exactly N elements"),
+        }
+    }
+}
+
+/// Writes a `u16` length prefix, lengths that don't fit are an error instead of being truncated.
+fn encode_len(len: usize, stream: &mut impl Write) -> std::io::Result<()> {
+    let Ok(len) = u16::try_from(len) else {
+        let msg = format!("length {len} exceeds the limit of {}", u16::MAX);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+    };
+    len.encode(stream)
+}
+
+fn decode_len(stream: &mut impl Read, max_len: usize) -> std::io::Result<usize> {
+    let len = u16::decode(stream)? as usize;
+    if len > max_len {
+        let msg = format!("length {len} exceeds the limit of {max_len}");
+        return Err(invalid_data(msg));
+    }
+    Ok(len)
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::*;
+    use crate::{ClientEvent, Direction, Feedback, GameEvent, Player, MAX_NAME_LEN, MAX_PLAYERS};
+
+    fn encode(value: &impl Encode) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn rename(name: String) -> ClientEvent {
+        ClientEvent::Rename { player_id: 1, name }
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut bytes = encode(&ClientEvent::Pause);
+        bytes.extend([0, 0]);
+        let err = ClientEvent::decode_exact(&bytes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "2 trailing bytes after message");
+
+        // `decode` itself leaves them in the stream
+        let mut stream = &bytes[..];
+        ClientEvent::decode(&mut stream).unwrap();
+        assert_eq!(stream, [0, 0]);
+    }
+
+    /// Asserts that every prefix of the encoded `value` is rejected.
+    fn assert_truncated_fails<T: Encode + Decode>(value: &T) {
+        let bytes = encode(value);
+        for len in 0..bytes.len() {
+            let err = T::decode_exact(&bytes[..len]).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{len} of {bytes:?}");
+        }
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert_truncated_fails(&ClientEvent::AddPlayer { request_id: 3 });
+        assert_truncated_fails(&rename("name".into()));
+        assert_truncated_fails(&GameEvent::PlayerSync {
+            players: vec![Player::new(2, [1, 2, 3, 4], "name".into()); 2],
+        });
+        assert_truncated_fails(&GameEvent::PlayerAdded {
+            request_id: 1,
+            player: Player::new(2, [1, 2, 3, 4], "name".into()),
+        });
+        assert_truncated_fails(&u64::MAX);
+    }
+
+    #[test]
+    fn max_len_on_decode() {
+        let name = "a".repeat(MAX_NAME_LEN + 1);
+        let mut bytes = vec![4, 1, 0];
+        String::encode(&name, &mut bytes).unwrap();
+        let err = ClientEvent::decode_exact(&bytes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "length 33 exceeds the limit of 32");
+
+        // the length is checked before anything is read, so huge lengths are rejected early
+        let err = ClientEvent::decode_exact(&[4, 1, 0, 0xff, 0xff]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut bytes = vec![2];
+        encode_len(MAX_PLAYERS + 1, &mut bytes).unwrap();
+        let err = GameEvent::decode_exact(&bytes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn max_len_on_encode() {
+        let mut bytes = Vec::new();
+        let err = rename("a".repeat(MAX_NAME_LEN + 1))
+            .encode(&mut bytes)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(encode(&rename("a".repeat(MAX_NAME_LEN))).len() > MAX_NAME_LEN);
+
+        let long = "a".repeat(u16::MAX as usize + 1);
+        let err = long.encode(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn unknown_tags() {
+        for bytes in [&[0][..], &[13], &[0xff]] {
+            let err = ClientEvent::decode_exact(bytes).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+        let err = GameEvent::decode_exact(&[7]).unwrap_err();
+        assert_eq!(err.to_string(), "unknown GameEvent tag: 7");
+
+        let err = ClientEvent::decode_exact(&[2, 1, 0, 3]).unwrap_err();
+        assert_eq!(err.to_string(), "unknown Direction tag: 3");
+        let err = Feedback::decode_exact(&[3]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(Direction::decode_exact(&[2]).is_ok());
+    }
+
+    #[test]
+    fn invalid_values() {
+        let err = bool::decode_exact(&[2]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let err = ClientEvent::decode_exact(&[4, 1, 0, 2, 0, 0xc3, 0x28]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
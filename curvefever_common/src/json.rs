@@ -0,0 +1,807 @@
+use std::fmt::Write as _;
+
+use crate::LengthPrefixed;
+
+/// Limits the nesting of arrays and objects, so malicious input can't overflow the stack.
+const MAX_DEPTH: usize = 32;
+
+/// A parsed JSON document, numbers are kept as text so `u64` ids don't lose precision.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// Members in document order.
+    Object(Vec<(String, JsonValue)>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonError(String);
+
+impl JsonError {
+    pub fn new(msg: impl Into<String>) -> Self {
+        Self(msg.into())
+    }
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// JSON encoding used by text websocket messages, see the derive macro in `curvefever_derive`
+/// for the layout of structs and enums.
+pub trait ToJson {
+    fn to_json(&self) -> JsonValue;
+}
+
+pub trait FromJson: Sized {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError>;
+
+    fn from_json_str(input: &str) -> Result<Self, JsonError> {
+        Self::from_json(&JsonValue::parse(input)?)
+    }
+}
+
+impl JsonValue {
+    pub fn parse(input: &str) -> Result<Self, JsonError> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.pos < parser.input.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    /// The first member named `key` if this is an object.
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            JsonValue::Null => "null",
+            JsonValue::Bool(_) => "bool",
+            JsonValue::Number(_) => "number",
+            JsonValue::String(_) => "string",
+            JsonValue::Array(_) => "array",
+            JsonValue::Object(_) => "object",
+        }
+    }
+}
+
+impl std::fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonValue::Null => f.write_str("null"),
+            JsonValue::Bool(b) => write!(f, "{b}"),
+            JsonValue::Number(n) => f.write_str(n),
+            JsonValue::String(s) => write_string(f, s),
+            JsonValue::Array(values) => {
+                f.write_char('[')?;
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{v}")?;
+                }
+                f.write_char(']')
+            }
+            JsonValue::Object(members) => {
+                f.write_char('{')?;
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, k)?;
+                    write!(f, ":{v}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> JsonError {
+        JsonError(format!("{msg} at position {}", self.pos))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), JsonError> {
+        if !self.input[self.pos..].starts_with(literal.as_bytes()) {
+            return Err(self.error(&format!("expected `{literal}`")));
+        }
+        self.pos += literal.len();
+        Ok(())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<JsonValue, JsonError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|_| JsonValue::Null),
+            Some(b't') => self.expect("true").map(|_| JsonValue::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| JsonValue::Bool(false)),
+            Some(b'"') => self.string().map(JsonValue::String),
+            Some(b'-' | b'0'..=b'9') => self.number().map(JsonValue::Number),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(values));
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(JsonValue::Array(values));
+                        }
+                        _ => return Err(self.error("expected `,` or `]`")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error("expected member name"));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    let value = self.value(depth + 1)?;
+                    members.push((key, value));
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(JsonValue::Object(members));
+                        }
+                        _ => return Err(self.error("expected `,` or `}`")),
+                    }
+                }
+            }
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn digits(&mut self) -> Result<(), JsonError> {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error("expected digit"));
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<String, JsonError> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+            if let Some(b'0'..=b'9') = self.peek() {
+                return Err(self.error("leading zero"));
+            }
+        } else {
+            self.digits()?;
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.digits()?;
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            self.digits()?;
+        }
+        let number = std::str::from_utf8(&self.input[start..self.pos]).expect("only ascii");
+        Ok(number.to_string())
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, JsonError> {
+        let Some(hex) = self.input.get(self.pos..self.pos + 4) else {
+            return Err(self.error("unexpected end of input"));
+        };
+        // `from_str_radix` would also accept a sign
+        if !hex.iter().all(u8::is_ascii_hexdigit) {
+            return Err(self.error("invalid unicode escape"));
+        }
+        let hex = std::str::from_utf8(hex).expect("only ascii");
+        let code = u32::from_str_radix(hex, 16).expect("only hex digits");
+        self.pos += 4;
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        // skip the opening quote
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(b) = self.peek() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let Some(escape) = self.peek() else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex_escape()?;
+                            // utf-16 surrogate pair
+                            if (0xd800..0xdc00).contains(&code) {
+                                self.expect("\\u")?;
+                                let low = self.hex_escape()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or_else(|| self.error("invalid code point"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                0..=0x1f => return Err(self.error("control character in string")),
+                b => bytes.push(b),
+            }
+        }
+        // the input is a str, and escapes are pushed as utf-8
+        Ok(String::from_utf8(bytes).expect("valid utf-8"))
+    }
+}
+
+/// Reads the member `key` of an object, a missing member is treated like `null`, so it's only
+/// allowed for `Option`s.
+pub fn field<T: FromJson>(object: &JsonValue, key: &str) -> Result<T, JsonError> {
+    let Some(value) = object.get(key) else {
+        return T::from_json(&JsonValue::Null)
+            .map_err(|_| JsonError(format!("missing field `{key}`")));
+    };
+    T::from_json(value).map_err(|e| JsonError(format!("field `{key}`: {e}")))
+}
+
+/// Reads the member `key` of an object, limited to `max_len` like `#[cods(max_len = N)]` does
+/// for the binary encoding.
+pub fn field_bounded<T: FromJson + LengthPrefixed>(
+    object: &JsonValue,
+    key: &str,
+    max_len: usize,
+) -> Result<T, JsonError> {
+    let value: T = field(object, key)?;
+    let len = value.len_prefix();
+    if len > max_len {
+        return Err(JsonError(format!(
+            "field `{key}`: length {len} exceeds the limit of {max_len}"
+        )));
+    }
+    Ok(value)
+}
+
+/// The name of the variant an enum value, either the `type` member of an object or a plain
+/// string for variants without fields.
+pub fn variant_name(value: &JsonValue) -> Result<&str, JsonError> {
+    match value {
+        JsonValue::String(name) => Ok(name),
+        JsonValue::Object(_) => match value.get("type") {
+            Some(JsonValue::String(name)) => Ok(name),
+            Some(v) => Err(JsonError(format!(
+                "expected string type, found {}",
+                v.type_name()
+            ))),
+            None => Err(JsonError::new("missing field `type`")),
+        },
+        v => Err(JsonError(format!(
+            "expected object, found {}",
+            v.type_name()
+        ))),
+    }
+}
+
+fn unexpected(expected: &str, value: &JsonValue) -> JsonError {
+    JsonError(format!("expected {expected}, found {}", value.type_name()))
+}
+
+macro_rules! impl_number {
+    ($($ty:ty),*) => {
+        $(
+            impl ToJson for $ty {
+                fn to_json(&self) -> JsonValue {
+                    JsonValue::Number(self.to_string())
+                }
+            }
+
+            impl FromJson for $ty {
+                fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+                    let JsonValue::Number(n) = value else {
+                        return Err(unexpected("number", value));
+                    };
+                    n.parse().map_err(|_| {
+                        JsonError(format!("{n} is not a valid {}", stringify!($ty)))
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_number!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+macro_rules! impl_float {
+    ($($ty:ty),*) => {
+        $(
+            impl ToJson for $ty {
+                fn to_json(&self) -> JsonValue {
+                    if self.is_finite() {
+                        JsonValue::Number(self.to_string())
+                    } else {
+                        JsonValue::Null
+                    }
+                }
+            }
+
+            impl FromJson for $ty {
+                fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+                    let JsonValue::Number(n) = value else {
+                        return Err(unexpected("number", value));
+                    };
+                    n.parse().map_err(|_| {
+                        JsonError(format!("{n} is not a valid {}", stringify!($ty)))
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32, f64);
+
+impl ToJson for bool {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Bool(*self)
+    }
+}
+
+impl FromJson for bool {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        match value {
+            JsonValue::Bool(b) => Ok(*b),
+            v => Err(unexpected("bool", v)),
+        }
+    }
+}
+
+impl ToJson for JsonValue {
+    fn to_json(&self) -> JsonValue {
+        self.clone()
+    }
+}
+
+impl FromJson for JsonValue {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        Ok(value.clone())
+    }
+}
+
+impl ToJson for str {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::String(self.to_string())
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::String(self.clone())
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json(&self) -> JsonValue {
+        (**self).to_json()
+    }
+}
+
+impl FromJson for String {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        match value {
+            JsonValue::String(s) => Ok(s.clone()),
+            v => Err(unexpected("string", v)),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Array(self.iter().map(T::to_json).collect())
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        match value {
+            JsonValue::Array(values) => values.iter().map(T::from_json).collect(),
+            v => Err(unexpected("array", v)),
+        }
+    }
+}
+
+impl<T: ToJson, const N: usize> ToJson for [T; N] {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Array(self.iter().map(T::to_json).collect())
+    }
+}
+
+impl<T: FromJson, const N: usize> FromJson for [T; N] {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        let vec = Vec::<T>::from_json(value)?;
+        let len = vec.len();
+        vec.try_into()
+            .map_err(|_| JsonError(format!("expected {N} elements, found {len}")))
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> JsonValue {
+        match self {
+            Some(v) => v.to_json(),
+            None => JsonValue::Null,
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        match value {
+            JsonValue::Null => Ok(None),
+            v => T::from_json(v).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientEvent, Direction, GameEvent, Player, MAX_NAME_LEN};
+
+    fn parse(input: &str) -> JsonValue {
+        JsonValue::parse(input).unwrap_or_else(|e| panic!("{input}: {e}"))
+    }
+
+    fn string(input: &str) -> String {
+        match parse(input) {
+            JsonValue::String(s) => s,
+            v => panic!("{input} is a {}", v.type_name()),
+        }
+    }
+
+    fn number(input: &str) -> String {
+        match parse(input) {
+            JsonValue::Number(n) => n,
+            v => panic!("{input} is a {}", v.type_name()),
+        }
+    }
+
+    fn nested(depth: usize) -> String {
+        format!("{}{}", "[".repeat(depth), "]".repeat(depth))
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse(" null "), JsonValue::Null);
+        assert_eq!(parse("true"), JsonValue::Bool(true));
+        assert_eq!(parse("\tfalse\r\n"), JsonValue::Bool(false));
+        assert_eq!(parse("[ ]"), JsonValue::Array(Vec::new()));
+        assert_eq!(parse("{ }"), JsonValue::Object(Vec::new()));
+        assert_eq!(
+            parse(r#"{"a": [1, "b", null], "a": {}}"#),
+            JsonValue::Object(vec![
+                (
+                    "a".into(),
+                    JsonValue::Array(vec![
+                        JsonValue::Number("1".into()),
+                        JsonValue::String("b".into()),
+                        JsonValue::Null,
+                    ])
+                ),
+                ("a".into(), JsonValue::Object(Vec::new())),
+            ])
+        );
+        // the first of duplicate members wins
+        assert_eq!(
+            parse(r#"{"a": 1, "a": 2}"#).get("a"),
+            Some(&JsonValue::Number("1".into()))
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(string(r#""\"\\\/\b\f\n\r\t""#), "\"\\/\u{8}\u{c}\n\r\t");
+        assert_eq!(string(r#""\u0041\u00e4\u20AC""#), "Aä€");
+        assert_eq!(string(r#""\u0000""#), "\0");
+        assert_eq!(string("\"ünïcödé\""), "ünïcödé");
+
+        let err = JsonValue::parse(r#""\x""#).unwrap_err();
+        assert_eq!(err.to_string(), "invalid escape at position 3");
+        assert!(JsonValue::parse(r#""\u00""#).is_err());
+        assert!(JsonValue::parse(r#""\u00g0""#).is_err());
+        assert!(JsonValue::parse(r#""\u+041""#).is_err());
+        assert!(JsonValue::parse("\"a\nb\"").is_err());
+        assert!(JsonValue::parse("\"\\").is_err());
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        assert_eq!(string(r#""\ud83d\ude00""#), "😀");
+        assert_eq!(string(r#""\uD834\uDD1E""#), "𝄞");
+        // unescaped characters outside the basic plane are just utf-8
+        assert_eq!(string("\"😀\""), "😀");
+
+        // lone or reversed surrogates
+        assert!(JsonValue::parse(r#""\ud83d""#).is_err());
+        assert!(JsonValue::parse(r#""\ud83dx""#).is_err());
+        assert!(JsonValue::parse(r#""\ud83dA""#).is_err());
+        assert!(JsonValue::parse(r#""\ud83d\ud83d""#).is_err());
+        assert!(JsonValue::parse(r#""\ude00""#).is_err());
+        assert!(JsonValue::parse(r#""\ude00\ud83d""#).is_err());
+    }
+
+    #[test]
+    fn numbers() {
+        for n in [
+            "0",
+            "-0",
+            "1",
+            "-12",
+            "0.5",
+            "1e10",
+            "1E-3",
+            "-1.5e+7",
+            "18446744073709551615",
+        ] {
+            assert_eq!(number(n), n);
+        }
+        for n in [
+            "01",
+            "-01",
+            "00",
+            "-",
+            "+1",
+            ".5",
+            "1.",
+            "1e",
+            "1e+",
+            "0x10",
+            "NaN",
+            "-Infinity",
+        ] {
+            assert!(JsonValue::parse(n).is_err(), "{n}");
+        }
+
+        // numbers are kept as text, so integers don't lose precision
+        assert_eq!(u64::from_json_str("18446744073709551615"), Ok(u64::MAX));
+        assert!(u64::from_json_str("18446744073709551616").is_err());
+        assert!(u8::from_json_str("256").is_err());
+        assert!(u16::from_json_str("-1").is_err());
+        assert!(u16::from_json_str("1.0").is_err());
+        assert_eq!(i8::from_json_str("-128"), Ok(-128));
+        assert_eq!(f32::from_json_str("-2.5e-1"), Ok(-0.25));
+        assert_eq!(f32::NAN.to_json(), JsonValue::Null);
+        assert_eq!(f64::INFINITY.to_json(), JsonValue::Null);
+    }
+
+    #[test]
+    fn max_depth() {
+        assert!(JsonValue::parse(&nested(MAX_DEPTH + 1)).is_ok());
+        let err = JsonValue::parse(&nested(MAX_DEPTH + 2)).unwrap_err();
+        assert!(err.to_string().starts_with("nesting too deep"), "{err}");
+
+        let objects = format!(
+            "{}1{}",
+            r#"{"a":"#.repeat(MAX_DEPTH + 2),
+            "}".repeat(MAX_DEPTH + 2)
+        );
+        assert!(JsonValue::parse(&objects).is_err());
+        // doesn't overflow the stack
+        assert!(JsonValue::parse(&"[".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        let inputs = [
+            "",
+            "   ",
+            "nul",
+            "True",
+            "[1,]",
+            "[1 2]",
+            "[",
+            "{",
+            r#"{"a"}"#,
+            r#"{"a":}"#,
+            r#"{"a":1,}"#,
+            r#"{a:1}"#,
+            r#"{"a" 1}"#,
+            "\"abc",
+            "1 2",
+            "[] x",
+            "'a'",
+        ];
+        for input in inputs {
+            assert!(JsonValue::parse(input).is_err(), "{input:?}");
+        }
+        let err = JsonValue::parse("[1] x").unwrap_err();
+        assert_eq!(err.to_string(), "trailing characters at position 4");
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let inputs = [
+            r#"{"a":[1,-2.5e3,true,false,null],"b":{"c":"d"}}"#,
+            r#"["quote \" backslash \\ newline \n tab \t","\u0001","😀"]"#,
+        ];
+        for input in inputs {
+            let value = parse(input);
+            assert_eq!(value.to_string(), input);
+            assert_eq!(parse(&value.to_string()), value);
+        }
+        let control = JsonValue::String("\u{1}\u{7f}".into());
+        assert_eq!(control.to_string(), r#""\u0001\u007f""#);
+        assert_eq!(parse(&control.to_string()), control);
+    }
+
+    #[test]
+    fn derived_layout() {
+        let event = ClientEvent::Input {
+            player_id: 3,
+            dir: Direction::Left,
+        };
+        assert_eq!(
+            event.to_json().to_string(),
+            r#"{"type":"input","player_id":3,"dir":"left"}"#
+        );
+        // variants without fields are still objects, unless no variant of the enum has fields
+        assert_eq!(
+            ClientEvent::Restart.to_json().to_string(),
+            r#"{"type":"restart"}"#
+        );
+        assert_eq!(Direction::Left.to_json().to_string(), r#""left""#);
+        assert_eq!(
+            ClientEvent::from_json_str(r#""restart""#),
+            Ok(ClientEvent::Restart)
+        );
+        assert_eq!(
+            ClientEvent::from_json_str(r#"{"type":"restart"}"#),
+            Ok(ClientEvent::Restart)
+        );
+        assert_eq!(
+            ClientEvent::from_json_str(
+                r#" { "dir" : "left", "type" : "input", "player_id" : 3 } "#
+            ),
+            Ok(event)
+        );
+
+        let event = GameEvent::PlayerAdded {
+            request_id: u64::MAX,
+            player: Player::new(1, [1, 2, 3, 255], "a\"b".into()),
+        };
+        let json = event.to_json().to_string();
+        assert_eq!(
+            json,
+            r#"{"type":"player_added","request_id":18446744073709551615,"player":{"color":[1,2,3,255],"id":1,"name":"a\"b"}}"#
+        );
+        assert_eq!(GameEvent::from_json_str(&json), Ok(event));
+    }
+
+    #[test]
+    fn derived_errors() {
+        let cases = [
+            (r#"{"type":"jump"}"#, ""),
+            (r#"{"player_id":1}"#, "missing field `type`"),
+            (
+                r#"{"type":"input","dir":"left"}"#,
+                "missing field `player_id`",
+            ),
+            (
+                r#"{"type":"input","player_id":70000,"dir":"left"}"#,
+                "field `player_id`",
+            ),
+            (
+                r#"{"type":"input","player_id":1,"dir":"up"}"#,
+                "field `dir`",
+            ),
+            (r#"{"type":1}"#, "expected string type, found number"),
+            ("[]", "expected object, found array"),
+        ];
+        for (input, msg) in cases {
+            let err = ClientEvent::from_json_str(input).unwrap_err();
+            assert!(err.to_string().contains(msg), "{input}: {err}");
+        }
+
+        let name = "a".repeat(MAX_NAME_LEN + 1);
+        let input = format!(r#"{{"type":"rename","player_id":1,"name":"{name}"}}"#);
+        let err = ClientEvent::from_json_str(&input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "field `name`: length 33 exceeds the limit of 32"
+        );
+    }
+}
@@ -1,189 +1,86 @@
-use curvefever_derive::EnumTryFromRepr;
+use curvefever_derive::{Decode, Encode, EnumTryFromRepr, FromJson, ToJson};
 
-#[derive(Debug)]
+pub use codec::{Decode, Encode, LengthPrefixed};
+
+/// Allows the derived codecs to refer to `::curvefever_common` inside this crate.
+extern crate self as curvefever_common;
+
+mod codec;
+pub mod json;
+pub mod tilt;
+
+/// The maximum length of a player name in bytes.
+pub const MAX_NAME_LEN: usize = 32;
+/// The maximum number of players in a [`GameEvent::PlayerSync`].
+pub const MAX_PLAYERS: usize = 64;
+/// Round trip times above this many milliseconds are shown as a warning.
+pub const POOR_RTT_MS: u32 = 150;
+
+#[derive(Debug, PartialEq, Encode, Decode, ToJson, FromJson)]
 pub enum ClientEvent {
+    #[cods(tag = 1)]
     SyncPlayers,
+    #[cods(tag = 2)]
     Input { player_id: u16, dir: Direction },
+    #[cods(tag = 3)]
     AddPlayer { request_id: u64 },
-    Rename { player_id: u16, name: String },
+    #[cods(tag = 4)]
+    Rename {
+        player_id: u16,
+        #[cods(max_len = MAX_NAME_LEN)]
+        name: String,
+    },
+    #[cods(tag = 5)]
     PrevColor { player_id: u16 },
+    #[cods(tag = 6)]
     NextColor { player_id: u16 },
+    #[cods(tag = 7)]
     Restart,
+    #[cods(tag = 8)]
     Pause,
+    #[cods(tag = 9)]
     Share,
+    #[cods(tag = 10)]
     Help,
+    /// Steering with a variable turn rate, from `-1.0` for turning left at the full rate to `1.0`
+    /// for turning right at the full rate. Applies until the next input event.
+    #[cods(tag = 11)]
+    AnalogInput { player_id: u16, steering: f32 },
+    /// Answer to a [`GameEvent::Ping`], echoing its `timestamp`.
+    #[cods(tag = 12)]
+    Pong { timestamp: u64 },
 }
 
-impl ClientEvent {
-    pub const TYPE_SYNC_PLAYERS: u8 = 1;
-    pub const TYPE_INPUT: u8 = 2;
-    pub const TYPE_ADD_PLAYER: u8 = 3;
-    pub const TYPE_RENAME: u8 = 4;
-    pub const TYPE_PREV_COLOR: u8 = 5;
-    pub const TYPE_NEXT_COLOR: u8 = 6;
-    pub const TYPE_RESTART: u8 = 7;
-    pub const TYPE_PAUSE: u8 = 8;
-    pub const TYPE_SHARE: u8 = 9;
-    pub const TYPE_HELP: u8 = 10;
-
-    pub fn encode(&self, stream: &mut impl std::io::Write) -> anyhow::Result<()> {
-        match self {
-            ClientEvent::SyncPlayers => {
-                stream.write_all(&[Self::TYPE_SYNC_PLAYERS])?;
-            }
-            ClientEvent::Input { player_id, dir } => {
-                stream.write_all(&[Self::TYPE_INPUT])?;
-                stream.write_all(&u16::to_le_bytes(*player_id))?;
-                stream.write_all(&[*dir as u8])?;
-            }
-            ClientEvent::AddPlayer { request_id } => {
-                stream.write_all(&[Self::TYPE_ADD_PLAYER])?;
-                stream.write_all(&u64::to_le_bytes(*request_id))?;
-            }
-            ClientEvent::Rename { player_id, name } => {
-                stream.write_all(&[Self::TYPE_RENAME])?;
-                stream.write_all(&u16::to_le_bytes(*player_id))?;
-                write_string(stream, name)?;
-            }
-            ClientEvent::PrevColor { player_id } => {
-                stream.write_all(&[Self::TYPE_PREV_COLOR])?;
-                stream.write_all(&u16::to_le_bytes(*player_id))?;
-            }
-            ClientEvent::NextColor { player_id } => {
-                stream.write_all(&[Self::TYPE_NEXT_COLOR])?;
-                stream.write_all(&u16::to_le_bytes(*player_id))?;
-            }
-            ClientEvent::Restart => {
-                stream.write_all(&[Self::TYPE_RESTART])?;
-            }
-            ClientEvent::Pause => {
-                stream.write_all(&[Self::TYPE_PAUSE])?;
-            }
-            ClientEvent::Share => {
-                stream.write_all(&[Self::TYPE_SHARE])?;
-            }
-            ClientEvent::Help => {
-                stream.write_all(&[Self::TYPE_HELP])?;
-            }
-        }
-
-        Ok(())
-    }
-
-    pub fn decode(stream: &mut impl std::io::Read) -> anyhow::Result<Self> {
-        let ty = read_u8(stream)?;
-        let event = match ty {
-            Self::TYPE_SYNC_PLAYERS => ClientEvent::SyncPlayers,
-            Self::TYPE_INPUT => {
-                let player_id = read_u16(stream)?;
-                let dir = read_u8(stream)?;
-                let Ok(dir) = Direction::try_from(dir) else {
-                    anyhow::bail!("unknown direction {}", dir);
-                };
-
-                ClientEvent::Input { player_id, dir }
-            }
-            Self::TYPE_ADD_PLAYER => {
-                let request_id = read_u64(stream)?;
-                ClientEvent::AddPlayer { request_id }
-            }
-            Self::TYPE_RENAME => {
-                let player_id = read_u16(stream)?;
-                let name = read_string(stream)?;
-                ClientEvent::Rename { player_id, name }
-            }
-            Self::TYPE_PREV_COLOR => {
-                let player_id = read_u16(stream)?;
-                ClientEvent::PrevColor { player_id }
-            }
-            Self::TYPE_NEXT_COLOR => {
-                let player_id = read_u16(stream)?;
-                ClientEvent::NextColor { player_id }
-            }
-            Self::TYPE_RESTART => ClientEvent::Restart,
-            Self::TYPE_PAUSE => ClientEvent::Pause,
-            Self::TYPE_SHARE => ClientEvent::Share,
-            Self::TYPE_HELP => ClientEvent::Help,
-            _ => {
-                anyhow::bail!("Unknown ClientEvent type: {}", ty);
-            }
-        };
-
-        Ok(event)
-    }
-}
-
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Encode, Decode, ToJson, FromJson)]
 pub enum GameEvent {
+    #[cods(tag = 1)]
     Exit,
+    #[cods(tag = 2)]
     PlayerSync {
+        #[cods(max_len = MAX_PLAYERS)]
         players: Vec<Player>,
     },
     /// Response to a [`ClientEvent::AddPlayer`].
-    PlayerAdded {
-        request_id: u64,
-        player: Player,
-    },
+    #[cods(tag = 3)]
+    PlayerAdded { request_id: u64, player: Player },
+    /// Only sent to the remotes controlling `player_id`.
+    #[cods(tag = 4)]
+    PlayerFeedback { player_id: u16, feedback: Feedback },
+    /// Sent periodically to measure the round trip time, should be answered immediately with a
+    /// [`ClientEvent::Pong`].
+    #[cods(tag = 5)]
+    Ping { timestamp: u64 },
+    /// The rolling round trip time of this connection, sent after each measurement.
+    #[cods(tag = 6)]
+    Latency { rtt_ms: u32 },
 }
 
-impl GameEvent {
-    pub const TYPE_EXIT: u8 = 1;
-    pub const TYPE_PLAYER_LIST: u8 = 2;
-    pub const TYPE_PLAYER_ADDED: u8 = 3;
-
-    pub fn encode(&self, stream: &mut impl std::io::Write) -> std::io::Result<()> {
-        match self {
-            GameEvent::Exit => {
-                stream.write_all(&[Self::TYPE_EXIT])?;
-            }
-            GameEvent::PlayerSync { players } => {
-                stream.write_all(&[Self::TYPE_PLAYER_LIST])?;
-                stream.write_all(&u16::to_le_bytes(players.len() as u16))?;
-                for p in players.iter() {
-                    p.encode(stream)?;
-                }
-            }
-            GameEvent::PlayerAdded { request_id, player } => {
-                stream.write_all(&[Self::TYPE_PLAYER_ADDED])?;
-                stream.write_all(&u64::to_le_bytes(*request_id))?;
-                player.encode(stream)?;
-            }
-        }
-
-        Ok(())
-    }
-
-    pub fn decode(stream: &mut impl std::io::Read) -> anyhow::Result<Self> {
-        let ty = read_u8(stream)?;
-
-        let event = match ty {
-            Self::TYPE_EXIT => GameEvent::Exit,
-            Self::TYPE_PLAYER_LIST => {
-                let num_players = read_u16(stream)?;
-                let mut players = Vec::with_capacity(num_players as usize);
-                for _ in 0..num_players {
-                    players.push(Player::decode(stream)?);
-                }
-                GameEvent::PlayerSync { players }
-            }
-            Self::TYPE_PLAYER_ADDED => {
-                let request_id = read_u64(stream)?;
-                let player = Player::decode(stream)?;
-                GameEvent::PlayerAdded { request_id, player }
-            }
-            _ => {
-                anyhow::bail!("Unknown GameEvent type: {}", ty);
-            }
-        };
-
-        Ok(event)
-    }
-}
-
-#[derive(Clone, Debug)]
+/// The field order is part of the wire format.
+#[derive(Clone, Debug, PartialEq, Encode, Decode, ToJson, FromJson)]
 pub struct Player {
-    pub id: u16,
     pub color: [u8; 4],
+    pub id: u16,
+    #[cods(max_len = MAX_NAME_LEN)]
     pub name: String,
 }
 
@@ -191,24 +88,9 @@ impl Player {
     pub fn new(id: u16, color: [u8; 4], name: String) -> Self {
         Self { id, color, name }
     }
-
-    pub fn encode(&self, stream: &mut impl std::io::Write) -> std::io::Result<()> {
-        stream.write_all(&self.color)?;
-        stream.write_all(&u16::to_le_bytes(self.id))?;
-        write_string(stream, &self.name)?;
-        Ok(())
-    }
-
-    pub fn decode(stream: &mut impl std::io::Read) -> std::io::Result<Player> {
-        let mut color = [0; 4];
-        stream.read_exact(&mut color)?;
-        let id = read_u16(stream)?;
-        let name = read_string(stream)?;
-        Ok(Player { id, color, name })
-    }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumTryFromRepr)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumTryFromRepr, Encode, Decode, ToJson, FromJson)]
 #[cods(repr = u8)]
 pub enum Direction {
     Straight = 0,
@@ -226,35 +108,185 @@ impl Direction {
     }
 }
 
-fn read_u8(stream: &mut impl std::io::Read) -> std::io::Result<u8> {
-    let mut buf = [0];
-    stream.read_exact(&mut buf)?;
-    Ok(buf[0])
+/// Something that happened to a player, signaled on their phone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumTryFromRepr, Encode, Decode, ToJson, FromJson)]
+#[cods(repr = u8)]
+pub enum Feedback {
+    Crash = 0,
+    ItemPickup = 1,
+    RoundWon = 2,
 }
 
-fn read_u16(stream: &mut impl std::io::Read) -> std::io::Result<u16> {
-    let mut buf = [0; 2];
-    stream.read_exact(&mut buf)?;
-    Ok(u16::from_le_bytes(buf))
+/// Truncates `name` to at most [`MAX_NAME_LEN`] bytes, without splitting a character.
+pub fn truncate_name(name: &mut String) {
+    if name.len() <= MAX_NAME_LEN {
+        return;
+    }
+    let mut len = MAX_NAME_LEN;
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    name.truncate(len);
 }
 
-fn read_u64(stream: &mut impl std::io::Read) -> std::io::Result<u64> {
-    let mut buf = [0; 8];
-    stream.read_exact(&mut buf)?;
-    Ok(u64::from_le_bytes(buf))
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-fn write_string(stream: &mut impl std::io::Write, name: &str) -> std::io::Result<()> {
-    stream.write_all(&u16::to_le_bytes(name.len() as u16))?;
-    stream.write_all(name.as_bytes())?;
-    Ok(())
-}
+    fn encode(value: &impl Encode) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn player() -> Player {
+        Player::new(0x0304, [1, 2, 3, 4], "Jörg".into())
+    }
+
+    fn client_events() -> Vec<ClientEvent> {
+        vec![
+            ClientEvent::SyncPlayers,
+            ClientEvent::Input {
+                player_id: 1,
+                dir: Direction::Left,
+            },
+            ClientEvent::AddPlayer {
+                request_id: u64::MAX,
+            },
+            ClientEvent::Rename {
+                player_id: 2,
+                name: "a".repeat(MAX_NAME_LEN),
+            },
+            ClientEvent::PrevColor { player_id: 3 },
+            ClientEvent::NextColor { player_id: 4 },
+            ClientEvent::Restart,
+            ClientEvent::Pause,
+            ClientEvent::Share,
+            ClientEvent::Help,
+            ClientEvent::AnalogInput {
+                player_id: 5,
+                steering: -0.25,
+            },
+            ClientEvent::Pong { timestamp: 1234 },
+        ]
+    }
+
+    fn game_events() -> Vec<GameEvent> {
+        vec![
+            GameEvent::Exit,
+            GameEvent::PlayerSync {
+                players: Vec::new(),
+            },
+            GameEvent::PlayerSync {
+                players: vec![player(); MAX_PLAYERS],
+            },
+            GameEvent::PlayerAdded {
+                request_id: 7,
+                player: player(),
+            },
+            GameEvent::PlayerFeedback {
+                player_id: 8,
+                feedback: Feedback::ItemPickup,
+            },
+            GameEvent::Ping { timestamp: 99 },
+            GameEvent::Latency { rtt_ms: 150 },
+        ]
+    }
+
+    #[test]
+    fn client_events_round_trip() {
+        for e in client_events() {
+            let decoded = ClientEvent::decode_exact(&encode(&e)).unwrap();
+            assert_eq!(decoded, e);
+        }
+    }
 
-fn read_string(stream: &mut impl std::io::Read) -> std::io::Result<String> {
-    let name_len = read_u16(stream)?;
-    let mut name_buf = vec![0; name_len as usize];
-    stream.read_exact(&mut name_buf)?;
-    let name = String::from_utf8(name_buf)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-    Ok(name)
+    #[test]
+    fn game_events_round_trip() {
+        for e in game_events() {
+            let decoded = GameEvent::decode_exact(&encode(&e)).unwrap();
+            assert_eq!(decoded, e);
+        }
+    }
+
+    #[test]
+    fn feedback_round_trip() {
+        for f in [Feedback::Crash, Feedback::ItemPickup, Feedback::RoundWon] {
+            assert_eq!(Feedback::decode_exact(&encode(&f)).unwrap(), f);
+        }
+        for d in [Direction::Straight, Direction::Right, Direction::Left] {
+            assert_eq!(Direction::decode_exact(&encode(&d)).unwrap(), d);
+        }
+    }
+
+    /// The layouts of the messages that existed before the codec was derived.
+    #[test]
+    fn client_event_golden_bytes() {
+        let cases: &[(ClientEvent, &[u8])] = &[
+            (ClientEvent::SyncPlayers, &[1]),
+            (
+                ClientEvent::Input {
+                    player_id: 0x0102,
+                    dir: Direction::Right,
+                },
+                &[2, 0x02, 0x01, 1],
+            ),
+            (
+                ClientEvent::AddPlayer {
+                    request_id: 0x0102_0304_0506_0708,
+                },
+                &[3, 8, 7, 6, 5, 4, 3, 2, 1],
+            ),
+            (
+                ClientEvent::Rename {
+                    player_id: 7,
+                    name: "ab".into(),
+                },
+                &[4, 7, 0, 2, 0, b'a', b'b'],
+            ),
+            (ClientEvent::PrevColor { player_id: 0x100 }, &[5, 0, 1]),
+            (ClientEvent::NextColor { player_id: 3 }, &[6, 3, 0]),
+            (ClientEvent::Restart, &[7]),
+            (ClientEvent::Pause, &[8]),
+            (ClientEvent::Share, &[9]),
+            (ClientEvent::Help, &[10]),
+        ];
+        for (event, bytes) in cases {
+            assert_eq!(encode(event), *bytes, "{event:?}");
+            assert_eq!(ClientEvent::decode_exact(bytes).unwrap(), *event);
+        }
+    }
+
+    #[test]
+    fn game_event_golden_bytes() {
+        let player = Player::new(0x0304, [10, 20, 30, 255], "x".into());
+        let player_bytes = [10, 20, 30, 255, 0x04, 0x03, 1, 0, b'x'];
+
+        let cases: &[(GameEvent, Vec<u8>)] = &[
+            (GameEvent::Exit, vec![1]),
+            (
+                GameEvent::PlayerSync {
+                    players: vec![player.clone(), player.clone()],
+                },
+                [&[2, 2, 0][..], &player_bytes, &player_bytes].concat(),
+            ),
+            (
+                GameEvent::PlayerAdded {
+                    request_id: 5,
+                    player: player.clone(),
+                },
+                [&[3, 5, 0, 0, 0, 0, 0, 0, 0][..], &player_bytes].concat(),
+            ),
+        ];
+        for (event, bytes) in cases {
+            assert_eq!(encode(event), *bytes, "{event:?}");
+            assert_eq!(GameEvent::decode_exact(bytes).unwrap(), *event);
+        }
+    }
+
+    #[test]
+    fn player_color_comes_first() {
+        let bytes = encode(&Player::new(1, [0xaa, 0xbb, 0xcc, 0xdd], String::new()));
+        assert_eq!(bytes, [0xaa, 0xbb, 0xcc, 0xdd, 1, 0, 0, 0]);
+    }
 }
@@ -0,0 +1,129 @@
+use crate::Direction;
+
+/// The tilt in degrees at which analog steering reaches the full turn rate, this is also shown as
+/// the full deflection of the tilt indicator.
+pub const MAX_TILT: f32 = 60.0;
+
+/// How far the phone is tilted like a steering wheel, in degrees, positive when its right side
+/// is lowered. This is the angle between the horizontal and the left-to-right axis of the screen,
+/// so it doesn't depend on how far the phone is tilted towards the player. `beta` and `gamma` are
+/// the angles of a `deviceorientation` event, `screen_angle` is the angle of the screen
+/// orientation, since the left-to-right axis of the screen changes with it.
+pub fn tilt_angle(beta: f32, gamma: f32, screen_angle: f32) -> f32 {
+    let (beta, gamma, screen_angle) = (
+        beta.to_radians(),
+        gamma.to_radians(),
+        screen_angle.to_radians(),
+    );
+
+    // the direction pointing up, away from the ground, in device coordinates
+    let up_x = -beta.cos() * gamma.sin();
+    let up_y = beta.sin();
+
+    // the component along the left-to-right axis of the screen
+    let right = up_x * screen_angle.cos() - up_y * screen_angle.sin();
+    -right.clamp(-1.0, 1.0).asin().to_degrees()
+}
+
+/// The direction for a tilt in degrees, see [`tilt_angle`]. The player goes straight while the
+/// tilt is within the dead zone.
+pub fn tilt_direction(tilt: f32, dead_zone: f32) -> Direction {
+    if tilt <= -dead_zone {
+        Direction::Left
+    } else if tilt >= dead_zone {
+        Direction::Right
+    } else {
+        Direction::Straight
+    }
+}
+
+/// The tilt beyond the dead zone as analog steering, see [`ClientEvent::AnalogInput`]. The turn
+/// rate increases from zero at the dead zone to the full rate at [`MAX_TILT`].
+///
+/// [`ClientEvent::AnalogInput`]: curvefever_common::ClientEvent::AnalogInput
+pub fn tilt_steering(tilt: f32, dead_zone: f32) -> f32 {
+    let beyond_dead_zone = (tilt.abs() - dead_zone).max(0.0);
+    let steering = (beyond_dead_zone / (MAX_TILT - dead_zone)).min(1.0);
+    steering.copysign(tilt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 0.01;
+    const DEAD_ZONE: f32 = 10.0;
+
+    fn direction(beta: f32, gamma: f32, screen_angle: f32) -> Direction {
+        tilt_direction(tilt_angle(beta, gamma, screen_angle), DEAD_ZONE)
+    }
+
+    #[test]
+    fn flat_phone_is_straight() {
+        for screen_angle in [0.0, 90.0, 180.0, 270.0] {
+            assert!(tilt_angle(0.0, 0.0, screen_angle).abs() < EPSILON);
+            assert_eq!(direction(0.0, 0.0, screen_angle), Direction::Straight);
+        }
+    }
+
+    #[test]
+    fn portrait_uses_gamma() {
+        assert!((tilt_angle(0.0, 20.0, 0.0) - 20.0).abs() < EPSILON);
+        assert!((tilt_angle(0.0, -20.0, 0.0) + 20.0).abs() < EPSILON);
+        assert_eq!(direction(0.0, 20.0, 0.0), Direction::Right);
+        assert_eq!(direction(0.0, -20.0, 0.0), Direction::Left);
+
+        // turning the phone upside down swaps the sides
+        assert_eq!(direction(0.0, 20.0, 180.0), Direction::Left);
+        assert_eq!(direction(0.0, -20.0, 180.0), Direction::Right);
+
+        // tilting the phone towards the player doesn't steer
+        assert_eq!(direction(40.0, 0.0, 0.0), Direction::Straight);
+        assert_eq!(direction(-40.0, 0.0, 0.0), Direction::Straight);
+    }
+
+    #[test]
+    fn landscape_uses_beta() {
+        assert!((tilt_angle(20.0, 0.0, 90.0) - 20.0).abs() < EPSILON);
+        assert!((tilt_angle(20.0, 0.0, 270.0) + 20.0).abs() < EPSILON);
+        assert_eq!(direction(20.0, 0.0, 90.0), Direction::Right);
+        assert_eq!(direction(-20.0, 0.0, 90.0), Direction::Left);
+        assert_eq!(direction(20.0, 0.0, 270.0), Direction::Left);
+        assert_eq!(direction(-20.0, 0.0, 270.0), Direction::Right);
+
+        // tilting the phone towards the player doesn't steer
+        assert_eq!(direction(0.0, 40.0, 90.0), Direction::Straight);
+        assert_eq!(direction(0.0, 40.0, 270.0), Direction::Straight);
+    }
+
+    #[test]
+    fn dead_zone_edges() {
+        let dead_zone = 10.0;
+        assert_eq!(tilt_direction(9.9, dead_zone), Direction::Straight);
+        assert_eq!(tilt_direction(10.0, dead_zone), Direction::Right);
+        assert_eq!(tilt_direction(-9.9, dead_zone), Direction::Straight);
+        assert_eq!(tilt_direction(-10.0, dead_zone), Direction::Left);
+
+        assert_eq!(tilt_steering(0.0, dead_zone), 0.0);
+        assert_eq!(tilt_steering(10.0, dead_zone), 0.0);
+        assert_eq!(tilt_steering(-10.0, dead_zone), 0.0);
+        assert!((tilt_steering(35.0, dead_zone) - 0.5).abs() < EPSILON);
+        assert!((tilt_steering(-35.0, dead_zone) + 0.5).abs() < EPSILON);
+        assert_eq!(tilt_steering(MAX_TILT, dead_zone), 1.0);
+        assert_eq!(tilt_steering(90.0, dead_zone), 1.0);
+        assert_eq!(tilt_steering(-90.0, dead_zone), -1.0);
+    }
+
+    #[test]
+    fn steering_matches_direction() {
+        for tilt in [-70.0, -30.0, -10.0, -5.0, 5.0, 10.0, 30.0, 70.0] {
+            let steering = tilt_steering(tilt, DEAD_ZONE);
+            let expected = match tilt_direction(tilt, DEAD_ZONE) {
+                Direction::Left => steering <= 0.0,
+                Direction::Right => steering >= 0.0,
+                Direction::Straight => steering == 0.0,
+            };
+            assert!(expected, "tilt {tilt}, steering {steering}");
+        }
+    }
+}
@@ -1,6 +1,7 @@
-use proc_macro::{Ident, Literal, TokenStream, TokenTree};
+use proc_macro::{Delimiter, Group, Ident, Literal, TokenStream, TokenTree};
 use std::borrow::Cow;
 use std::fmt::Write as _;
+use std::iter::Peekable;
 
 struct Enum {
     repr: Option<Ident>,
@@ -214,7 +215,7 @@ fn parse_enum(input: TokenStream) -> Enum {
     }
 }
 
-fn transform_case(input: &str, case: Case) -> Cow<str> {
+fn transform_case(input: &str, case: Case) -> Cow<'_, str> {
     match case {
         Case::Camel => {
             let mut output = input.to_string();
@@ -407,3 +408,532 @@ pub fn derive_members_array(input: TokenStream) -> TokenStream {
 
     output.parse().unwrap()
 }
+
+struct Item {
+    name: String,
+    /// The type of the enum tag, defaults to `u8`.
+    repr: Option<String>,
+    data: Data,
+}
+
+enum Data {
+    Struct(Fields),
+    Enum(Vec<Variant>),
+}
+
+enum Fields {
+    Unit,
+    Named(Vec<Field>),
+    Unnamed(Vec<Field>),
+}
+
+struct Field {
+    /// The field name, or `f{i}` for tuple fields.
+    binding: String,
+    /// The maximum length of a length prefixed field, `#[cods(max_len = N)]`.
+    max_len: Option<String>,
+}
+
+struct Variant {
+    ident: String,
+    tag: Option<String>,
+    value: Option<Literal>,
+    fields: Fields,
+}
+
+/// Consumes all leading attributes and returns the `key = value` arguments of `cods` attributes.
+fn parse_cods_attributes(
+    tokens: &mut Peekable<impl Iterator<Item = TokenTree>>,
+) -> Vec<(String, TokenTree)> {
+    let mut args = Vec::new();
+    while let Some(TokenTree::Punct(p)) = tokens.peek() {
+        if p.as_char() != '#' {
+            break;
+        }
+        tokens.next();
+
+        let mut attributes = match tokens.next() {
+            Some(TokenTree::Group(g)) => g.stream().into_iter(),
+            _ => panic!("expected attribute list"),
+        };
+        match attributes.next() {
+            Some(TokenTree::Ident(i)) if i.to_string() == "cods" => (),
+            _ => continue,
+        }
+        let mut attribute_args = match attributes.next() {
+            Some(TokenTree::Group(g)) => g.stream().into_iter(),
+            _ => panic!("expected attribute args"),
+        };
+
+        loop {
+            let key = match attribute_args.next() {
+                Some(TokenTree::Ident(i)) => i.to_string(),
+                Some(t) => panic!("unexpected token: {t}"),
+                None => break,
+            };
+            expect_punct_like(&mut attribute_args, "=");
+            let Some(value) = attribute_args.next() else {
+                panic!("expected value for attribute `{key}`");
+            };
+            args.push((key, value));
+
+            match attribute_args.next() {
+                Some(TokenTree::Punct(p)) if p.as_char() == ',' => (),
+                Some(t) => panic!("unexpected token: {t}"),
+                None => break,
+            }
+        }
+    }
+    args
+}
+
+fn skip_visibility(tokens: &mut Peekable<impl Iterator<Item = TokenTree>>) {
+    if let Some(TokenTree::Ident(i)) = tokens.peek() {
+        if i.to_string() == "pub" {
+            tokens.next();
+            // pub(crate), pub(super), ...
+            if let Some(TokenTree::Group(g)) = tokens.peek() {
+                if g.delimiter() == Delimiter::Parenthesis {
+                    tokens.next();
+                }
+            }
+        }
+    }
+}
+
+/// Skips a type up to and including the next `,` that isn't nested inside generic arguments.
+fn skip_type(tokens: &mut Peekable<impl Iterator<Item = TokenTree>>) {
+    let mut depth = 0;
+    for t in tokens.by_ref() {
+        if let TokenTree::Punct(p) = &t {
+            match p.as_char() {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                ',' if depth == 0 => break,
+                _ => (),
+            }
+        }
+    }
+}
+
+fn parse_fields(group: Group) -> Fields {
+    let mut tokens = group.stream().into_iter().peekable();
+    let mut fields = Vec::new();
+    while tokens.peek().is_some() {
+        let mut max_len = None;
+        for (key, value) in parse_cods_attributes(&mut tokens) {
+            if key == "max_len" {
+                max_len = Some(value.to_string());
+            }
+        }
+        skip_visibility(&mut tokens);
+
+        let binding = match group.delimiter() {
+            Delimiter::Brace => {
+                let name = match tokens.next() {
+                    Some(TokenTree::Ident(i)) => i.to_string(),
+                    _ => panic!("expected field name"),
+                };
+                expect_punct_like(&mut tokens, ":");
+                name
+            }
+            Delimiter::Parenthesis => format!("f{}", fields.len()),
+            _ => panic!("expected fields"),
+        };
+        skip_type(&mut tokens);
+
+        fields.push(Field { binding, max_len });
+    }
+
+    match group.delimiter() {
+        Delimiter::Brace => Fields::Named(fields),
+        _ => Fields::Unnamed(fields),
+    }
+}
+
+fn parse_item(input: TokenStream) -> Item {
+    let mut tokens = input.into_iter().peekable();
+
+    let mut repr = None;
+    for (key, value) in parse_cods_attributes(&mut tokens) {
+        if key == "repr" {
+            repr = Some(value.to_string());
+        }
+    }
+
+    skip_visibility(&mut tokens);
+
+    let keyword = match tokens.next() {
+        Some(TokenTree::Ident(i)) => i.to_string(),
+        _ => panic!("expected struct or enum keyword"),
+    };
+
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(i)) => i.to_string(),
+        _ => panic!("expected identifier"),
+    };
+
+    let body = match tokens.next() {
+        Some(TokenTree::Group(g)) => Some(g),
+        Some(TokenTree::Punct(p)) if p.as_char() == ';' => None,
+        Some(TokenTree::Punct(p)) if p.as_char() == '<' => panic!("generics are not supported"),
+        _ => panic!("expected body"),
+    };
+
+    let data = match (keyword.as_str(), body) {
+        ("struct", None) => Data::Struct(Fields::Unit),
+        ("struct", Some(g)) => Data::Struct(parse_fields(g)),
+        ("enum", Some(g)) => {
+            let mut body = g.stream().into_iter().peekable();
+            let mut variants = Vec::new();
+            while body.peek().is_some() {
+                let mut tag = None;
+                for (key, value) in parse_cods_attributes(&mut body) {
+                    if key == "tag" {
+                        tag = Some(value.to_string());
+                    }
+                }
+
+                let ident = match body.next() {
+                    Some(TokenTree::Ident(i)) => i.to_string(),
+                    _ => panic!("expected enum variant name or attributes"),
+                };
+
+                let mut fields = Fields::Unit;
+                if let Some(TokenTree::Group(_)) = body.peek() {
+                    let Some(TokenTree::Group(g)) = body.next() else {
+                        unreachable!()
+                    };
+                    fields = parse_fields(g);
+                }
+
+                let mut value = None;
+                if let Some(TokenTree::Punct(p)) = body.peek() {
+                    if p.as_char() == '=' {
+                        body.next();
+                        match body.next() {
+                            Some(TokenTree::Literal(l)) => value = Some(l),
+                            _ => panic!("expected variant value after ="),
+                        }
+                    }
+                }
+
+                variants.push(Variant {
+                    ident,
+                    tag,
+                    value,
+                    fields,
+                });
+
+                match body.next() {
+                    Some(TokenTree::Punct(p)) if p.as_char() == ',' => (),
+                    None => (),
+                    _ => panic!("expected ,"),
+                }
+            }
+            Data::Enum(variants)
+        }
+        (k, _) => panic!("expected struct or enum found {k}"),
+    };
+
+    Item { name, repr, data }
+}
+
+impl Fields {
+    fn fields(&self) -> &[Field] {
+        match self {
+            Fields::Unit => &[],
+            Fields::Named(fields) | Fields::Unnamed(fields) => fields,
+        }
+    }
+
+    /// The pattern binding all fields to [`Field::binding`].
+    fn pattern(&self) -> String {
+        let bindings: Vec<_> = self.fields().iter().map(|f| f.binding.as_str()).collect();
+        match self {
+            Fields::Unit => String::new(),
+            Fields::Named(_) => format!("{{ {} }}", bindings.join(", ")),
+            Fields::Unnamed(_) => format!("({})", bindings.join(", ")),
+        }
+    }
+
+    /// Statements encoding each bound field in declaration order.
+    fn encode_statements(&self) -> String {
+        let mut output = String::new();
+        for f in self.fields() {
+            let binding = &f.binding;
+            let _ = match &f.max_len {
+                Some(max_len) => write!(
+                    output,
+                    "::curvefever_common::LengthPrefixed::encode_bounded({binding}, stream, {max_len})?;"
+                ),
+                None => write!(
+                    output,
+                    "::curvefever_common::Encode::encode({binding}, stream)?;"
+                ),
+            };
+        }
+        output
+    }
+
+    /// Constructor arguments decoding each field in declaration order.
+    fn decode_args(&self) -> String {
+        let decoded: Vec<_> = self
+            .fields()
+            .iter()
+            .map(|f| match &f.max_len {
+                Some(max_len) => format!(
+                    "::curvefever_common::LengthPrefixed::decode_bounded(stream, {max_len})?"
+                ),
+                None => "::curvefever_common::Decode::decode(stream)?".to_string(),
+            })
+            .collect();
+        match self {
+            Fields::Unit => String::new(),
+            Fields::Named(fields) => {
+                let args: Vec<_> = (fields.iter().zip(decoded))
+                    .map(|(f, d)| format!("{}: {d}", f.binding))
+                    .collect();
+                format!("{{ {} }}", args.join(", "))
+            }
+            Fields::Unnamed(_) => format!("({})", decoded.join(", ")),
+        }
+    }
+}
+
+impl Variant {
+    fn tag(&self) -> String {
+        match (&self.tag, &self.value) {
+            (Some(tag), _) => tag.clone(),
+            (None, Some(value)) => value.to_string(),
+            (None, None) => format!(
+                "compile_error!(\"no cods(tag = <tag>) defined for variant {}\")",
+                self.ident
+            ),
+        }
+    }
+}
+
+/// Encodes fields in declaration order, integers and floats are little endian, `String` and
+/// `Vec<T>` are prefixed with a `u16` length, which can be limited further using
+/// `#[cods(max_len = N)]`. Enums start with the tag of the variant, either
+/// `#[cods(tag = N)]` or the discriminant, encoded as `#[cods(repr = <repr>)]` or `u8`.
+#[proc_macro_derive(Encode, attributes(cods))]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let Item { name, repr, data } = parse_item(input);
+    let repr = repr.as_deref().unwrap_or("u8");
+
+    let mut output = format!(
+        "impl ::curvefever_common::Encode for {name} {{
+            fn encode(&self, stream: &mut impl std::io::Write) -> std::io::Result<()> {{"
+    );
+
+    match data {
+        Data::Struct(fields) => {
+            let pattern = fields.pattern();
+            let _ = write!(output, "let Self {pattern} = self;");
+            output.push_str(&fields.encode_statements());
+        }
+        Data::Enum(variants) => {
+            output.push_str("match self {");
+            for v in variants {
+                let ident = &v.ident;
+                let pattern = v.fields.pattern();
+                let tag = v.tag();
+                let _ = write!(
+                    output,
+                    "Self::{ident} {pattern} => {{
+                        ::curvefever_common::Encode::encode(&({tag} as {repr}), stream)?;"
+                );
+                output.push_str(&v.fields.encode_statements());
+                output.push('}');
+            }
+            output.push('}');
+        }
+    }
+
+    output.push_str("Ok(()) }}");
+    output.parse().unwrap()
+}
+
+/// The counterpart to [`derive_encode`], unknown enum tags and lengths exceeding `max_len` are an
+/// [`std::io::ErrorKind::InvalidData`] error.
+#[proc_macro_derive(Decode, attributes(cods))]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let Item { name, repr, data } = parse_item(input);
+    let repr = repr.as_deref().unwrap_or("u8");
+
+    let mut output = format!(
+        "impl ::curvefever_common::Decode for {name} {{
+            fn decode(stream: &mut impl std::io::Read) -> std::io::Result<Self> {{"
+    );
+
+    match data {
+        Data::Struct(fields) => {
+            let args = fields.decode_args();
+            let _ = write!(output, "Ok(Self {args})");
+        }
+        Data::Enum(variants) => {
+            let _ = write!(
+                output,
+                "let tag: {repr} = ::curvefever_common::Decode::decode(stream)?;
+                match tag {{"
+            );
+            for v in variants {
+                let ident = &v.ident;
+                let tag = v.tag();
+                let args = v.fields.decode_args();
+                let _ = write!(output, "{tag} => Ok(Self::{ident} {args}),");
+            }
+            let _ = write!(
+                output,
+                "_ => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(\"unknown {name} tag: {{tag}}\"),
+                )),
+            }}"
+            );
+        }
+    }
+
+    output.push_str("}}");
+    output.parse().unwrap()
+}
+
+impl Fields {
+    /// Object members for each bound field, tuple fields have no names so they aren't supported.
+    fn json_members(&self) -> Vec<String> {
+        match self {
+            Fields::Unit => Vec::new(),
+            Fields::Named(fields) => fields
+                .iter()
+                .map(|f| {
+                    let binding = &f.binding;
+                    format!(
+                        "(\"{binding}\".to_string(), ::curvefever_common::json::ToJson::to_json({binding}))"
+                    )
+                })
+                .collect(),
+            Fields::Unnamed(_) => vec!["compile_error!(\"tuple fields aren't supported\")".into()],
+        }
+    }
+
+    /// Constructor arguments reading each field from the object `value`.
+    fn json_args(&self) -> String {
+        match self {
+            Fields::Unit => String::new(),
+            Fields::Named(fields) => {
+                let args: Vec<_> = fields
+                    .iter()
+                    .map(|f| {
+                        let binding = &f.binding;
+                        match &f.max_len {
+                            Some(max_len) => format!(
+                                "{binding}: ::curvefever_common::json::field_bounded(value, \"{binding}\", {max_len})?"
+                            ),
+                            None => format!(
+                                "{binding}: ::curvefever_common::json::field(value, \"{binding}\")?"
+                            ),
+                        }
+                    })
+                    .collect();
+                format!("{{ {} }}", args.join(", "))
+            }
+            Fields::Unnamed(_) => "(compile_error!(\"tuple fields aren't supported\"))".into(),
+        }
+    }
+}
+
+/// Encodes structs as objects, enums are objects with the snake_case variant name as the `type`
+/// member. Enums without any fields are encoded as just the variant name string.
+#[proc_macro_derive(ToJson, attributes(cods))]
+pub fn derive_to_json(input: TokenStream) -> TokenStream {
+    let Item { name, data, .. } = parse_item(input);
+
+    let mut output = format!(
+        "impl ::curvefever_common::json::ToJson for {name} {{
+            fn to_json(&self) -> ::curvefever_common::json::JsonValue {{"
+    );
+
+    match data {
+        Data::Struct(fields) => {
+            let pattern = fields.pattern();
+            let members = fields.json_members().join(", ");
+            let _ = write!(
+                output,
+                "let Self {pattern} = self;
+                ::curvefever_common::json::JsonValue::Object(vec![{members}])"
+            );
+        }
+        Data::Enum(variants) => {
+            let unit_only = variants.iter().all(|v| matches!(v.fields, Fields::Unit));
+            output.push_str("match self {");
+            for v in variants {
+                let ident = &v.ident;
+                let pattern = v.fields.pattern();
+                let json_name = transform_case(ident, Case::Snake);
+                let json_name = format!(
+                    "::curvefever_common::json::JsonValue::String(\"{json_name}\".to_string())"
+                );
+                if unit_only {
+                    let _ = write!(output, "Self::{ident} => {json_name},");
+                } else {
+                    let mut members = vec![format!("(\"type\".to_string(), {json_name})")];
+                    members.extend(v.fields.json_members());
+                    let members = members.join(", ");
+                    let _ = write!(
+                        output,
+                        "Self::{ident} {pattern} => ::curvefever_common::json::JsonValue::Object(vec![{members}]),"
+                    );
+                }
+            }
+            output.push('}');
+        }
+    }
+
+    output.push_str("}}");
+    output.parse().unwrap()
+}
+
+/// The counterpart to [`derive_to_json`], variants without fields may also be written as just
+/// the variant name string. Unknown members are ignored.
+#[proc_macro_derive(FromJson, attributes(cods))]
+pub fn derive_from_json(input: TokenStream) -> TokenStream {
+    let Item { name, data, .. } = parse_item(input);
+
+    let mut output = format!(
+        "impl ::curvefever_common::json::FromJson for {name} {{
+            fn from_json(
+                value: &::curvefever_common::json::JsonValue,
+            ) -> Result<Self, ::curvefever_common::json::JsonError> {{"
+    );
+
+    match data {
+        Data::Struct(fields) => {
+            let args = fields.json_args();
+            let _ = write!(output, "Ok(Self {args})");
+        }
+        Data::Enum(variants) => {
+            output.push_str(
+                "let variant = ::curvefever_common::json::variant_name(value)?;
+                match variant {",
+            );
+            for v in variants {
+                let ident = &v.ident;
+                let json_name = transform_case(ident, Case::Snake);
+                let args = v.fields.json_args();
+                let _ = write!(output, "\"{json_name}\" => Ok(Self::{ident} {args}),");
+            }
+            let _ = write!(
+                output,
+                "_ => Err(::curvefever_common::json::JsonError::new(format!(
+                    \"unknown {name} type: {{variant}}\"
+                ))),
+            }}"
+            );
+        }
+    }
+
+    output.push_str("}}");
+    output.parse().unwrap()
+}
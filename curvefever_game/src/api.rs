@@ -0,0 +1,583 @@
+use std::time::Duration;
+
+use curvefever_common::{truncate_name, ClientEvent, MAX_NAME_LEN};
+use curvefever_derive::{FromJson, ToJson};
+use egui::Key;
+use tokio::sync::oneshot;
+
+use crate::particles::VisualEffects;
+use crate::world::{
+    CrashMessage, GameState, ItemKind, Palette, Player, SuddenDeathMode, TrailPattern, World,
+    WorldEvent, START_DELAY,
+};
+
+/// The longest sudden death delay or duration that can be set, in seconds.
+const MAX_SUDDEN_DEATH_SECS: f32 = 3600.0;
+
+/// Requests of the HTTP API, answered by the simulation in `bg_task`.
+#[derive(Debug)]
+pub enum ApiRequest {
+    State(oneshot::Sender<ApiState>),
+    Players(oneshot::Sender<Vec<ApiPlayer>>),
+    AddPlayer(AddPlayer, oneshot::Sender<Result<ApiPlayer, ApiError>>),
+    Restart(oneshot::Sender<Result<(), ApiError>>),
+    TogglePause(oneshot::Sender<Result<(), ApiError>>),
+    Settings(oneshot::Sender<ApiSettings>),
+    UpdateSettings(ApiSettings, oneshot::Sender<Result<ApiSettings, ApiError>>),
+    HostPlayers(oneshot::Sender<Vec<HostPlayer>>),
+    RemovePlayer(u16, oneshot::Sender<Result<(), ApiError>>),
+}
+
+/// Events passed on to the simulation by the web server, handled in `bg_task`.
+#[derive(Debug)]
+pub enum ServerEvent {
+    /// Sent by a remote, or by the host dashboard on behalf of a player.
+    Client(ClientEvent),
+    Host(HostEvent),
+    /// The rolling round trip time of a session controlling the player, `None` when it
+    /// disconnected.
+    Latency {
+        player_id: u16,
+        rtt: Option<Duration>,
+    },
+}
+
+/// Changes to the lobby only the host can make, mirroring the player menu.
+#[derive(Debug)]
+pub enum HostEvent {
+    PrevPattern {
+        player_id: u16,
+    },
+    NextPattern {
+        player_id: u16,
+    },
+    SetKeys {
+        player_id: u16,
+        left_key: Option<Key>,
+        right_key: Option<Key>,
+    },
+    /// Moves the player to `index` in the list of players.
+    MovePlayer {
+        player_id: u16,
+        index: usize,
+    },
+}
+
+/// The body of `POST /api/host`, sent by the host dashboard.
+#[derive(Debug, FromJson)]
+pub enum HostAction {
+    Rename {
+        player_id: u16,
+        #[cods(max_len = MAX_NAME_LEN)]
+        name: String,
+    },
+    PrevColor {
+        player_id: u16,
+    },
+    NextColor {
+        player_id: u16,
+    },
+    PrevPattern {
+        player_id: u16,
+    },
+    NextPattern {
+        player_id: u16,
+    },
+    /// Keys are named like [`Key::from_name`] expects, `null` unbinds the key.
+    SetKeys {
+        player_id: u16,
+        left_key: Option<String>,
+        right_key: Option<String>,
+    },
+    Remove {
+        player_id: u16,
+    },
+    /// Disconnects the remotes controlling the player and removes it.
+    Kick {
+        player_id: u16,
+    },
+    Move {
+        player_id: u16,
+        index: u16,
+    },
+}
+
+/// How a [`HostAction`] is applied.
+#[derive(Debug)]
+pub enum HostCommand {
+    /// Applied by the simulation with the next update, without an answer.
+    Event(ServerEvent),
+    /// Removing a player can be rejected, so it's sent as an [`ApiRequest::RemovePlayer`].
+    RemovePlayer { player_id: u16, kick: bool },
+}
+
+impl HostAction {
+    /// Changes that remotes can make are sent as the same [`ClientEvent`]s.
+    pub fn into_command(self) -> Result<HostCommand, ApiError> {
+        let key = |name: Option<String>| match name {
+            Some(n) => Key::from_name(&n)
+                .map(Some)
+                .ok_or_else(|| ApiError::Invalid(format!("unknown key `{n}`"))),
+            None => Ok(None),
+        };
+        let event = match self {
+            Self::Rename { player_id, name } => {
+                ServerEvent::Client(ClientEvent::Rename { player_id, name })
+            }
+            Self::PrevColor { player_id } => {
+                ServerEvent::Client(ClientEvent::PrevColor { player_id })
+            }
+            Self::NextColor { player_id } => {
+                ServerEvent::Client(ClientEvent::NextColor { player_id })
+            }
+            Self::PrevPattern { player_id } => {
+                ServerEvent::Host(HostEvent::PrevPattern { player_id })
+            }
+            Self::NextPattern { player_id } => {
+                ServerEvent::Host(HostEvent::NextPattern { player_id })
+            }
+            Self::SetKeys {
+                player_id,
+                left_key,
+                right_key,
+            } => ServerEvent::Host(HostEvent::SetKeys {
+                player_id,
+                left_key: key(left_key)?,
+                right_key: key(right_key)?,
+            }),
+            Self::Remove { player_id } => {
+                return Ok(HostCommand::RemovePlayer {
+                    player_id,
+                    kick: false,
+                })
+            }
+            Self::Kick { player_id } => {
+                return Ok(HostCommand::RemovePlayer {
+                    player_id,
+                    kick: true,
+                })
+            }
+            Self::Move { player_id, index } => ServerEvent::Host(HostEvent::MovePlayer {
+                player_id,
+                index: index as usize,
+            }),
+        };
+        Ok(HostCommand::Event(event))
+    }
+}
+
+/// An error answering a request, the HTTP status depends on the variant.
+#[derive(Debug)]
+pub enum ApiError {
+    /// `400 Bad Request`
+    Invalid(String),
+    /// `409 Conflict`, the request isn't possible in the current state of the game.
+    Conflict(&'static str),
+    /// `503 Service Unavailable`, the simulation isn't running anymore.
+    Unavailable,
+}
+
+#[derive(Debug, ToJson)]
+pub struct ApiState {
+    /// `starting`, `running`, `paused` or `stopped`.
+    pub state: &'static str,
+    /// The seconds since the round started, `0` while counting down.
+    pub round_time: f32,
+    /// The seconds until the round starts while counting down.
+    pub countdown: f32,
+    pub map: String,
+    pub mode: String,
+    pub players: Vec<ApiPlayer>,
+}
+
+#[derive(Clone, Debug, ToJson)]
+pub struct ApiPlayer {
+    pub id: u16,
+    pub name: String,
+    pub color: [u8; 4],
+    pub score: u16,
+    pub crashed: bool,
+}
+
+/// A player as shown on the host dashboard.
+#[derive(Debug, ToJson)]
+pub struct HostPlayer {
+    pub id: u16,
+    pub name: String,
+    pub color: [u8; 4],
+    pub pattern: TrailPattern,
+    pub left_key: Option<&'static str>,
+    pub right_key: Option<&'static str>,
+    /// The round trip time of the remote controlling this player in milliseconds.
+    pub rtt_ms: Option<u32>,
+    /// The ids of the websocket sessions that controlled this player, filled in by the server.
+    pub sessions: Vec<u64>,
+}
+
+/// Gameplay events sent to overlays by the `/events` stream.
+#[derive(Clone, Debug, ToJson)]
+pub enum StreamEvent {
+    RoundStarted {
+        map: String,
+        mode: String,
+        players: Vec<ApiPlayer>,
+    },
+    Crashed {
+        player_id: u16,
+        message: ApiCrashMessage,
+    },
+    ItemCollected {
+        player_id: u16,
+        item: ItemKind,
+    },
+    ScoreChanged {
+        player_id: u16,
+        score: u16,
+    },
+    /// `winner` is `None` if the last players crashed simultaneously.
+    RoundEnded {
+        winner: Option<u16>,
+        players: Vec<ApiPlayer>,
+    },
+}
+
+/// A [`CrashMessage`] with colors as RGBA arrays.
+#[derive(Clone, Debug, ToJson)]
+pub enum ApiCrashMessage {
+    Own {
+        name: String,
+        color: [u8; 4],
+    },
+    Wall {
+        name: String,
+        color: [u8; 4],
+    },
+    Other {
+        crashed_name: String,
+        crashed_color: [u8; 4],
+        other_name: String,
+        other_color: [u8; 4],
+    },
+}
+
+impl ApiCrashMessage {
+    fn new(message: &CrashMessage, palette: Palette) -> Self {
+        match message {
+            CrashMessage::Own { name, color } => Self::Own {
+                name: name.clone(),
+                color: color.color32(palette).to_array(),
+            },
+            CrashMessage::Wall { name, color } => Self::Wall {
+                name: name.clone(),
+                color: color.color32(palette).to_array(),
+            },
+            CrashMessage::Other {
+                crashed_name,
+                crashed_color,
+                other_name,
+                other_color,
+            } => Self::Other {
+                crashed_name: crashed_name.clone(),
+                crashed_color: crashed_color.color32(palette).to_array(),
+                other_name: other_name.clone(),
+                other_color: other_color.color32(palette).to_array(),
+            },
+        }
+    }
+}
+
+/// The body of `POST /api/players`.
+#[derive(Debug, Default, FromJson)]
+pub struct AddPlayer {
+    pub name: Option<String>,
+}
+
+/// All settings when read, only the present fields are changed when updating.
+#[derive(Debug, Default, ToJson, FromJson)]
+pub struct ApiSettings {
+    pub palette: Option<Palette>,
+    pub sudden_death: Option<SuddenDeathMode>,
+    /// The seconds after the start of a round at which sudden death begins.
+    pub sudden_death_delay: Option<f32>,
+    /// The seconds it takes sudden death to complete.
+    pub sudden_death_duration: Option<f32>,
+    pub visual_effects: Option<VisualEffects>,
+    /// The volume from `0.0` to `1.0`.
+    pub volume: Option<f32>,
+    pub muted: Option<bool>,
+    pub auto_screenshot: Option<bool>,
+}
+
+/// The changes made to the world by an [`ApiRequest`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Handled {
+    pub players_changed: bool,
+    /// A new round was started.
+    pub restarted: bool,
+}
+
+/// Answers the request, returns what changed.
+pub fn handle_request(world: &mut World, request: ApiRequest) -> Handled {
+    let mut handled = Handled::default();
+    // the receiver may have been dropped when the HTTP connection was closed, so replies are
+    // allowed to fail
+    match request {
+        ApiRequest::State(reply) => _ = reply.send(state(world)),
+        ApiRequest::Players(reply) => _ = reply.send(players(world)),
+        ApiRequest::AddPlayer(add, reply) => {
+            let res = add_player(world, add);
+            handled.players_changed = res.is_ok();
+            _ = reply.send(res);
+        }
+        ApiRequest::Restart(reply) => {
+            let res = match world.state {
+                GameState::Stopped(_) => {
+                    world.restart();
+                    Ok(())
+                }
+                _ => Err(ApiError::Conflict("a round is already in progress")),
+            };
+            handled.restarted = res.is_ok();
+            _ = reply.send(res);
+        }
+        ApiRequest::TogglePause(reply) => {
+            let res = match world.state {
+                GameState::Running(_) | GameState::Paused(_) => {
+                    world.toggle_pause();
+                    Ok(())
+                }
+                _ => Err(ApiError::Conflict("no round is running")),
+            };
+            _ = reply.send(res);
+        }
+        ApiRequest::Settings(reply) => _ = reply.send(settings(world)),
+        ApiRequest::UpdateSettings(update, reply) => {
+            let res = update_settings(world, update).map(|_| settings(world));
+            // the palette changes the colors of players
+            handled.players_changed = res.is_ok();
+            _ = reply.send(res);
+        }
+        ApiRequest::HostPlayers(reply) => _ = reply.send(host_players(world)),
+        ApiRequest::RemovePlayer(player_id, reply) => {
+            let res = remove_player(world, player_id);
+            handled.players_changed = res.is_ok();
+            _ = reply.send(res);
+        }
+    }
+    handled
+}
+
+/// The stream events for the events of the last update of the `world`.
+pub fn stream_events(world: &World, events: &[WorldEvent]) -> Vec<StreamEvent> {
+    events
+        .iter()
+        .filter_map(|e| match e {
+            WorldEvent::StateChanged {
+                from: GameState::Starting(_),
+                to: GameState::Running(_),
+            } => Some(StreamEvent::RoundStarted {
+                map: world.map.name.clone(),
+                mode: world.mode().name.clone(),
+                players: players(world),
+            }),
+            WorldEvent::StateChanged {
+                from: GameState::Running(_),
+                to: GameState::Stopped(_),
+            } => Some(StreamEvent::RoundEnded {
+                winner: world.round_winner(),
+                players: players(world),
+            }),
+            WorldEvent::Crashed {
+                player_id, message, ..
+            } => Some(StreamEvent::Crashed {
+                player_id: *player_id,
+                message: ApiCrashMessage::new(message, world.settings.palette),
+            }),
+            WorldEvent::ItemCollected {
+                player_id, kind, ..
+            } => Some(StreamEvent::ItemCollected {
+                player_id: *player_id,
+                item: *kind,
+            }),
+            WorldEvent::ScoreChanged { player_id, score } => Some(StreamEvent::ScoreChanged {
+                player_id: *player_id,
+                score: *score,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn state(world: &World) -> ApiState {
+    let now = world.clock.now;
+    let (state, round_time, countdown) = match world.state {
+        GameState::Starting(start) => {
+            let remaining = (start + START_DELAY).saturating_sub(now);
+            ("starting", Duration::ZERO, remaining)
+        }
+        GameState::Running(start) => ("running", now.saturating_sub(start), Duration::ZERO),
+        GameState::Paused(start) => ("paused", now.saturating_sub(start), Duration::ZERO),
+        GameState::Stopped(start) => ("stopped", now.saturating_sub(start), Duration::ZERO),
+    };
+
+    ApiState {
+        state,
+        round_time: round_time.as_secs_f32(),
+        countdown: countdown.as_secs_f32(),
+        map: world.map.name.clone(),
+        mode: world.mode().name.clone(),
+        players: players(world),
+    }
+}
+
+fn players(world: &World) -> Vec<ApiPlayer> {
+    let palette = world.settings.palette;
+    world.players.iter().map(|p| player(p, palette)).collect()
+}
+
+fn player(player: &Player, palette: Palette) -> ApiPlayer {
+    ApiPlayer {
+        id: player.id,
+        name: player.name.clone(),
+        color: player.color.color32(palette).to_array(),
+        score: player.score,
+        crashed: player.crashed,
+    }
+}
+
+fn host_players(world: &World) -> Vec<HostPlayer> {
+    let palette = world.settings.palette;
+    let players = world.players.iter().map(|p| HostPlayer {
+        id: p.id,
+        name: p.name.clone(),
+        color: p.color.color32(palette).to_array(),
+        pattern: p.pattern,
+        left_key: p.left_key.map(Key::name),
+        right_key: p.right_key.map(Key::name),
+        rtt_ms: p.remote_rtt.map(|rtt| rtt.as_millis() as u32),
+        sessions: Vec::new(),
+    });
+    players.collect()
+}
+
+fn add_player(world: &mut World, add: AddPlayer) -> Result<ApiPlayer, ApiError> {
+    if !matches!(world.state, GameState::Stopped(_)) {
+        return Err(ApiError::Conflict(
+            "players can only be added between rounds",
+        ));
+    }
+    if world.add_player().is_none() {
+        return Err(ApiError::Conflict(
+            "the maximum number of players is reached",
+        ));
+    }
+
+    let p = world.players.last_mut().expect("a player was just added");
+    if let Some(mut name) = add.name {
+        truncate_name(&mut name);
+        p.name = name;
+    }
+    Ok(player(p, world.settings.palette))
+}
+
+fn remove_player(world: &mut World, player_id: u16) -> Result<(), ApiError> {
+    if !matches!(world.state, GameState::Stopped(_)) {
+        return Err(ApiError::Conflict(
+            "players can only be removed between rounds",
+        ));
+    }
+    let Some(idx) = world.players.iter().position(|p| p.id == player_id) else {
+        return Err(ApiError::Invalid(format!("unknown player {player_id}")));
+    };
+    if world.players.len() <= 2 {
+        return Err(ApiError::Conflict("at least two players are required"));
+    }
+    world.remove_player(idx);
+    Ok(())
+}
+
+fn settings(world: &World) -> ApiSettings {
+    let settings = &world.settings;
+    ApiSettings {
+        palette: Some(settings.palette),
+        sudden_death: Some(settings.sudden_death.mode),
+        sudden_death_delay: Some(settings.sudden_death.delay.as_secs_f32()),
+        sudden_death_duration: Some(settings.sudden_death.duration.as_secs_f32()),
+        visual_effects: Some(settings.visual_effects),
+        volume: Some(settings.audio.volume),
+        muted: Some(settings.audio.muted),
+        auto_screenshot: Some(settings.export.auto_screenshot),
+    }
+}
+
+/// Validates all fields before changing anything, so invalid requests have no effect.
+fn update_settings(world: &mut World, update: ApiSettings) -> Result<(), ApiError> {
+    let secs = |name: &str, secs: Option<f32>, min: f32| match secs {
+        Some(s) if !(min..=MAX_SUDDEN_DEATH_SECS).contains(&s) => Err(ApiError::Invalid(format!(
+            "`{name}` must be between {min} and {MAX_SUDDEN_DEATH_SECS}"
+        ))),
+        Some(s) => Ok(Some(Duration::from_secs_f32(s))),
+        None => Ok(None),
+    };
+    let delay = secs("sudden_death_delay", update.sudden_death_delay, 0.0)?;
+    // sudden death progress is divided by the duration
+    let duration = secs("sudden_death_duration", update.sudden_death_duration, 1.0)?;
+    if let Some(v) = update.volume {
+        if !(0.0..=1.0).contains(&v) {
+            return Err(ApiError::Invalid("`volume` must be between 0 and 1".into()));
+        }
+    }
+
+    let settings = &mut world.settings;
+    if let Some(p) = update.palette {
+        settings.palette = p;
+    }
+    if let Some(m) = update.sudden_death {
+        settings.sudden_death.mode = m;
+    }
+    if let Some(d) = delay {
+        settings.sudden_death.delay = d;
+    }
+    if let Some(d) = duration {
+        settings.sudden_death.duration = d;
+    }
+    if let Some(v) = update.visual_effects {
+        settings.visual_effects = v;
+    }
+    if let Some(v) = update.volume {
+        settings.audio.volume = v;
+    }
+    if let Some(m) = update.muted {
+        settings.audio.muted = m;
+    }
+    if let Some(a) = update.auto_screenshot {
+        settings.export.auto_screenshot = a;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restart(world: &mut World) -> (Handled, Result<(), ApiError>) {
+        let (reply, mut receiver) = oneshot::channel();
+        let handled = handle_request(world, ApiRequest::Restart(reply));
+        (handled, receiver.try_recv().unwrap())
+    }
+
+    #[test]
+    fn rejected_restart_is_reported() {
+        let mut world = World::new();
+        world.add_player();
+
+        let (handled, res) = restart(&mut world);
+        assert!(res.is_ok());
+        assert!(handled.restarted);
+        assert!(matches!(world.state, GameState::Starting(_)));
+
+        let (handled, res) = restart(&mut world);
+        assert!(matches!(res, Err(ApiError::Conflict(_))));
+        assert_eq!(handled, Handled::default());
+    }
+}
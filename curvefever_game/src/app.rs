@@ -1,22 +1,35 @@
-use std::f32::consts::{PI, TAU};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI, TAU};
 use std::net::{IpAddr, Ipv4Addr};
-use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
+use arc_swap::ArcSwap;
 use async_channel::{Receiver, Sender};
-use curvefever_common::{ClientEvent, Direction, GameEvent};
+use curvefever_common::{
+    truncate_name, ClientEvent, Direction, Feedback, GameEvent, MAX_NAME_LEN, POOR_RTT_MS,
+};
 use eframe::CreationContext;
-use egui::epaint::{PathShape, RectShape};
+use egui::epaint::{PathShape, RectShape, Tessellator};
 use egui::layers::ShapeIdx;
 use egui::{
     Align2, CentralPanel, Color32, Context, Event, FontFamily, FontId, Frame, Id, InputState, Key,
-    Painter, Pos2, Rect, Rounding, Shape, Stroke, Vec2,
+    Mesh, Painter, Pos2, Rect, Rounding, Shape, Stroke, Vec2,
 };
 use qrcode::QrCode;
 
+use crate::api::{self, ApiRequest, HostEvent, ServerEvent, StreamEvent};
+use crate::audio::{self, Audio};
+use crate::editor::{EditorTool, MapEditor};
+use crate::export::{self, Recording};
+use crate::map::{Map, Wall};
+use crate::particles::{self, Particle, Particles};
+use crate::script::{GameMode, ScriptHost};
 use crate::world::{
-    CrashMessage, GameState, Item, Player, TrailSection, TurnDirection, World, BASE_THICKNESS,
-    ITEM_KINDS, ITEM_RADIUS, PLAYER_COLORS, START_DELAY, UPDATE_TIME, WORLD_SIZE,
+    CrashMessage, GameState, Item, ItemKind, Palette, Player, TrailPattern, TrailSection, World,
+    WorldEvent, BASE_THICKNESS, ITEM_KINDS, ITEM_RADIUS, MIN_PLAYER_DIST, PLAYER_COLORS,
+    START_DELAY, UPDATE_TIME, WORLD_SIZE,
 };
 
 macro_rules! key_pressed {
@@ -37,18 +50,57 @@ macro_rules! key_pressed {
     };
 }
 
-pub const PLAYER_MENU_FIELDS: usize = 3;
+pub const PLAYER_MENU_FIELDS: usize = 4;
 const KEY_PLACEHOLDER: &str = "<none>";
+const POOR_LATENCY_COLOR: Color32 = Color32::from_rgb(250, 110, 40);
+/// The maximum number of world events queued for the render thread.
+const MAX_PENDING_EVENTS: usize = 1024;
+pub const WORLD_BG_COLOR: Color32 = Color32::from_gray(24);
+pub const WALL_COLOR: Color32 = Color32::from_gray(150);
+/// The extra width and alpha of the layers drawn below trails when they glow.
+const TRAIL_GLOW_LAYERS: [(f32, u8); 2] = [(8.0, 20), (4.0, 40)];
+pub const PORTAL_COLORS: [Color32; 3] = [
+    Color32::from_rgb(250, 140, 30),
+    Color32::from_rgb(40, 160, 250),
+    Color32::from_rgb(200, 60, 250),
+];
 
 pub struct CurvefeverApp {
     bg_thread: Option<std::thread::JoinHandle<()>>,
-    game_sender: Sender<GameEvent>,
-    world: Arc<RwLock<World>>,
+    world_sender: Sender<WorldCommand>,
+    world: Arc<WorldSnapshot>,
     menu: Arc<RwLock<Menu>>,
     world_to_screen_offset: Vec2,
     world_to_screen_scale: f32,
     local_url: String,
+    /// The host dashboard, including the token it requires.
+    host_url: String,
     qrcode: QrCode,
+    /// Tessellated finished trail runs, by player id.
+    trail_meshes: RefCell<HashMap<u16, TrailMeshes>>,
+    particles: Particles,
+}
+
+/// The tessellated runs of a player's trail that are finished and won't change anymore.
+#[derive(Default)]
+struct TrailMeshes {
+    key: Option<TrailMeshKey>,
+    /// The index of the first trail section that isn't part of the `mesh`.
+    finished_sections: usize,
+    /// All finished runs in a single mesh, so painting them only copies one vertex buffer.
+    mesh: Mesh,
+}
+
+/// Everything that affects the look of a trail, the cache is rebuilt if any of these change.
+#[derive(Clone, Copy, PartialEq)]
+struct TrailMeshKey {
+    trail_generation: u32,
+    color: Color32,
+    pattern: TrailPattern,
+    glow: bool,
+    world_to_screen_offset: Vec2,
+    world_to_screen_scale: f32,
+    pixels_per_point: f32,
 }
 
 impl CurvefeverApp {
@@ -90,18 +142,121 @@ impl CurvefeverApp {
     }
 }
 
-#[derive(Clone, Copy, Default)]
+/// The latest state of the world, published by the simulation for rendering.
+struct WorldSnapshot {
+    world: ArcSwap<World>,
+    /// The events of all updates since the render thread last took them.
+    events: Mutex<Vec<WorldEvent>>,
+}
+
+impl WorldSnapshot {
+    fn new(world: &World) -> Self {
+        Self {
+            world: ArcSwap::from_pointee(world.clone()),
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn take_events(&self) -> Vec<WorldEvent> {
+        std::mem::take(&mut self.events.lock().unwrap())
+    }
+
+    /// Queues events for the render thread, dropping the oldest ones if it isn't taking them,
+    /// for example while the window is minimized.
+    fn push_events(&self, events: &[WorldEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        let mut pending = self.events.lock().unwrap();
+        pending.extend_from_slice(events);
+        if pending.len() > MAX_PENDING_EVENTS {
+            let excess = pending.len() - MAX_PENDING_EVENTS;
+            pending.drain(..excess);
+        }
+    }
+
+    fn load(&self) -> Arc<World> {
+        self.world.load_full()
+    }
+
+    /// Publishes the state after every update, so the render thread always draws the latest one.
+    /// This is cheap, since finished trail sections and maps are shared with the previous snapshot.
+    fn publish(&self, world: &World) {
+        self.world.store(Arc::new(world.clone()));
+    }
+}
+
+/// The channels connecting the simulation to the web server.
+pub struct ServerChannels {
+    pub server_events: Receiver<ServerEvent>,
+    pub api_requests: Receiver<ApiRequest>,
+    pub game_events: Sender<GameEvent>,
+    pub stream_events: Sender<StreamEvent>,
+}
+
+/// Changes to the world requested by the local UI, applied by the simulation in [`bg_task`].
+#[derive(Debug)]
+enum WorldCommand {
+    Quit,
+    LocalInput {
+        player_index: usize,
+        dir: Direction,
+    },
+    TogglePause,
+    Restart,
+    NextMap,
+    NextMode,
+    NextPalette,
+    NextSuddenDeathMode,
+    NextVisualEffects,
+    ToggleAutoScreenshot,
+    ToggleSvgExport,
+    NextGifWidth,
+    NextGifSpeed,
+    NextGifStyle,
+    SaveGif,
+    ToggleMute,
+    VolumeUp,
+    VolumeDown,
+    AddMap(Map),
+    PlayMap(Map),
+    AddPlayer,
+    RemovePlayer {
+        player_index: usize,
+    },
+    EditPlayer {
+        player_index: usize,
+        edit: PlayerEdit,
+    },
+}
+
+#[derive(Debug)]
+enum PlayerEdit {
+    PrevColor,
+    NextColor,
+    PushChar(char),
+    PopChar,
+    LeftKey(Option<Key>),
+    RightKey(Option<Key>),
+    PrevPattern,
+    NextPattern,
+}
+
+#[derive(Clone, Default)]
 struct Menu {
     state: MenuState,
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 enum MenuState {
     #[default]
     Home,
     Help,
     Share,
+    Export,
+    Audio,
     Player(PlayerMenu),
+    Editor(Box<MapEditor>),
 }
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
@@ -141,34 +296,141 @@ impl PlayerMenu {
 
 fn bg_task(
     ctx: Context,
-    world: Arc<RwLock<World>>,
+    mut world: World,
+    snapshot: Arc<WorldSnapshot>,
     menu: Arc<RwLock<Menu>>,
-    server_receiver: Receiver<ClientEvent>,
-    game_sender: Sender<GameEvent>,
+    world_receiver: Receiver<WorldCommand>,
+    server: ServerChannels,
 ) {
+    let ServerChannels {
+        server_events: server_receiver,
+        api_requests: api_receiver,
+        game_events: game_sender,
+        stream_events: stream_sender,
+    } = server;
+    let mut recording = Recording::default();
+    let mut audio = Audio::new(audio::default_backend());
+    let mut script_mode = None;
+    let mut script = None;
     let mut start = Instant::now();
-    loop {
-        let mut world = world.write().unwrap();
-        if !world.is_running {
-            break;
+    'game: loop {
+        let mut players_invalidated = false;
+        while let Ok(c) = world_receiver.try_recv() {
+            match c {
+                WorldCommand::Quit => break 'game,
+                WorldCommand::LocalInput { player_index, dir } => {
+                    if let Some(p) = world.players.get_mut(player_index) {
+                        p.local_direction = dir;
+                    }
+                }
+                WorldCommand::TogglePause => world.toggle_pause(),
+                WorldCommand::Restart => world.restart(),
+                WorldCommand::NextMap => world.next_map(),
+                WorldCommand::NextMode => world.next_mode(),
+                WorldCommand::NextPalette => {
+                    world.settings.palette.next();
+                    players_invalidated = true;
+                }
+                WorldCommand::NextSuddenDeathMode => world.settings.sudden_death.mode.next(),
+                WorldCommand::NextVisualEffects => world.settings.visual_effects.next(),
+                WorldCommand::ToggleAutoScreenshot => {
+                    let export = &mut world.settings.export;
+                    export.auto_screenshot = !export.auto_screenshot;
+                }
+                WorldCommand::ToggleSvgExport => {
+                    let export = &mut world.settings.export;
+                    export.svg = !export.svg;
+                }
+                WorldCommand::NextGifWidth => world.settings.export.next_gif_width(),
+                WorldCommand::NextGifSpeed => world.settings.export.next_gif_speed(),
+                WorldCommand::NextGifStyle => world.settings.export.gif_style.next(),
+                WorldCommand::SaveGif => {
+                    if !matches!(world.state, GameState::Stopped(_)) || recording.is_empty() {
+                        tracing::warn!("No finished round to save as a GIF");
+                        continue;
+                    }
+                    let settings = world.settings.export.clone();
+                    export::spawn_save_gif(recording.clone(), settings);
+                }
+                WorldCommand::ToggleMute => {
+                    let audio = &mut world.settings.audio;
+                    audio.muted = !audio.muted;
+                }
+                WorldCommand::VolumeUp => world.settings.audio.volume_up(),
+                WorldCommand::VolumeDown => world.settings.audio.volume_down(),
+                WorldCommand::AddMap(map) => world.add_map(map),
+                WorldCommand::PlayMap(map) => {
+                    world.map = Arc::new(map);
+                    world.restart();
+                }
+                WorldCommand::AddPlayer => {
+                    world.add_player();
+                    players_invalidated = true;
+                }
+                WorldCommand::RemovePlayer { player_index } => {
+                    world.remove_player(player_index);
+                    players_invalidated = true;
+                }
+                WorldCommand::EditPlayer { player_index, edit } => {
+                    let Some(p) = world.players.get_mut(player_index) else {
+                        continue;
+                    };
+                    match edit {
+                        PlayerEdit::PrevColor => p.color.prev(),
+                        PlayerEdit::NextColor => p.color.next(),
+                        PlayerEdit::PushChar(c) => {
+                            if p.name.len() + c.len_utf8() <= MAX_NAME_LEN {
+                                p.name.push(c);
+                            }
+                        }
+                        PlayerEdit::PopChar => _ = p.name.pop(),
+                        PlayerEdit::LeftKey(k) => p.left_key = k,
+                        PlayerEdit::RightKey(k) => p.right_key = k,
+                        PlayerEdit::PrevPattern => p.pattern.prev(),
+                        PlayerEdit::NextPattern => p.pattern.next(),
+                    }
+                    players_invalidated = true;
+                }
+            }
         }
 
-        let mut players_invalidated = false;
         while let Ok(e) = server_receiver.try_recv() {
+            let e = match e {
+                ServerEvent::Client(e) => e,
+                ServerEvent::Host(e) => {
+                    players_invalidated |= handle_host_event(&mut world, &menu, e);
+                    continue;
+                }
+                ServerEvent::Latency { player_id, rtt } => {
+                    if let Some(p) = find_player(&mut world.players, player_id) {
+                        p.remote_rtt = rtt;
+                    }
+                    continue;
+                }
+            };
             match e {
                 ClientEvent::SyncPlayers => {
                     players_invalidated = true;
                 }
                 ClientEvent::Input { player_id, dir } => {
                     if let Some(p) = find_player(&mut world.players, player_id) {
-                        p.remote_direction = dir;
+                        p.set_remote_direction(dir);
+                    }
+                }
+                ClientEvent::AnalogInput {
+                    player_id,
+                    steering,
+                } => {
+                    if let Some(p) = find_player(&mut world.players, player_id) {
+                        p.set_remote_steering(steering);
                     }
                 }
                 ClientEvent::AddPlayer { request_id } => {
                     if matches!(&world.state, GameState::Stopped(_)) {
                         let id = world.add_player();
                         if id.is_some() {
-                            let player = player_dto(world.players.last().unwrap());
+                            let palette = world.settings.palette;
+                            let player = player_dto(world.players.last().unwrap(), palette);
                             let event = GameEvent::PlayerAdded { request_id, player };
                             game_sender.send_blocking(event).unwrap();
                             players_invalidated = true;
@@ -178,6 +440,7 @@ fn bg_task(
                 ClientEvent::Rename { player_id, name } => {
                     if let Some(p) = find_player(&mut world.players, player_id) {
                         p.name = name;
+                        truncate_name(&mut p.name);
                         players_invalidated = true;
                     }
                 }
@@ -199,8 +462,12 @@ fn bg_task(
                 }
                 ClientEvent::Restart => {
                     let mut menu = menu.write().unwrap();
-                    menu.state = MenuState::Home;
-                    world.restart();
+                    // leaving the editor would throw away unsaved changes
+                    let editing = matches!(menu.state, MenuState::Editor(_));
+                    if !editing && matches!(world.state, GameState::Stopped(_)) {
+                        world.restart();
+                        menu.state = MenuState::Home;
+                    }
                 }
                 ClientEvent::Pause => {
                     world.toggle_pause();
@@ -225,15 +492,62 @@ fn bg_task(
                         }
                     }
                 }
+                // answered by the server, which reports the round trip time as `Latency`
+                ClientEvent::Pong { .. } => (),
             }
         }
 
+        while let Ok(r) = api_receiver.try_recv() {
+            let handled = api::handle_request(&mut world, r);
+            if handled.restarted {
+                let mut menu = menu.write().unwrap();
+                menu.state = MenuState::Home;
+            }
+            if handled.players_changed {
+                // players can be removed by the host dashboard
+                clamp_player_menu(&menu, world.players.len());
+            }
+            players_invalidated |= handled.players_changed;
+        }
+
         if players_invalidated {
-            sync_players(&game_sender, &world.players)
+            sync_players(&game_sender, &world.players, world.settings.palette)
         }
 
-        world.update();
-        drop(world);
+        if script_mode != Some(world.mode_index) {
+            script_mode = Some(world.mode_index);
+            script = load_script(world.mode());
+        }
+
+        let last_now = world.clock.now;
+        let events = world.update();
+        if let Some(host) = &mut script {
+            let dt = world.clock.now.saturating_sub(last_now);
+            host.run(&mut world, &events, dt);
+        }
+        recording.record(&world);
+        audio.handle_events(&events, &world.settings.audio);
+        snapshot.push_events(&events);
+        for e in api::stream_events(&world, &events) {
+            stream_sender.send_blocking(e).unwrap();
+        }
+        for e in player_feedback(&world, &events) {
+            game_sender.send_blocking(e).unwrap();
+        }
+        snapshot.publish(&world);
+
+        let round_ended = events.iter().any(|e| {
+            matches!(
+                e,
+                WorldEvent::StateChanged {
+                    from: GameState::Running(_),
+                    to: GameState::Stopped(_),
+                }
+            )
+        });
+        if round_ended && world.settings.export.auto_screenshot {
+            export::spawn_save_screenshot(Arc::new(world.clone()));
+        }
 
         ctx.request_repaint();
         let update_time = start.elapsed();
@@ -249,34 +563,43 @@ fn bg_task(
 }
 
 impl CurvefeverApp {
-    pub fn new(
-        cc: &CreationContext,
-        server_receiver: Receiver<ClientEvent>,
-        game_sender: Sender<GameEvent>,
-    ) -> Self {
-        let world = Arc::new(RwLock::new(World::new()));
+    pub fn new(cc: &CreationContext, server: ServerChannels, host_token: &str) -> Self {
+        let bg_world = World::new();
+        let world = Arc::new(WorldSnapshot::new(&bg_world));
         let menu = Arc::new(RwLock::new(Menu::default()));
+        let (world_sender, world_receiver) = async_channel::unbounded();
 
         let bg_ctx = cc.egui_ctx.clone();
-        let bg_world = Arc::clone(&world);
+        let bg_snapshot = Arc::clone(&world);
         let bg_menu = Arc::clone(&menu);
-        let bg_game_sender = game_sender.clone();
         let bg_thread = std::thread::spawn(move || {
-            bg_task(bg_ctx, bg_world, bg_menu, server_receiver, bg_game_sender);
+            bg_task(
+                bg_ctx,
+                bg_world,
+                bg_snapshot,
+                bg_menu,
+                world_receiver,
+                server,
+            );
         });
 
         let local_ip = local_ip_address::local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
         let local_url = format!("http://{local_ip}:8910");
         let qrcode = QrCode::new(&local_url).expect("code should always be valid");
+        let host_url = format!("{local_url}/host?token={host_token}");
+        tracing::info!("Host dashboard: {host_url}");
         Self {
             bg_thread: Some(bg_thread),
-            game_sender,
+            world_sender,
             world,
             menu,
             world_to_screen_offset: Vec2::ZERO,
             world_to_screen_scale: 1.0,
             local_url,
+            host_url,
             qrcode,
+            trail_meshes: RefCell::new(HashMap::new()),
+            particles: Particles::default(),
         }
     }
 }
@@ -306,20 +629,40 @@ impl eframe::App for CurvefeverApp {
                     painter,
                     Rect::from_min_size(Pos2::ZERO, WORLD_SIZE),
                     Rounding::ZERO,
-                    Color32::from_gray(24),
+                    WORLD_BG_COLOR,
                 );
 
-                let world = self.world.read().unwrap();
+                let world = self.world.load();
+                let menu = self.menu.read().unwrap();
+                if let MenuState::Editor(editor) = &menu.state {
+                    self.draw_editor(painter, editor);
+                    return;
+                }
+
+                self.draw_map(painter, &world.map);
+                let boundary = world.boundary();
+                let full_rect = Rect::from_min_size(Pos2::ZERO, WORLD_SIZE);
+                if boundary != full_rect {
+                    self.draw_boundary(painter, boundary);
+                }
+                self.draw_markers(painter, &world);
                 for i in world.items.iter() {
                     self.draw_item(painter, i);
                 }
+                self.trail_meshes
+                    .borrow_mut()
+                    .retain(|id, _| world.players.iter().any(|p| p.id == *id));
                 for p in world.players.iter() {
                     self.draw_player(painter, p, &world);
                 }
+                let events = self.world.take_events();
+                if world.settings.visual_effects.particles() {
+                    self.particles
+                        .handle_events(&events, world.clock.now, world.settings.palette);
+                }
+                self.draw_particles(painter, &world);
                 if world.wall_teleporting() {
-                    let rect = Rect::from_min_size(Pos2::ZERO, WORLD_SIZE);
-                    let stroke = Stroke::new(2.0, Color32::from_rgb(0, 200, 0));
-                    self.rect_stroke(painter, rect, Rounding::ZERO, stroke);
+                    self.draw_wall_teleporting_outline(painter, &world, boundary);
                 }
 
                 if matches!(world.state, GameState::Paused(_) | GameState::Stopped(_)) {
@@ -327,7 +670,6 @@ impl eframe::App for CurvefeverApp {
                     let rect = Rect::from_min_size(Pos2::ZERO, WORLD_SIZE);
                     self.rect_filled(painter, rect, Rounding::ZERO, Color32::from_black_alpha(80));
 
-                    let menu = self.menu.read().unwrap();
                     match &menu.state {
                         MenuState::Home => {
                             self.draw_normal_menu(painter, &world);
@@ -338,9 +680,16 @@ impl eframe::App for CurvefeverApp {
                         MenuState::Share => {
                             self.draw_join_menu(painter);
                         }
+                        MenuState::Export => {
+                            self.draw_export_menu(painter, &world);
+                        }
+                        MenuState::Audio => {
+                            self.draw_audio_menu(painter, &world);
+                        }
                         MenuState::Player(player_menu) => {
                             self.draw_player_menu(painter, player_menu, &world);
                         }
+                        MenuState::Editor(_) => (),
                     }
                 }
 
@@ -350,10 +699,7 @@ impl eframe::App for CurvefeverApp {
 
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
         // tell the bg thread to stop
-        {
-            let mut world = self.world.write().unwrap();
-            world.is_running = false;
-        }
+        self.send(WorldCommand::Quit);
 
         // wait for it to stop
         let bg_thread = self.bg_thread.take();
@@ -366,33 +712,94 @@ impl eframe::App for CurvefeverApp {
 }
 
 impl CurvefeverApp {
+    fn send(&self, command: WorldCommand) {
+        if let Err(e) = self.world_sender.try_send(command) {
+            tracing::error!("Error sending world command: {e}");
+        }
+    }
+
+    fn send_player_edit(&self, player_index: usize, edit: PlayerEdit) {
+        self.send(WorldCommand::EditPlayer { player_index, edit });
+    }
+
     fn handle_input(&self, input: &InputState) {
-        let mut world = self.world.write().unwrap();
+        let world = self.world.load();
         let mut menu = self.menu.write().unwrap();
         match &mut menu.state {
             MenuState::Home => {
-                for p in world.players.iter_mut() {
+                for (player_index, p) in world.players.iter().enumerate() {
                     let left_down = p.left_key.is_some_and(|k| input.key_down(k));
                     let right_down = p.right_key.is_some_and(|k| input.key_down(k));
-                    p.local_direction = Direction::from_left_right_down(left_down, right_down);
+                    let dir = Direction::from_left_right_down(left_down, right_down);
+                    if dir != p.local_direction {
+                        self.send(WorldCommand::LocalInput { player_index, dir });
+                    }
                 }
 
                 if input.key_pressed(Key::Escape) {
-                    world.toggle_pause();
+                    self.send(WorldCommand::TogglePause);
                 } else if input.key_pressed(Key::Space) {
-                    world.restart();
+                    self.send(WorldCommand::Restart);
                 }
 
                 if matches!(world.state, GameState::Stopped(_)) {
-                    if input.key_pressed(Key::H) {
+                    let hotkey = |key| hotkey_pressed(input, &world.players, key);
+                    if hotkey(Key::H) {
                         menu.state = MenuState::Help;
-                    } else if input.key_pressed(Key::S) {
+                    } else if hotkey(Key::S) {
                         menu.state = MenuState::Share;
-                    } else if input.key_pressed(Key::P) {
+                    } else if hotkey(Key::P) {
                         menu.state = MenuState::Player(PlayerMenu::default());
+                    } else if hotkey(Key::E) {
+                        let editor = MapEditor::new((*world.map).clone());
+                        menu.state = MenuState::Editor(Box::new(editor));
+                    } else if hotkey(Key::M) {
+                        self.send(WorldCommand::NextMap);
+                    } else if hotkey(Key::C) {
+                        self.send(WorldCommand::NextPalette);
+                    } else if hotkey(Key::B) {
+                        self.send(WorldCommand::NextSuddenDeathMode);
+                    } else if hotkey(Key::X) {
+                        menu.state = MenuState::Export;
+                    } else if hotkey(Key::U) {
+                        menu.state = MenuState::Audio;
+                    } else if hotkey(Key::V) {
+                        self.send(WorldCommand::NextVisualEffects);
+                    } else if hotkey(Key::G) {
+                        self.send(WorldCommand::NextMode);
                     }
                 }
             }
+            MenuState::Export => {
+                if key_pressed!(input, Key::Escape | Key::Q | Key::X) {
+                    menu.state = MenuState::Home;
+                } else if input.key_pressed(Key::S) {
+                    export::spawn_save_screenshot(Arc::clone(&world));
+                } else if input.key_pressed(Key::A) {
+                    self.send(WorldCommand::ToggleAutoScreenshot);
+                } else if input.key_pressed(Key::V) {
+                    self.send(WorldCommand::ToggleSvgExport);
+                } else if input.key_pressed(Key::G) {
+                    self.send(WorldCommand::SaveGif);
+                } else if input.key_pressed(Key::R) {
+                    self.send(WorldCommand::NextGifWidth);
+                } else if input.key_pressed(Key::F) {
+                    self.send(WorldCommand::NextGifSpeed);
+                } else if input.key_pressed(Key::T) {
+                    self.send(WorldCommand::NextGifStyle);
+                }
+            }
+            MenuState::Audio => {
+                if key_pressed!(input, Key::Escape | Key::Q | Key::U) {
+                    menu.state = MenuState::Home;
+                } else if input.key_pressed(Key::M) {
+                    self.send(WorldCommand::ToggleMute);
+                } else if key_pressed!(input, Key::Plus | Key::Equals | Key::ArrowUp) {
+                    self.send(WorldCommand::VolumeUp);
+                } else if key_pressed!(input, Key::Minus | Key::ArrowDown) {
+                    self.send(WorldCommand::VolumeDown);
+                }
+            }
             MenuState::Help => {
                 if key_pressed!(input, Key::Escape | Key::Q | Key::H) {
                     menu.state = MenuState::Home;
@@ -403,9 +810,89 @@ impl CurvefeverApp {
                     menu.state = MenuState::Home;
                 }
             }
-            MenuState::Player(player_menu) => {
-                let mut players_invalidated = false;
+            MenuState::Editor(editor) => {
+                if editor.renaming {
+                    for e in input.events.iter() {
+                        match e {
+                            Event::Text(text) => editor.map.name.push_str(text),
+                            Event::Key {
+                                key: Key::Backspace,
+                                pressed: true,
+                                ..
+                            } => {
+                                editor.map.name.pop();
+                            }
+                            Event::Key {
+                                key: Key::Enter | Key::Escape,
+                                pressed: true,
+                                ..
+                            } => {
+                                editor.renaming = false;
+                            }
+                            _ => (),
+                        }
+                    }
+                    return;
+                }
+
+                if key_pressed!(input, Key::Escape | Key::Q) {
+                    menu.state = MenuState::Home;
+                    return;
+                }
 
+                if input.key_pressed(Key::Num1) {
+                    editor.tool = EditorTool::Segment;
+                } else if input.key_pressed(Key::Num2) {
+                    editor.tool = EditorTool::Circle;
+                } else if input.key_pressed(Key::Num3) {
+                    editor.tool = EditorTool::Move;
+                } else if input.key_pressed(Key::R) {
+                    editor.renaming = true;
+                } else if input.key_pressed(Key::C) {
+                    editor.clear();
+                } else if input.key_pressed(Key::S) {
+                    // saved maps can be selected later for any number of players
+                    editor.message = match editor.validate(PLAYER_COLORS.len()) {
+                        Ok(()) => {
+                            let res = editor.map.file_path().and_then(|path| {
+                                editor.map.save(&path)?;
+                                Ok(path)
+                            });
+                            match res {
+                                Ok(path) => {
+                                    self.send(WorldCommand::AddMap(editor.map.clone()));
+                                    Some(format!("saved to {}", path.display()))
+                                }
+                                Err(e) => Some(format!("error saving map: {e}")),
+                            }
+                        }
+                        Err(e) => Some(e),
+                    };
+                } else if input.key_pressed(Key::T) {
+                    match editor.validate(world.players.len()) {
+                        Ok(()) => {
+                            self.send(WorldCommand::PlayMap(editor.map.clone()));
+                            menu.state = MenuState::Home;
+                            return;
+                        }
+                        Err(e) => editor.message = Some(e),
+                    }
+                }
+
+                if let Some(pos) = input.pointer.hover_pos() {
+                    let pos = self.stw_pos(pos);
+                    if input.pointer.primary_pressed() {
+                        editor.pointer_pressed(pos);
+                    } else if input.pointer.primary_released() {
+                        editor.pointer_released(pos);
+                    } else if input.pointer.primary_down() {
+                        editor.pointer_moved(pos);
+                    } else if input.pointer.secondary_pressed() {
+                        editor.delete_at(pos);
+                    }
+                }
+            }
+            MenuState::Player(player_menu) => {
                 if input.key_pressed(Key::Escape) {
                     if player_menu.selection_active {
                         player_menu.selection_active = false;
@@ -422,14 +909,15 @@ impl CurvefeverApp {
                     }
 
                     if input.key_pressed(Key::Equals) {
-                        world.add_player();
-                        players_invalidated = true;
+                        self.send(WorldCommand::AddPlayer);
                     } else if input.key_pressed(Key::Minus) {
-                        world.remove_player(player_menu.player_index);
-                        if player_menu.player_index >= world.players.len() {
+                        let player_index = player_menu.player_index;
+                        self.send(WorldCommand::RemovePlayer { player_index });
+                        // players are only removed if there are more than two
+                        let num_players = world.players.len();
+                        if num_players > 2 && player_index == num_players - 1 {
                             player_menu.player_index -= 1;
                         }
-                        players_invalidated = true;
                     }
 
                     if key_pressed!(input, Key::ArrowLeft | Key::H) {
@@ -455,28 +943,23 @@ impl CurvefeverApp {
                                     continue;
                                 };
 
+                                let idx = player_menu.player_index;
                                 match key {
                                     Key::ArrowLeft | Key::ArrowUp => {
-                                        let idx = player_menu.player_index;
-                                        world.players[idx].color.prev();
-                                        players_invalidated = true;
+                                        self.send_player_edit(idx, PlayerEdit::PrevColor);
                                     }
                                     Key::ArrowRight | Key::ArrowDown => {
-                                        let idx = player_menu.player_index;
-                                        world.players[idx].color.next();
-                                        players_invalidated = true;
+                                        self.send_player_edit(idx, PlayerEdit::NextColor);
                                     }
                                     Key::Enter => {
                                         player_menu.selection_active =
                                             !player_menu.selection_active;
                                     }
                                     Key::Backspace => {
-                                        world.players[player_menu.player_index].name.pop();
-                                        players_invalidated = true;
+                                        self.send_player_edit(idx, PlayerEdit::PopChar);
                                     }
                                     Key::Space => {
-                                        world.players[player_menu.player_index].name.push(' ');
-                                        players_invalidated = true;
+                                        self.send_player_edit(idx, PlayerEdit::PushChar(' '));
                                     }
                                     &k if (Key::A..=Key::Z).contains(&k) => {
                                         let char_offset = k as u32 - Key::A as u32;
@@ -485,15 +968,13 @@ impl CurvefeverApp {
                                             false => 'a' as u32 + char_offset,
                                         };
                                         let char = char::from_u32(char).unwrap();
-                                        world.players[player_menu.player_index].name.push(char);
-                                        players_invalidated = true;
+                                        self.send_player_edit(idx, PlayerEdit::PushChar(char));
                                     }
                                     &k if (Key::Num0..=Key::Num9).contains(&k) => {
                                         let char_offset = k as u32 - Key::Num0 as u32;
                                         let char = '0' as u32 + char_offset;
                                         let char = char::from_u32(char).unwrap();
-                                        world.players[player_menu.player_index].name.push(char);
-                                        players_invalidated = true;
+                                        self.send_player_edit(idx, PlayerEdit::PushChar(char));
                                     }
                                     _ => (),
                                 }
@@ -506,7 +987,8 @@ impl CurvefeverApp {
                                     Key::Backspace | Key::Delete => None,
                                     _ => Some(key),
                                 };
-                                world.players[player_menu.player_index].left_key = key;
+                                let idx = player_menu.player_index;
+                                self.send_player_edit(idx, PlayerEdit::LeftKey(key));
                             }
                         }
                         2 => {
@@ -516,30 +998,66 @@ impl CurvefeverApp {
                                     Key::Backspace | Key::Delete => None,
                                     _ => Some(key),
                                 };
-                                world.players[player_menu.player_index].right_key = key;
+                                let idx = player_menu.player_index;
+                                self.send_player_edit(idx, PlayerEdit::RightKey(key));
+                            }
+                        }
+                        3 => {
+                            let idx = player_menu.player_index;
+                            if key_pressed!(input, Key::ArrowLeft | Key::ArrowUp | Key::H | Key::K)
+                            {
+                                self.send_player_edit(idx, PlayerEdit::PrevPattern);
+                            } else if key_pressed!(
+                                input,
+                                Key::ArrowRight | Key::ArrowDown | Key::L | Key::J
+                            ) {
+                                self.send_player_edit(idx, PlayerEdit::NextPattern);
                             }
                         }
                         _ => (),
                     }
                 }
-
-                if players_invalidated {
-                    sync_players(&self.game_sender, &world.players);
-                }
             }
         }
     }
 
-    fn draw_player(&self, painter: &Painter, player: &Player, world: &RwLockReadGuard<World>) {
-        // draw trail
+    fn draw_player(&self, painter: &Painter, player: &Player, world: &World) {
+        let color = player.color.color32(world.settings.palette);
+
+        // draw finished trail runs
+        let key = TrailMeshKey {
+            trail_generation: player.trail_generation,
+            color,
+            pattern: player.pattern,
+            glow: world.settings.visual_effects.trail_glow(),
+            world_to_screen_offset: self.world_to_screen_offset,
+            world_to_screen_scale: self.world_to_screen_scale,
+            pixels_per_point: painter.ctx().pixels_per_point(),
+        };
+        let mut trail_meshes = self.trail_meshes.borrow_mut();
+        let cache = trail_meshes.entry(player.id).or_default();
+        if cache.key != Some(key) || cache.finished_sections > player.trail.len() {
+            *cache = TrailMeshes {
+                key: Some(key),
+                ..Default::default()
+            };
+        }
+
+        // draw trail, only tessellating runs that were finished since the last frame
+        let start = cache.finished_sections;
         let mut trail_points = Vec::new();
-        let mut last_pos = player.trail.first().map_or(Pos2::ZERO, |s| s.start_pos());
-        let mut thickness = player.trail.first().map_or(0.0, |s| s.thickness());
+        let mut last_pos = player
+            .trail
+            .get(start)
+            .map_or(Pos2::ZERO, |s| s.start_pos());
+        let mut thickness = player.trail.get(start).map_or(0.0, |s| s.thickness());
         let mut push_start = true;
-        for s in player.trail.iter() {
+        for (i, s) in (start..).zip(player.trail.iter_from(start)) {
             if s.gap() {
-                let color = player.color.color32();
-                self.draw_trail(painter, trail_points.clone(), thickness, color);
+                let shapes =
+                    self.trail_shapes(&trail_points, thickness, color, player.pattern, key.glow);
+                tessellate(painter, shapes, &mut cache.mesh);
+                cache.finished_sections = i + 1;
                 trail_points.clear();
 
                 push_start = true;
@@ -548,8 +1066,10 @@ impl CurvefeverApp {
             }
 
             if s.thickness() != thickness || s.start_pos() != last_pos {
-                let color = player.color.color32();
-                self.draw_trail(painter, trail_points.clone(), thickness, color);
+                let shapes =
+                    self.trail_shapes(&trail_points, thickness, color, player.pattern, key.glow);
+                tessellate(painter, shapes, &mut cache.mesh);
+                cache.finished_sections = i;
                 trail_points.clear();
 
                 push_start = true;
@@ -563,25 +1083,7 @@ impl CurvefeverApp {
                     trail_points.push(s.end);
                 }
                 TrailSection::Arc(s) => {
-                    let angle_delta = match s.dir {
-                        TurnDirection::Right => {
-                            let angle_delta = if s.player_end_angle < s.player_start_angle {
-                                s.player_end_angle.rem_euclid(TAU) - s.player_start_angle
-                            } else {
-                                s.player_end_angle - s.player_start_angle
-                            };
-                            angle_delta
-                        }
-                        TurnDirection::Left => {
-                            let angle_delta = if s.player_start_angle < s.player_end_angle {
-                                s.player_start_angle.rem_euclid(TAU) - s.player_end_angle
-                            } else {
-                                s.player_start_angle - s.player_end_angle
-                            };
-                            -angle_delta
-                        }
-                    };
-
+                    let angle_delta = s.angle_delta();
                     let num_points = (angle_delta / (0.01 * TAU)).abs().round().max(1.0);
                     let angle_step = angle_delta / num_points;
 
@@ -602,16 +1104,33 @@ impl CurvefeverApp {
             thickness = s.thickness();
             last_pos = s.end_pos();
         }
+        if !cache.mesh.is_empty() {
+            painter.add(Shape::mesh(cache.mesh.clone()));
+        }
+        drop(trail_meshes);
+
+        let pos = player.interpolated_pos(world.clock.alpha());
+        if !player.crashed && last_pos == player.pos {
+            if let Some(last) = trail_points.last_mut() {
+                *last = pos;
+            }
+        }
         if trail_points.len() > 1 {
-            let color = player.color.color32();
-            self.draw_trail(painter, trail_points, thickness, color);
+            self.draw_trail(
+                painter,
+                &trail_points,
+                thickness,
+                color,
+                player.pattern,
+                key.glow,
+            );
         }
 
         // draw player dot
         if !player.crashed && (player.gap() || player.trail.is_empty()) {
             let a = if player.gap() { 80 } else { 255 };
-            let color = player.color.color32().with_alpha(a);
-            self.circle_filled(painter, player.pos, 0.5 * player.thickness(), color);
+            let color = color.with_alpha(a);
+            self.circle_filled(painter, pos, 0.5 * player.thickness(), color);
         }
 
         // draw arrow
@@ -655,27 +1174,300 @@ impl CurvefeverApp {
     fn draw_trail(
         &self,
         painter: &Painter,
-        trail_points: Vec<Pos2>,
+        trail_points: &[Pos2],
         thickness: f32,
         color: Color32,
+        pattern: TrailPattern,
+        glow: bool,
     ) {
-        let [first, .., last] = &trail_points[..] else {
-            return;
+        painter.extend(self.trail_shapes(trail_points, thickness, color, pattern, glow));
+    }
+
+    /// The screen space shapes of a continuous trail run.
+    fn trail_shapes(
+        &self,
+        trail_points: &[Pos2],
+        thickness: f32,
+        color: Color32,
+        pattern: TrailPattern,
+        glow: bool,
+    ) -> Vec<Shape> {
+        if trail_points.len() < 2 {
+            return Vec::new();
+        }
+
+        let scale = self.world_to_screen_scale;
+        let points: Vec<Pos2> = trail_points.iter().map(|p| self.wts_pos(*p)).collect();
+        let first = points[0];
+        let last = points[points.len() - 1];
+        let cap_radius = scale * (0.5 * thickness - 0.5);
+        let stroke = Stroke::new(scale * thickness, color);
+
+        let mut shapes = Vec::new();
+        if glow {
+            for (width, alpha) in TRAIL_GLOW_LAYERS {
+                let glow_stroke = Stroke::new(scale * (thickness + width), color.with_alpha(alpha));
+                shapes.push(Shape::line(points.clone(), glow_stroke));
+            }
+        }
+
+        let pattern_shapes = match pattern {
+            TrailPattern::Solid => vec![
+                Shape::circle_filled(first, cap_radius, color),
+                Shape::circle_filled(last, cap_radius, color),
+                Shape::line(points, stroke),
+            ],
+            TrailPattern::Dashed => {
+                let dash_length = 3.0 * thickness.max(BASE_THICKNESS);
+                let gap_length = 0.5 * dash_length;
+                Shape::dashed_line(&points, stroke, scale * dash_length, scale * gap_length)
+            }
+            TrailPattern::Dotted => {
+                let spacing = 1.5 * thickness.max(BASE_THICKNESS);
+                let radius = 0.5 * thickness;
+                Shape::dotted_line(&points, color, scale * spacing, scale * radius)
+            }
+            TrailPattern::Outlined => {
+                let inner_stroke = Stroke::new(scale * 0.4 * thickness, WORLD_BG_COLOR);
+                vec![
+                    Shape::circle_filled(first, cap_radius, color),
+                    Shape::circle_filled(last, cap_radius, color),
+                    Shape::line(points.clone(), stroke),
+                    Shape::line(points, inner_stroke),
+                ]
+            }
         };
+        shapes.extend(pattern_shapes);
+        shapes
+    }
 
-        self.circle_filled(painter, *first, 0.5 * thickness - 0.5, color);
-        self.circle_filled(painter, *last, 0.5 * thickness - 0.5, color);
+    fn draw_markers(&self, painter: &Painter, world: &World) {
+        for m in world.markers.iter() {
+            self.circle_filled(painter, m.pos, m.radius, m.color.with_alpha(30));
+            self.circle_stroke(painter, m.pos, m.radius, Stroke::new(2.0_f32, m.color));
+        }
+    }
 
-        let stroke = Stroke::new(thickness, color);
-        let path = PathShape::line(trail_points, stroke);
-        self.add_path(painter, path);
+    fn draw_particles(&self, painter: &Painter, world: &World) {
+        for p in self.particles.particles(world.clock.now) {
+            match p {
+                Particle::Dot { pos, radius, color } => {
+                    self.circle_filled(painter, pos, radius, color);
+                }
+                Particle::Ring {
+                    pos,
+                    radius,
+                    width,
+                    color,
+                } => {
+                    self.circle_stroke(painter, pos, radius, Stroke::new(width, color));
+                }
+            }
+        }
+    }
+
+    /// Outlines the boundary while players can pass through it, pulsing if visual effects are
+    /// enabled.
+    fn draw_wall_teleporting_outline(&self, painter: &Painter, world: &World, boundary: Rect) {
+        let color = Color32::from_rgb(0, 200, 0);
+        let stroke = if world.settings.visual_effects.particles() {
+            let t = world.clock.now.as_secs_f32();
+            let pulse = 0.5 + 0.5 * (TAU * particles::PULSE_FREQUENCY * t).sin();
+            Stroke::new(2.0 + 3.0 * pulse, color.gamma_multiply(0.4 + 0.6 * pulse))
+        } else {
+            Stroke::new(2.0_f32, color)
+        };
+        self.rect_stroke(painter, boundary, Rounding::ZERO, stroke);
+    }
+
+    /// Shades the area outside of the shrinking sudden death boundary.
+    fn draw_boundary(&self, painter: &Painter, boundary: Rect) {
+        let outside_color = Color32::from_rgba_unmultiplied(120, 0, 0, 60);
+        let outside_rects = [
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(WORLD_SIZE.x, boundary.min.y)),
+            Rect::from_min_max(Pos2::new(0.0, boundary.max.y), WORLD_SIZE.to_pos2()),
+            Rect::from_min_max(
+                Pos2::new(0.0, boundary.min.y),
+                Pos2::new(boundary.min.x, boundary.max.y),
+            ),
+            Rect::from_min_max(
+                Pos2::new(boundary.max.x, boundary.min.y),
+                Pos2::new(WORLD_SIZE.x, boundary.max.y),
+            ),
+        ];
+        for rect in outside_rects {
+            self.rect_filled(painter, rect, Rounding::ZERO, outside_color);
+        }
+
+        let stroke = Stroke::new(2.0_f32, Color32::from_rgb(220, 40, 40));
+        self.rect_stroke(painter, boundary, Rounding::ZERO, stroke);
+    }
+
+    fn draw_map(&self, painter: &Painter, map: &Map) {
+        for w in map.walls.iter() {
+            match w {
+                Wall::Segment {
+                    start,
+                    end,
+                    thickness,
+                } => {
+                    self.draw_trail(
+                        painter,
+                        &[*start, *end],
+                        *thickness,
+                        WALL_COLOR,
+                        TrailPattern::Solid,
+                        false,
+                    );
+                }
+                Wall::Arc {
+                    center,
+                    radius,
+                    start_angle,
+                    end_angle,
+                    thickness,
+                } => {
+                    let angle_delta = (end_angle - start_angle).rem_euclid(TAU);
+                    let steps = (angle_delta / (0.01 * TAU)).ceil().max(1.0) as u8;
+                    let end_angle = start_angle + angle_delta;
+                    let points = arc_points(*center, *radius, *start_angle, end_angle, steps);
+                    self.draw_trail(
+                        painter,
+                        &points,
+                        *thickness,
+                        WALL_COLOR,
+                        TrailPattern::Solid,
+                        false,
+                    );
+                }
+                Wall::Circle { center, radius } => {
+                    self.circle_filled(painter, *center, *radius, WALL_COLOR);
+                }
+            }
+        }
+
+        for (i, p) in map.portals.iter().enumerate() {
+            let color = PORTAL_COLORS[i % PORTAL_COLORS.len()];
+            for pos in [p.a, p.b] {
+                self.circle_filled(painter, pos, p.radius, color.with_alpha(40));
+                self.circle_stroke(painter, pos, p.radius, Stroke::new(3.0_f32, color));
+            }
+        }
     }
 
     fn draw_item(&self, painter: &Painter, item: &Item) {
-        self.circle_filled(painter, item.pos, ITEM_RADIUS, item.kind.color32());
+        self.draw_item_icon(painter, item.kind, item.pos, ITEM_RADIUS, 255);
     }
 
-    fn draw_normal_menu(&self, painter: &Painter, world: &RwLockReadGuard<World>) {
+    /// Draws a colored disc with a dark glyph that identifies the item kind independent of its
+    /// color.
+    fn draw_item_icon(&self, painter: &Painter, kind: ItemKind, pos: Pos2, radius: f32, alpha: u8) {
+        let bg_color = kind.color32().with_alpha(alpha);
+        let color = Color32::from_gray(16).with_alpha(alpha);
+        let stroke = Stroke::new(0.18 * radius, color);
+        let p = |x: f32, y: f32| pos + radius * Vec2::new(x, y);
+
+        self.circle_filled(painter, pos, radius, bg_color);
+
+        match kind {
+            ItemKind::Speedup => {
+                // double chevron
+                let points = vec![p(-0.45, -0.4), p(-0.05, 0.0), p(-0.45, 0.4)];
+                self.add_path(painter, PathShape::line(points, stroke));
+                let points = vec![p(0.0, -0.4), p(0.4, 0.0), p(0.0, 0.4)];
+                self.add_path(painter, PathShape::line(points, stroke));
+            }
+            ItemKind::Slowdown => {
+                // turtle
+                let shell_center = p(-0.1, 0.15);
+                let shell = arc_points(shell_center, 0.45 * radius, PI, TAU, 12);
+                self.add_path(
+                    painter,
+                    PathShape::convex_polygon(shell, color, Stroke::NONE),
+                );
+                self.circle_filled(painter, p(0.5, 0.02), 0.14 * radius, color);
+                self.line_segment(painter, [p(-0.35, 0.15), p(-0.42, 0.4)], stroke);
+                self.line_segment(painter, [p(0.15, 0.15), p(0.22, 0.4)], stroke);
+            }
+            ItemKind::FastTurning => {
+                // tight curved arrow
+                let end_angle = PI;
+                let points = arc_points(pos, 0.4 * radius, -0.5 * PI, end_angle, 16);
+                let tip = *points.last().unwrap();
+                self.add_path(painter, PathShape::line(points, stroke));
+                let head = arrow_head_points(tip, end_angle + FRAC_PI_2, 0.3 * radius);
+                self.add_path(painter, PathShape::line(head, stroke));
+            }
+            ItemKind::SlowTurning => {
+                // wide curved arrow
+                let end_angle = 1.67 * PI;
+                let points = arc_points(p(0.0, 0.9), 1.1 * radius, 1.33 * PI, end_angle, 12);
+                let tip = *points.last().unwrap();
+                self.add_path(painter, PathShape::line(points, stroke));
+                let head = arrow_head_points(tip, end_angle + FRAC_PI_2, 0.3 * radius);
+                self.add_path(painter, PathShape::line(head, stroke));
+            }
+            ItemKind::Expand => {
+                // arrows pointing outwards
+                self.line_segment(painter, [p(-0.45, 0.45), p(0.45, -0.45)], stroke);
+                let head = arrow_head_points(p(0.45, -0.45), -0.25 * PI, 0.3 * radius);
+                self.add_path(painter, PathShape::line(head, stroke));
+                let head = arrow_head_points(p(-0.45, 0.45), 0.75 * PI, 0.3 * radius);
+                self.add_path(painter, PathShape::line(head, stroke));
+            }
+            ItemKind::Shrink => {
+                // arrows pointing inwards
+                self.line_segment(painter, [p(-0.55, 0.55), p(-0.1, 0.1)], stroke);
+                let head = arrow_head_points(p(-0.1, 0.1), -0.25 * PI, 0.3 * radius);
+                self.add_path(painter, PathShape::line(head, stroke));
+                self.line_segment(painter, [p(0.55, -0.55), p(0.1, -0.1)], stroke);
+                let head = arrow_head_points(p(0.1, -0.1), 0.75 * PI, 0.3 * radius);
+                self.add_path(painter, PathShape::line(head, stroke));
+            }
+            ItemKind::Ghost => {
+                // dome with feet and eyes
+                let mut body = arc_points(p(0.0, -0.1), 0.4 * radius, PI, TAU, 12);
+                body.push(p(0.4, 0.35));
+                body.push(p(-0.4, 0.35));
+                self.add_path(
+                    painter,
+                    PathShape::convex_polygon(body, color, Stroke::NONE),
+                );
+                for x in [-0.4, -0.13, 0.13] {
+                    let foot = vec![p(x, 0.3), p(x + 0.27, 0.3), p(x + 0.135, 0.55)];
+                    self.add_path(
+                        painter,
+                        PathShape::convex_polygon(foot, color, Stroke::NONE),
+                    );
+                }
+                self.circle_filled(painter, p(-0.15, -0.08), 0.09 * radius, bg_color);
+                self.circle_filled(painter, p(0.15, -0.08), 0.09 * radius, bg_color);
+            }
+            ItemKind::NoGap => {
+                // chain links
+                self.circle_stroke(painter, p(-0.22, 0.0), 0.28 * radius, stroke);
+                self.circle_stroke(painter, p(0.22, 0.0), 0.28 * radius, stroke);
+            }
+            ItemKind::WallTeleporting => {
+                // arrow passing through a wall
+                let wall_stroke = Stroke::new(0.12 * radius, color);
+                self.line_segment(painter, [p(0.0, -0.6), p(0.0, 0.6)], wall_stroke);
+                self.line_segment(painter, [p(-0.55, 0.0), p(0.5, 0.0)], stroke);
+                let head = arrow_head_points(p(0.5, 0.0), 0.0, 0.3 * radius);
+                self.add_path(painter, PathShape::line(head, stroke));
+            }
+            ItemKind::Clear => {
+                // scissors
+                self.line_segment(painter, [p(-0.2, 0.2), p(0.4, -0.55)], stroke);
+                self.line_segment(painter, [p(0.2, 0.2), p(-0.4, -0.55)], stroke);
+                let ring_stroke = Stroke::new(0.12 * radius, color);
+                self.circle_stroke(painter, p(-0.28, 0.36), 0.16 * radius, ring_stroke);
+                self.circle_stroke(painter, p(0.28, 0.36), 0.16 * radius, ring_stroke);
+            }
+        }
+    }
+
+    fn draw_normal_menu(&self, painter: &Painter, world: &World) {
         if let GameState::Stopped(_) = world.state {
             const FONT: FontId = FontId::new(20.0, FontFamily::Proportional);
             const BG_RECT_EXPAND: Vec2 = Vec2::new(6.0, 4.0);
@@ -684,12 +1476,13 @@ impl CurvefeverApp {
             const H_OFFSET: f32 = 15.0;
             let text_color = Color32::from_gray(200);
             let key_bg_color = Color32::from_gray(48).with_alpha(160);
-            let center_pos = (0.5 * WORLD_SIZE).to_pos2();
+            // shifted up to leave room for all hints
+            let first_pos = Pos2::new(0.5 * WORLD_SIZE.x, 0.5 * WORLD_SIZE.y - 2.0 * V_OFFSET);
 
             let outline_rect_idx = painter.add(Shape::Noop);
             let text_rect = self.text(
                 painter,
-                center_pos + Vec2::new(-H_OFFSET, -V_OFFSET),
+                first_pos + Vec2::new(-H_OFFSET, -V_OFFSET),
                 Align2::RIGHT_CENTER,
                 "SPACE",
                 FONT,
@@ -705,46 +1498,122 @@ impl CurvefeverApp {
             );
             self.text(
                 painter,
-                center_pos + Vec2::new(H_OFFSET, -V_OFFSET),
+                first_pos + Vec2::new(H_OFFSET, -V_OFFSET),
                 Align2::LEFT_CENTER,
                 "to restart",
                 FONT,
                 text_color,
             );
 
+            let map_hint = format!("to change map ({})", world.map.name);
+            let mode_hint = format!("to change game mode ({})", world.mode().name);
+            let palette_hint = format!("to change colors ({})", world.settings.palette.name());
+            let sudden_death_hint = format!(
+                "to change sudden death ({})",
+                world.settings.sudden_death.mode.name()
+            );
+            let audio = &world.settings.audio;
+            let audio_hint = if audio.muted {
+                "to change audio (muted)".to_string()
+            } else {
+                format!("to change audio ({:.0}%)", 100.0 * audio.volume)
+            };
+            let visual_effects_hint = format!(
+                "to change visual effects ({})",
+                world.settings.visual_effects.name()
+            );
             let hints = [
-                ("H", "for help"),
-                ("S", "to share"),
-                ("P", "to manage players"),
+                (Key::H, "for help"),
+                (Key::S, "to share"),
+                (Key::P, "to manage players"),
+                (Key::M, &map_hint),
+                (Key::G, &mode_hint),
+                (Key::E, "to edit the map"),
+                (Key::C, &palette_hint),
+                (Key::B, &sudden_death_hint),
+                (Key::X, "to export the round"),
+                (Key::U, &audio_hint),
+                (Key::V, &visual_effects_hint),
             ];
+            let hints: Vec<_> = hints
+                .into_iter()
+                .filter(|&(key, _)| !is_bound(&world.players, key))
+                .map(|(key, desc)| (key.name(), desc))
+                .collect();
+            self.draw_key_hints(painter, first_pos, &hints);
+        }
+    }
 
-            for (i, (key, desc)) in hints.iter().enumerate() {
-                let outline_rect_idx = painter.add(Shape::Noop);
-                let text_rect = self.text(
-                    painter,
-                    center_pos + Vec2::new(-H_OFFSET, i as f32 * V_OFFSET),
-                    Align2::RIGHT_CENTER,
-                    key,
-                    FONT,
-                    text_color,
-                );
-                self.set_rect(
-                    painter,
-                    outline_rect_idx,
-                    text_rect.expand2(BG_RECT_EXPAND),
-                    bg_rounding,
-                    key_bg_color,
-                    Stroke::NONE,
-                );
-                self.text(
-                    painter,
-                    center_pos + Vec2::new(H_OFFSET, i as f32 * V_OFFSET),
-                    Align2::LEFT_CENTER,
-                    desc,
-                    FONT,
-                    text_color,
-                );
-            }
+    fn draw_export_menu(&self, painter: &Painter, world: &World) {
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        let export = &world.settings.export;
+        let auto_screenshot_hint = format!(
+            "to save screenshots after each round ({})",
+            on_off(export.auto_screenshot)
+        );
+        let svg_hint = format!("to also save screenshots as SVG ({})", on_off(export.svg));
+        let gif_width_hint = format!("to change the GIF width ({}px)", export.gif_width);
+        let gif_speed_hint = format!("to change the GIF speed ({}x)", export.gif_speed);
+        let gif_style_hint = format!("to change the GIF style ({})", export.gif_style.name());
+        let hints = [
+            ("S", "to save a screenshot"),
+            ("A", &auto_screenshot_hint),
+            ("V", &svg_hint),
+            ("G", "to save the round as a GIF"),
+            ("R", &gif_width_hint),
+            ("F", &gif_speed_hint),
+            ("T", &gif_style_hint),
+        ];
+        let first_pos = Pos2::new(0.5 * WORLD_SIZE.x, 0.5 * WORLD_SIZE.y - 120.0);
+        self.draw_key_hints(painter, first_pos, &hints);
+    }
+
+    fn draw_audio_menu(&self, painter: &Painter, world: &World) {
+        let audio = &world.settings.audio;
+        let mute_hint = if audio.muted { "to unmute" } else { "to mute" };
+        let volume_hint = format!("to change the volume ({:.0}%)", 100.0 * audio.volume);
+        let hints = [("M", mute_hint), ("+/-", &volume_hint)];
+        let first_pos = Pos2::new(0.5 * WORLD_SIZE.x, 0.5 * WORLD_SIZE.y - 20.0);
+        self.draw_key_hints(painter, first_pos, &hints);
+    }
+
+    /// Draws one line per hint, with the highlighted key left of `first_pos` and its
+    /// description right of it.
+    fn draw_key_hints(&self, painter: &Painter, first_pos: Pos2, hints: &[(&str, &str)]) {
+        const FONT: FontId = FontId::new(20.0, FontFamily::Proportional);
+        const BG_RECT_EXPAND: Vec2 = Vec2::new(6.0, 4.0);
+        let bg_rounding = Rounding::same(6.0);
+        const V_OFFSET: f32 = 40.0;
+        const H_OFFSET: f32 = 15.0;
+        let text_color = Color32::from_gray(200);
+        let key_bg_color = Color32::from_gray(48).with_alpha(160);
+
+        for (i, (key, desc)) in hints.iter().enumerate() {
+            let outline_rect_idx = painter.add(Shape::Noop);
+            let text_rect = self.text(
+                painter,
+                first_pos + Vec2::new(-H_OFFSET, i as f32 * V_OFFSET),
+                Align2::RIGHT_CENTER,
+                *key,
+                FONT,
+                text_color,
+            );
+            self.set_rect(
+                painter,
+                outline_rect_idx,
+                text_rect.expand2(BG_RECT_EXPAND),
+                bg_rounding,
+                key_bg_color,
+                Stroke::NONE,
+            );
+            self.text(
+                painter,
+                first_pos + Vec2::new(H_OFFSET, i as f32 * V_OFFSET),
+                Align2::LEFT_CENTER,
+                *desc,
+                FONT,
+                text_color,
+            );
         }
     }
 
@@ -752,11 +1621,12 @@ impl CurvefeverApp {
         const FIELD_HEIGHT: f32 = WORLD_SIZE.y / (ITEM_KINDS.len() + 1) as f32;
         for (i, item) in ITEM_KINDS.iter().enumerate() {
             let pos = Pos2::new(0.5 * WORLD_SIZE.x, (i + 1) as f32 * FIELD_HEIGHT);
-            self.circle_filled(
+            self.draw_item_icon(
                 painter,
+                *item,
                 pos - Vec2::new(40.0, 0.0),
-                ITEM_RADIUS,
-                item.color32(),
+                2.0 * ITEM_RADIUS,
+                255,
             );
             let font = FontId::new(20.0, FontFamily::Proportional);
             self.text(
@@ -827,14 +1697,58 @@ impl CurvefeverApp {
                 self.pixel_perfect_rect_filled(painter, rect, Rounding::ZERO, Color32::BLACK);
             }
         }
+
+        // host dashboard url, below the qr code so it isn't scanned by players
+        self.text(
+            painter,
+            center + Vec2::new(0.0, qrcode_size.y / 2.0 + 0.5 * text_size),
+            Align2::CENTER_TOP,
+            format!("Host dashboard: {}", self.host_url),
+            FontId::new(0.4 * text_size, FontFamily::Proportional),
+            Color32::from_gray(140),
+        );
     }
 
-    fn draw_player_menu(
-        &self,
-        painter: &Painter,
-        player_menu: &PlayerMenu,
-        world: &RwLockReadGuard<World>,
-    ) {
+    fn draw_editor(&self, painter: &Painter, editor: &MapEditor) {
+        const FONT: FontId = FontId::new(16.0, FontFamily::Proportional);
+        let text_color = Color32::from_gray(200);
+        let spawn_color = Color32::from_rgb(50, 200, 80);
+
+        for pos in editor.spawn_positions.iter() {
+            self.circle_filled(
+                painter,
+                *pos,
+                0.5 * MIN_PLAYER_DIST,
+                spawn_color.with_alpha(8),
+            );
+            self.circle_filled(painter, *pos, 4.0, spawn_color.with_alpha(160));
+        }
+
+        self.draw_map(painter, &editor.map);
+        if let Some(wall) = editor.preview() {
+            let preview = Map {
+                walls: vec![wall],
+                ..Default::default()
+            };
+            self.draw_map(painter, &preview);
+        }
+
+        let cursor = if editor.renaming { "_" } else { "" };
+        let lines = [
+            format!("name: {}{cursor}", editor.map.name),
+            format!("tool: {}", editor.tool.name()),
+            format!("spawn positions: {}", editor.spawn_positions.len()),
+            "1 segment   2 circle   3 move   right click delete".to_string(),
+            "R rename   C clear   S save   T test play   Q leave".to_string(),
+        ];
+        let mut pos = Pos2::new(20.0, 20.0);
+        for line in lines.iter().chain(editor.message.iter()) {
+            self.text(painter, pos, Align2::LEFT_TOP, line, FONT, text_color);
+            pos.y += 24.0;
+        }
+    }
+
+    fn draw_player_menu(&self, painter: &Painter, player_menu: &PlayerMenu, world: &World) {
         const FIELD_SIZE: Vec2 = Vec2::new(
             WORLD_SIZE.x / 6.0,
             WORLD_SIZE.y / (PLAYER_COLORS.len() + 1) as f32,
@@ -853,7 +1767,7 @@ impl CurvefeverApp {
                 Align2::CENTER_CENTER,
                 &player.name,
                 font,
-                player.color.color32(),
+                player.color.color32(world.settings.palette),
             );
 
             //left key
@@ -893,6 +1807,37 @@ impl CurvefeverApp {
                 font,
                 text_color,
             );
+
+            //trail pattern
+            let pos = Pos2::new(
+                0.5 * WORLD_SIZE.x + 2.5 * FIELD_SIZE.x,
+                (index as f32 + 1.0) * FIELD_SIZE.y,
+            );
+            let font = FontId::new(0.5 * FIELD_SIZE.y, FontFamily::Proportional);
+            self.text(
+                painter,
+                pos,
+                Align2::CENTER_CENTER,
+                player.pattern.name(),
+                font,
+                Color32::from_gray(200),
+            );
+
+            //remote latency, left of the name
+            if let Some(rtt) = player.remote_rtt {
+                let pos = Pos2::new(
+                    0.5 * WORLD_SIZE.x - 2.5 * FIELD_SIZE.x,
+                    (index as f32 + 1.0) * FIELD_SIZE.y,
+                );
+                let font = FontId::new(0.3 * FIELD_SIZE.y, FontFamily::Proportional);
+                let rtt_ms = rtt.as_millis();
+                let (text, text_color) = if rtt_ms > POOR_RTT_MS as u128 {
+                    (format!("{rtt_ms} ms!"), POOR_LATENCY_COLOR)
+                } else {
+                    (format!("{rtt_ms} ms"), Color32::from_gray(120))
+                };
+                self.text(painter, pos, Align2::CENTER_CENTER, &text, font, text_color);
+            }
         }
 
         //selection
@@ -914,13 +1859,14 @@ impl CurvefeverApp {
         };
         let y = (player_menu.player_index as f32 + 0.5) * FIELD_SIZE.y;
         let rect = Rect::from_min_size(Pos2::new(x, y), selection_size);
-        let stroke = Stroke::new(4.0, color);
+        let stroke = Stroke::new(4.0_f32, color);
         self.rect_stroke(painter, rect, Rounding::same(0.1 * FIELD_SIZE.y), stroke);
     }
 
-    fn draw_hud(&self, painter: &Painter, world: &RwLockReadGuard<World>) {
+    fn draw_hud(&self, painter: &Painter, world: &World) {
         const HUD_FONT: FontId = FontId::new(14.0, FontFamily::Proportional);
         const HUD_ALPHA: u8 = 160;
+        const HUD_EFFECT_RADIUS: f32 = 8.0;
         let hud_rounding = Rounding::same(8.0);
         let hud_text_color = Color32::from_gray(160).with_alpha(HUD_ALPHA);
         let hud_effect_bar_color = Color32::from_gray(100).with_alpha(HUD_ALPHA);
@@ -938,7 +1884,7 @@ impl CurvefeverApp {
                 Align2::LEFT_TOP,
                 &p.name,
                 HUD_FONT,
-                p.color.color32(),
+                p.color.color32(world.settings.palette),
             );
             let min = text_pos;
 
@@ -961,7 +1907,7 @@ impl CurvefeverApp {
                 };
 
                 let now = world.clock.now;
-                let passed_duration = now.duration_since(e.start).unwrap();
+                let passed_duration = now.saturating_sub(e.start);
                 let ratio = passed_duration.as_secs_f32() / e.duration.as_secs_f32();
 
                 // effect arc
@@ -972,12 +1918,13 @@ impl CurvefeverApp {
                     let mut points = Vec::new();
                     let mut angle: f32 = 0.0;
                     for _ in 0..num_points {
-                        let pos = effect_pos + 6.0 * Vec2::new(angle.cos(), angle.sin());
+                        let pos =
+                            effect_pos + HUD_EFFECT_RADIUS * Vec2::new(angle.cos(), angle.sin());
                         points.push(pos);
                         angle += angle_step;
                     }
                     let color = item_kind.color32();
-                    let stroke = Stroke::new(3.0, color.with_alpha(HUD_ALPHA));
+                    let stroke = Stroke::new(3.0_f32, color.with_alpha(HUD_ALPHA));
                     let path = PathShape::line(points, stroke);
                     self.add_path(painter, path);
                 }
@@ -989,18 +1936,23 @@ impl CurvefeverApp {
                     let mut points = Vec::new();
                     let mut angle: f32 = 0.0;
                     for _ in 0..num_points {
-                        let pos = effect_pos + 6.0 * Vec2::new(angle.cos(), angle.sin());
+                        let pos =
+                            effect_pos + HUD_EFFECT_RADIUS * Vec2::new(angle.cos(), angle.sin());
                         points.push(pos);
                         angle -= angle_step;
                     }
-                    let stroke = Stroke::new(3.0, hud_effect_bar_color);
+                    let stroke = Stroke::new(3.0_f32, hud_effect_bar_color);
                     let path = PathShape::line(points, stroke);
                     self.add_path(painter, path);
                 }
 
+                // effect icon
+                let icon_radius = HUD_EFFECT_RADIUS - 2.5;
+                self.draw_item_icon(painter, item_kind, effect_pos, icon_radius, HUD_ALPHA);
+
                 // update next pos
-                max.x = effect_pos.x + 10.0;
-                effect_pos.x += 20.0;
+                max.x = effect_pos.x + HUD_EFFECT_RADIUS + 4.0;
+                effect_pos.x += 2.0 * HUD_EFFECT_RADIUS + 8.0;
             }
 
             let outline_rect = Rect::from_min_max(min, max);
@@ -1016,11 +1968,12 @@ impl CurvefeverApp {
 
         // crash feed
         let mut text_pos = Pos2::new(WORLD_SIZE.x - 20.0, 20.0);
+        let palette = world.settings.palette;
         for c in world.crash_feed.iter() {
             match world.state {
                 GameState::Starting(_) | GameState::Running(_) => {
                     const CRASH_DISPLAY_DURATION: Duration = Duration::from_secs(5);
-                    let passed_duration = world.clock.now.duration_since(c.time).unwrap();
+                    let passed_duration = world.clock.now.saturating_sub(c.time);
                     if passed_duration > CRASH_DISPLAY_DURATION {
                         continue;
                     }
@@ -1048,7 +2001,7 @@ impl CurvefeverApp {
                         Align2::RIGHT_TOP,
                         name,
                         HUD_FONT,
-                        color.with_alpha(HUD_ALPHA),
+                        color.color32(palette).with_alpha(HUD_ALPHA),
                     );
                     let min = text_rect.left_top();
                     Rect::from_min_max(min, max)
@@ -1071,7 +2024,7 @@ impl CurvefeverApp {
                         Align2::RIGHT_TOP,
                         name,
                         HUD_FONT,
-                        color.with_alpha(HUD_ALPHA),
+                        color.color32(palette).with_alpha(HUD_ALPHA),
                     );
                     let min = text_rect.left_top();
                     Rect::from_min_max(min, max)
@@ -1088,7 +2041,7 @@ impl CurvefeverApp {
                         Align2::RIGHT_TOP,
                         other_name,
                         HUD_FONT,
-                        other_color.with_alpha(HUD_ALPHA),
+                        other_color.color32(palette).with_alpha(HUD_ALPHA),
                     );
                     let max = text_rect.right_bottom();
 
@@ -1109,7 +2062,7 @@ impl CurvefeverApp {
                         Align2::RIGHT_TOP,
                         crashed_name,
                         HUD_FONT,
-                        crashed_color.with_alpha(HUD_ALPHA),
+                        crashed_color.color32(palette).with_alpha(HUD_ALPHA),
                     );
                     let min = text_rect.left_top();
                     Rect::from_min_max(min, max)
@@ -1141,7 +2094,7 @@ impl CurvefeverApp {
 
         match world.state {
             GameState::Starting(start) => {
-                let time = world.clock.now.duration_since(start).unwrap().as_secs();
+                let time = world.clock.now.saturating_sub(start).as_secs();
                 let text = START_DELAY.as_secs() - time;
                 let font = FontId::new(80.0, FontFamily::Monospace);
                 self.text(
@@ -1157,7 +2110,7 @@ impl CurvefeverApp {
                 let outline_rect_idx = painter.add(Shape::Noop);
 
                 let font = FontId::new(20.0, FontFamily::Monospace);
-                let duration = world.clock.now.duration_since(start).unwrap();
+                let duration = world.clock.now.saturating_sub(start);
                 let total_secs = duration.as_secs();
                 let minutes = total_secs / 60;
                 let secs = total_secs % 60;
@@ -1196,6 +2149,12 @@ impl CurvefeverApp {
         painter.circle_filled(self.wts_pos(pos), radius, fill_color);
     }
 
+    fn circle_stroke(&self, painter: &Painter, pos: Pos2, mut radius: f32, mut stroke: Stroke) {
+        radius *= self.world_to_screen_scale;
+        stroke.width *= self.world_to_screen_scale;
+        painter.circle_stroke(self.wts_pos(pos), radius, stroke);
+    }
+
     fn line_segment(&self, painter: &Painter, points: [Pos2; 2], mut stroke: Stroke) {
         let points = [self.wts_pos(points[0]), self.wts_pos(points[1])];
         stroke.width *= self.world_to_screen_scale;
@@ -1254,7 +2213,7 @@ impl CurvefeverApp {
     }
 }
 
-trait ColorExt {
+pub trait ColorExt {
     fn with_alpha(&self, a: u8) -> Color32;
 }
 
@@ -1265,24 +2224,158 @@ impl ColorExt for Color32 {
     }
 }
 
+fn load_script(mode: &GameMode) -> Option<ScriptHost> {
+    let script = mode.script.as_ref()?;
+    match ScriptHost::new(script) {
+        Ok(h) => Some(h),
+        Err(e) => {
+            tracing::error!("Error loading game mode `{}`:\n{e:#}", mode.name);
+            None
+        }
+    }
+}
+
+/// Returns whether the players changed.
+fn handle_host_event(world: &mut World, menu: &RwLock<Menu>, event: HostEvent) -> bool {
+    let stopped = matches!(&world.state, GameState::Stopped(_));
+    match event {
+        HostEvent::PrevPattern { player_id } => {
+            let Some(p) = find_player(&mut world.players, player_id) else {
+                return false;
+            };
+            p.pattern.prev();
+        }
+        HostEvent::NextPattern { player_id } => {
+            let Some(p) = find_player(&mut world.players, player_id) else {
+                return false;
+            };
+            p.pattern.next();
+        }
+        HostEvent::SetKeys {
+            player_id,
+            left_key,
+            right_key,
+        } => {
+            let Some(p) = find_player(&mut world.players, player_id) else {
+                return false;
+            };
+            p.left_key = left_key;
+            p.right_key = right_key;
+        }
+        HostEvent::MovePlayer { player_id, index } => {
+            let idx = world.players.iter().position(|p| p.id == player_id);
+            let Some(idx) = idx.filter(|_| stopped) else {
+                return false;
+            };
+            let p = world.players.remove(idx);
+            let index = index.min(world.players.len());
+            world.players.insert(index, p);
+        }
+    }
+
+    clamp_player_menu(menu, world.players.len());
+    true
+}
+
+/// Keeps the selection of the player menu on an existing player.
+fn clamp_player_menu(menu: &RwLock<Menu>, num_players: usize) {
+    let mut menu = menu.write().unwrap();
+    if let MenuState::Player(player_menu) = &mut menu.state {
+        player_menu.player_index = player_menu.player_index.min(num_players - 1);
+    }
+}
+
+/// Whether a menu hotkey was pressed. Keys that are bound to a player's steering are ignored, so
+/// steering between rounds doesn't change any settings.
+fn hotkey_pressed(input: &InputState, players: &[Player], key: Key) -> bool {
+    !is_bound(players, key) && input.key_pressed(key)
+}
+
+fn is_bound(players: &[Player], key: Key) -> bool {
+    players
+        .iter()
+        .any(|p| p.left_key == Some(key) || p.right_key == Some(key))
+}
+
 fn find_player(players: &mut [Player], player_id: u16) -> Option<&mut Player> {
     players.iter_mut().find(|p| p.id == player_id)
 }
 
-fn sync_players(game_sender: &Sender<GameEvent>, players: &[Player]) {
-    let players = players.iter().map(player_dto).collect();
+fn sync_players(game_sender: &Sender<GameEvent>, players: &[Player], palette: Palette) {
+    let players = players.iter().map(|p| player_dto(p, palette)).collect();
     let event = GameEvent::PlayerSync { players };
     game_sender.send_blocking(event).unwrap();
 }
 
-fn player_dto(player: &Player) -> curvefever_common::Player {
+/// The feedback events for the remotes of the players affected by the `events`.
+fn player_feedback<'a>(
+    world: &'a World,
+    events: &'a [WorldEvent],
+) -> impl Iterator<Item = GameEvent> + 'a {
+    events.iter().filter_map(|e| {
+        let (player_id, feedback) = match e {
+            WorldEvent::Crashed { player_id, .. } => (*player_id, Feedback::Crash),
+            WorldEvent::ItemCollected { player_id, .. } => (*player_id, Feedback::ItemPickup),
+            WorldEvent::StateChanged {
+                from: GameState::Running(_),
+                to: GameState::Stopped(_),
+            } => (world.round_winner()?, Feedback::RoundWon),
+            _ => return None,
+        };
+        Some(GameEvent::PlayerFeedback {
+            player_id,
+            feedback,
+        })
+    })
+}
+
+fn player_dto(player: &Player, palette: Palette) -> curvefever_common::Player {
     curvefever_common::Player {
         id: player.id,
-        color: player.color.color32().to_array(),
+        color: player.color.color32(palette).to_array(),
         name: player.name.clone(),
     }
 }
 
+/// Tessellates screen space shapes into `mesh`, so they don't have to be tessellated again every
+/// frame.
+fn tessellate(painter: &Painter, shapes: Vec<Shape>, mesh: &mut Mesh) {
+    if shapes.is_empty() {
+        return;
+    }
+
+    let ctx = painter.ctx();
+    let options = ctx.tessellation_options(|o| *o);
+    let font_tex_size = ctx.fonts(|f| f.font_image_size());
+    let mut tessellator =
+        Tessellator::new(ctx.pixels_per_point(), options, font_tex_size, Vec::new());
+    for s in shapes {
+        tessellator.tessellate_shape(s, mesh);
+    }
+}
+
+/// Points along an arc around `center`, including both the start and end point.
+fn arc_points(center: Pos2, radius: f32, start_angle: f32, end_angle: f32, steps: u8) -> Vec<Pos2> {
+    let angle_step = (end_angle - start_angle) / steps as f32;
+    (0..=steps)
+        .map(|i| {
+            let angle = start_angle + i as f32 * angle_step;
+            center + radius * Vec2::new(angle.cos(), angle.sin())
+        })
+        .collect()
+}
+
+/// The two barbs of an arrow head pointing towards `angle`, joined at the `tip`.
+fn arrow_head_points(tip: Pos2, angle: f32, len: f32) -> Vec<Pos2> {
+    let left_angle = angle - FRAC_PI_4;
+    let right_angle = angle + FRAC_PI_4;
+    vec![
+        tip - len * Vec2::new(left_angle.cos(), left_angle.sin()),
+        tip,
+        tip - len * Vec2::new(right_angle.cos(), right_angle.sin()),
+    ]
+}
+
 fn pressed_key(event: &Event) -> Option<Key> {
     match event {
         Event::Key {
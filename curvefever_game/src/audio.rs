@@ -0,0 +1,480 @@
+use std::f32::consts::TAU;
+
+use crate::world::{CrashMessage, GameState, ItemKind, WorldEvent};
+
+pub const SAMPLE_RATE: u32 = 44_100;
+pub const VOLUME_STEP: f32 = 0.1;
+pub const DEFAULT_VOLUME: f32 = 0.6;
+/// The time it takes a tone to fade in, to avoid clicks.
+const ATTACK_TIME: f32 = 0.005;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioSettings {
+    /// The master volume from 0.0 to 1.0.
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            volume: DEFAULT_VOLUME,
+            muted: false,
+        }
+    }
+}
+
+impl AudioSettings {
+    /// The volume sounds are actually played at.
+    pub fn effective_volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.volume
+        }
+    }
+
+    pub fn volume_up(&mut self) {
+        self.volume = (self.volume + VOLUME_STEP).min(1.0);
+    }
+
+    pub fn volume_down(&mut self) {
+        self.volume = (self.volume - VOLUME_STEP).max(0.0);
+    }
+}
+
+/// Something that can play mono samples at [`SAMPLE_RATE`].
+pub trait AudioBackend {
+    fn play(&mut self, samples: Vec<f32>, volume: f32);
+}
+
+/// Discards all sounds, used for headless runs and when no sound device is available.
+pub struct NullAudio;
+
+impl AudioBackend for NullAudio {
+    fn play(&mut self, _samples: Vec<f32>, _volume: f32) {}
+}
+
+#[cfg(feature = "audio")]
+pub struct RodioAudio {
+    /// The stream has to be kept alive for sounds to play.
+    _stream: rodio::OutputStream,
+    handle: rodio::OutputStreamHandle,
+}
+
+#[cfg(feature = "audio")]
+impl RodioAudio {
+    pub fn new() -> anyhow::Result<Self> {
+        let (stream, handle) = rodio::OutputStream::try_default()?;
+        Ok(Self {
+            _stream: stream,
+            handle,
+        })
+    }
+}
+
+#[cfg(feature = "audio")]
+impl AudioBackend for RodioAudio {
+    fn play(&mut self, samples: Vec<f32>, volume: f32) {
+        use rodio::Source as _;
+
+        let source = rodio::buffer::SamplesBuffer::new(1, SAMPLE_RATE, samples).amplify(volume);
+        if let Err(e) = self.handle.play_raw(source) {
+            tracing::warn!("Error playing sound: {e}");
+        }
+    }
+}
+
+/// The sound device if the `audio` feature is enabled and one is available, otherwise
+/// [`NullAudio`].
+pub fn default_backend() -> Box<dyn AudioBackend> {
+    #[cfg(feature = "audio")]
+    match RodioAudio::new() {
+        Ok(a) => return Box::new(a),
+        Err(e) => tracing::warn!("Error opening sound device, audio is disabled:\n{e}"),
+    }
+
+    Box::new(NullAudio)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cue {
+    /// One of the seconds counting down before a round starts.
+    CountdownTick,
+    CountdownGo,
+    Pickup(ItemKind),
+    Crash(CrashKind),
+    GapStart,
+    GapEnd,
+    RoundWin,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrashKind {
+    Wall,
+    Own,
+    Other,
+}
+
+impl From<&CrashMessage> for CrashKind {
+    fn from(message: &CrashMessage) -> Self {
+        match message {
+            CrashMessage::Own { .. } => Self::Own,
+            CrashMessage::Wall { .. } => Self::Wall,
+            CrashMessage::Other { .. } => Self::Other,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Wave {
+    Sine,
+    Square,
+    Triangle,
+    Noise,
+}
+
+/// A single synthesized note, with the frequency sliding from `start_freq` to `end_freq`.
+#[derive(Clone, Copy)]
+struct Tone {
+    wave: Wave,
+    start_freq: f32,
+    end_freq: f32,
+    /// The length in seconds.
+    duration: f32,
+    gain: f32,
+}
+
+const fn tone(wave: Wave, start_freq: f32, end_freq: f32, duration: f32, gain: f32) -> Tone {
+    Tone {
+        wave,
+        start_freq,
+        end_freq,
+        duration,
+        gain,
+    }
+}
+
+impl Cue {
+    /// The tones played one after another.
+    fn tones(&self) -> Vec<Tone> {
+        use Wave::*;
+
+        match self {
+            Cue::CountdownTick => vec![tone(Sine, 660.0, 660.0, 0.08, 0.5)],
+            Cue::CountdownGo => vec![tone(Sine, 990.0, 990.0, 0.25, 0.5)],
+            Cue::Pickup(kind) => match kind {
+                ItemKind::Speedup => vec![tone(Square, 400.0, 900.0, 0.12, 0.2)],
+                ItemKind::Slowdown => vec![tone(Square, 900.0, 400.0, 0.12, 0.2)],
+                ItemKind::FastTurning => vec![
+                    tone(Triangle, 700.0, 700.0, 0.05, 0.5),
+                    tone(Triangle, 940.0, 940.0, 0.05, 0.5),
+                    tone(Triangle, 700.0, 700.0, 0.05, 0.5),
+                ],
+                ItemKind::SlowTurning => vec![
+                    tone(Triangle, 500.0, 500.0, 0.1, 0.5),
+                    tone(Triangle, 380.0, 380.0, 0.1, 0.5),
+                ],
+                ItemKind::Expand => vec![tone(Sine, 300.0, 150.0, 0.2, 0.6)],
+                ItemKind::Shrink => vec![tone(Sine, 600.0, 1200.0, 0.15, 0.5)],
+                ItemKind::Ghost => vec![tone(Sine, 500.0, 800.0, 0.35, 0.3)],
+                ItemKind::NoGap => vec![
+                    tone(Square, 520.0, 520.0, 0.06, 0.15),
+                    tone(Square, 780.0, 780.0, 0.1, 0.15),
+                ],
+                ItemKind::WallTeleporting => vec![
+                    tone(Sine, 400.0, 1600.0, 0.1, 0.4),
+                    tone(Sine, 1600.0, 400.0, 0.1, 0.4),
+                ],
+                ItemKind::Clear => vec![tone(Noise, 0.0, 0.0, 0.3, 0.25)],
+            },
+            Cue::Crash(kind) => match kind {
+                CrashKind::Wall => vec![tone(Noise, 0.0, 0.0, 0.25, 0.5)],
+                CrashKind::Own => vec![
+                    tone(Noise, 0.0, 0.0, 0.1, 0.4),
+                    tone(Square, 220.0, 110.0, 0.25, 0.2),
+                ],
+                CrashKind::Other => vec![
+                    tone(Noise, 0.0, 0.0, 0.1, 0.4),
+                    tone(Square, 330.0, 165.0, 0.25, 0.2),
+                ],
+            },
+            Cue::GapStart => vec![tone(Sine, 1200.0, 1200.0, 0.02, 0.1)],
+            Cue::GapEnd => vec![tone(Sine, 900.0, 900.0, 0.02, 0.1)],
+            Cue::RoundWin => vec![
+                tone(Triangle, 523.3, 523.3, 0.12, 0.5),
+                tone(Triangle, 659.3, 659.3, 0.12, 0.5),
+                tone(Triangle, 784.0, 784.0, 0.12, 0.5),
+                tone(Triangle, 1046.5, 1046.5, 0.35, 0.5),
+            ],
+        }
+    }
+
+    /// Synthesizes the cue as mono samples at [`SAMPLE_RATE`].
+    pub fn samples(&self) -> Vec<f32> {
+        let mut samples = Vec::new();
+        // a simple xorshift generator, the noise doesn't need to be random between plays
+        let mut noise_state: u32 = 0x9e37_79b9;
+        for t in self.tones() {
+            let num_samples = (t.duration * SAMPLE_RATE as f32) as usize;
+            let mut phase = 0.0;
+            for i in 0..num_samples {
+                let progress = i as f32 / num_samples as f32;
+                let freq = t.start_freq + progress * (t.end_freq - t.start_freq);
+                phase = (phase + freq / SAMPLE_RATE as f32) % 1.0;
+
+                let value = match t.wave {
+                    Wave::Sine => (TAU * phase).sin(),
+                    Wave::Square => {
+                        if phase < 0.5 {
+                            1.0
+                        } else {
+                            -1.0
+                        }
+                    }
+                    Wave::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+                    Wave::Noise => {
+                        noise_state ^= noise_state << 13;
+                        noise_state ^= noise_state >> 17;
+                        noise_state ^= noise_state << 5;
+                        2.0 * (noise_state as f32 / u32::MAX as f32) - 1.0
+                    }
+                };
+
+                let time = i as f32 / SAMPLE_RATE as f32;
+                let attack = (time / ATTACK_TIME).min(1.0);
+                let release = 1.0 - progress;
+                samples.push(t.gain * attack * release * value);
+            }
+        }
+        samples
+    }
+}
+
+/// Plays cues for the events of the world.
+pub struct Audio {
+    backend: Box<dyn AudioBackend>,
+}
+
+impl Audio {
+    pub fn new(backend: Box<dyn AudioBackend>) -> Self {
+        Self { backend }
+    }
+
+    pub fn play(&mut self, cue: Cue, volume: f32) {
+        if volume <= 0.0 {
+            return;
+        }
+        self.backend.play(cue.samples(), volume);
+    }
+
+    pub fn handle_events(&mut self, events: &[WorldEvent], settings: &AudioSettings) {
+        let volume = settings.effective_volume();
+        for e in events.iter() {
+            let cue = match e {
+                WorldEvent::Countdown { .. } => Cue::CountdownTick,
+                WorldEvent::StateChanged {
+                    from: GameState::Starting(_),
+                    to: GameState::Running(_),
+                } => Cue::CountdownGo,
+                WorldEvent::StateChanged {
+                    from: GameState::Running(_),
+                    to: GameState::Stopped(_),
+                } => Cue::RoundWin,
+                WorldEvent::ItemCollected { kind, .. } => Cue::Pickup(*kind),
+                WorldEvent::Crashed { message, .. } => Cue::Crash(CrashKind::from(message)),
+                WorldEvent::GapStarted { .. } => Cue::GapStart,
+                WorldEvent::GapEnded { .. } => Cue::GapEnd,
+                _ => continue,
+            };
+            self.play(cue, volume);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use egui::Pos2;
+
+    use super::*;
+    use crate::world::PlayerColor;
+
+    /// A played sound and its volume.
+    type Sound = (Vec<f32>, f32);
+
+    /// Records the sounds that were played.
+    #[derive(Clone, Default)]
+    struct RecordingAudio(Rc<RefCell<Vec<Sound>>>);
+
+    impl AudioBackend for RecordingAudio {
+        fn play(&mut self, samples: Vec<f32>, volume: f32) {
+            self.0.borrow_mut().push((samples, volume));
+        }
+    }
+
+    fn all_cues() -> Vec<Cue> {
+        let mut cues = vec![
+            Cue::CountdownTick,
+            Cue::CountdownGo,
+            Cue::Crash(CrashKind::Wall),
+            Cue::Crash(CrashKind::Own),
+            Cue::Crash(CrashKind::Other),
+            Cue::GapStart,
+            Cue::GapEnd,
+            Cue::RoundWin,
+        ];
+        cues.extend(ItemKind::members().iter().copied().map(Cue::Pickup));
+        cues
+    }
+
+    /// The cues and volumes played for the events.
+    fn played(events: &[WorldEvent], settings: &AudioSettings) -> Vec<(Cue, f32)> {
+        let backend = RecordingAudio::default();
+        let mut audio = Audio::new(Box::new(backend.clone()));
+        audio.handle_events(events, settings);
+
+        let cues = all_cues();
+        let played = backend.0.borrow();
+        played
+            .iter()
+            .map(|(samples, volume)| {
+                let cue = cues.iter().find(|c| c.samples() == *samples);
+                (*cue.expect("unknown sound"), *volume)
+            })
+            .collect()
+    }
+
+    fn crash(message: CrashMessage) -> WorldEvent {
+        WorldEvent::Crashed {
+            player_id: 0,
+            pos: Pos2::ZERO,
+            message,
+        }
+    }
+
+    fn events() -> Vec<WorldEvent> {
+        let secs = Duration::from_secs;
+        vec![
+            WorldEvent::Countdown { remaining_secs: 3 },
+            WorldEvent::Countdown { remaining_secs: 1 },
+            WorldEvent::StateChanged {
+                from: GameState::Starting(secs(0)),
+                to: GameState::Running(secs(3)),
+            },
+            WorldEvent::ItemCollected {
+                player_id: 0,
+                pos: Pos2::ZERO,
+                kind: ItemKind::Ghost,
+            },
+            // only the collection is audible
+            WorldEvent::EffectStarted {
+                player_id: Some(0),
+                kind: ItemKind::Ghost,
+            },
+            crash(CrashMessage::Wall {
+                name: "a".into(),
+                color: PlayerColor::Red,
+            }),
+            crash(CrashMessage::Own {
+                name: "b".into(),
+                color: PlayerColor::Blue,
+            }),
+            crash(CrashMessage::Other {
+                crashed_name: "c".into(),
+                crashed_color: PlayerColor::Green,
+                other_name: "a".into(),
+                other_color: PlayerColor::Red,
+            }),
+            WorldEvent::StateChanged {
+                from: GameState::Running(secs(3)),
+                to: GameState::Stopped(secs(20)),
+            },
+        ]
+    }
+
+    #[test]
+    fn cues_for_events() {
+        let settings = AudioSettings::default();
+        let cues: Vec<_> = played(&events(), &settings)
+            .into_iter()
+            .map(|(c, _)| c)
+            .collect();
+        assert_eq!(
+            cues,
+            [
+                Cue::CountdownTick,
+                Cue::CountdownTick,
+                Cue::CountdownGo,
+                Cue::Pickup(ItemKind::Ghost),
+                Cue::Crash(CrashKind::Wall),
+                Cue::Crash(CrashKind::Own),
+                Cue::Crash(CrashKind::Other),
+                Cue::RoundWin,
+            ]
+        );
+    }
+
+    #[test]
+    fn pause_is_silent() {
+        let secs = Duration::from_secs;
+        let events = [
+            WorldEvent::StateChanged {
+                from: GameState::Running(secs(3)),
+                to: GameState::Paused(secs(5)),
+            },
+            WorldEvent::StateChanged {
+                from: GameState::Paused(secs(5)),
+                to: GameState::Running(secs(5)),
+            },
+        ];
+        assert!(played(&events, &AudioSettings::default()).is_empty());
+    }
+
+    #[test]
+    fn cues_are_distinct() {
+        let cues = all_cues();
+        for (i, a) in cues.iter().enumerate() {
+            assert!(!a.samples().is_empty(), "{a:?} is silent");
+            for b in cues[i + 1..].iter() {
+                assert_ne!(a.samples(), b.samples(), "{a:?} and {b:?} sound the same");
+            }
+        }
+    }
+
+    #[test]
+    fn volume_is_respected() {
+        let mut settings = AudioSettings {
+            volume: 0.3,
+            muted: false,
+        };
+        let played = played(&events(), &settings);
+        assert!(played.iter().all(|(_, v)| *v == 0.3));
+
+        settings.volume_up();
+        assert!((settings.effective_volume() - 0.4).abs() < 1e-6);
+        for _ in 0..20 {
+            settings.volume_up();
+        }
+        assert_eq!(settings.effective_volume(), 1.0);
+    }
+
+    #[test]
+    fn muted_and_silent_play_nothing() {
+        let muted = AudioSettings {
+            volume: 1.0,
+            muted: true,
+        };
+        assert!(played(&events(), &muted).is_empty());
+
+        let mut silent = AudioSettings::default();
+        for _ in 0..20 {
+            silent.volume_down();
+        }
+        assert_eq!(silent.volume, 0.0);
+        assert!(played(&events(), &silent).is_empty());
+    }
+}
@@ -0,0 +1,328 @@
+use egui::{Pos2, Rect, Vec2};
+
+use crate::map::{Map, Wall};
+use crate::world::{self, MIN_PLAYER_DIST, MIN_PLAYER_WALL_DIST, WORLD_SIZE};
+
+pub const DEFAULT_WALL_THICKNESS: f32 = 8.0;
+pub const MIN_CIRCLE_RADIUS: f32 = 5.0;
+/// The distance from an element at which it can still be selected with the mouse.
+pub const SELECTION_DIST: f32 = 6.0;
+/// Grid spacing used when sampling possible spawn positions.
+const SPAWN_GRID_SIZE: f32 = 10.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EditorTool {
+    #[default]
+    Segment,
+    Circle,
+    Move,
+}
+
+impl EditorTool {
+    pub const fn name(&self) -> &str {
+        match self {
+            EditorTool::Segment => "segment",
+            EditorTool::Circle => "circle",
+            EditorTool::Move => "move",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapEditor {
+    pub map: Map,
+    pub tool: EditorTool,
+    pub renaming: bool,
+    /// The position at which the primary pointer button was pressed.
+    drag_start: Option<Pos2>,
+    /// The last pointer position while dragging.
+    drag_pos: Pos2,
+    /// The wall that is being moved.
+    moving: Option<usize>,
+    /// Spawn positions that satisfy [`MIN_PLAYER_WALL_DIST`] and [`MIN_PLAYER_DIST`].
+    pub spawn_positions: Vec<Pos2>,
+    pub message: Option<String>,
+}
+
+impl MapEditor {
+    pub fn new(map: Map) -> Self {
+        let spawn_positions = spawn_positions(&map);
+        Self {
+            map,
+            tool: EditorTool::default(),
+            renaming: false,
+            drag_start: None,
+            drag_pos: Pos2::ZERO,
+            moving: None,
+            spawn_positions,
+            message: None,
+        }
+    }
+
+    pub fn pointer_pressed(&mut self, pos: Pos2) {
+        self.drag_start = Some(pos);
+        self.drag_pos = pos;
+        if self.tool == EditorTool::Move {
+            self.moving = self.wall_at(pos);
+        }
+    }
+
+    pub fn pointer_moved(&mut self, pos: Pos2) {
+        if self.drag_start.is_none() {
+            return;
+        }
+
+        if let Some(idx) = self.moving {
+            let delta = pos - self.drag_pos;
+            translate_wall(&mut self.map.walls[idx], delta);
+        }
+        self.drag_pos = pos;
+    }
+
+    pub fn pointer_released(&mut self, pos: Pos2) {
+        self.pointer_moved(pos);
+        if let Some(wall) = self.preview() {
+            self.map.walls.push(wall);
+        }
+        self.drag_start = None;
+        self.moving = None;
+        self.update_spawn_positions();
+    }
+
+    /// The wall that would be added if the pointer was released now.
+    pub fn preview(&self) -> Option<Wall> {
+        let start = self.drag_start?;
+        let end = self.drag_pos;
+        match self.tool {
+            EditorTool::Segment if start.distance(end) > DEFAULT_WALL_THICKNESS => {
+                Some(Wall::Segment {
+                    start,
+                    end,
+                    thickness: DEFAULT_WALL_THICKNESS,
+                })
+            }
+            EditorTool::Circle if start.distance(end) > MIN_CIRCLE_RADIUS => Some(Wall::Circle {
+                center: start,
+                radius: start.distance(end),
+            }),
+            EditorTool::Segment | EditorTool::Circle | EditorTool::Move => None,
+        }
+    }
+
+    pub fn delete_at(&mut self, pos: Pos2) {
+        if let Some(idx) = self.wall_at(pos) {
+            self.map.walls.remove(idx);
+            self.update_spawn_positions();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.map.walls.clear();
+        self.map.portals.clear();
+        self.update_spawn_positions();
+    }
+
+    /// The topmost wall at `pos`.
+    fn wall_at(&self, pos: Pos2) -> Option<usize> {
+        self.map
+            .walls
+            .iter()
+            .rposition(|w| world::intersects_walls(std::slice::from_ref(w), pos, SELECTION_DIST))
+    }
+
+    fn update_spawn_positions(&mut self) {
+        self.spawn_positions = spawn_positions(&self.map);
+    }
+
+    /// Checks that there is enough room to spawn `num_players`.
+    pub fn validate(&self, num_players: usize) -> Result<(), String> {
+        if self.map.name.trim().is_empty() {
+            return Err("the map needs a name".into());
+        }
+        if self.spawn_positions.len() < num_players {
+            return Err(format!(
+                "not enough spawn area for {num_players} players, only {} fit",
+                self.spawn_positions.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn translate_wall(wall: &mut Wall, delta: Vec2) {
+    match wall {
+        Wall::Segment { start, end, .. } => {
+            *start += delta;
+            *end += delta;
+        }
+        Wall::Arc { center, .. } | Wall::Circle { center, .. } => {
+            *center += delta;
+        }
+    }
+}
+
+/// Greedily picks spawn positions on a grid, that are at least [`MIN_PLAYER_WALL_DIST`] away
+/// from walls and [`MIN_PLAYER_DIST`] away from each other.
+fn spawn_positions(map: &Map) -> Vec<Pos2> {
+    let area = Rect::from_min_max(
+        Pos2::new(MIN_PLAYER_WALL_DIST, MIN_PLAYER_WALL_DIST),
+        (WORLD_SIZE - Vec2::splat(MIN_PLAYER_WALL_DIST)).to_pos2(),
+    );
+    let num_x = (area.width() / SPAWN_GRID_SIZE) as usize;
+    let num_y = (area.height() / SPAWN_GRID_SIZE) as usize;
+
+    let mut positions: Vec<Pos2> = Vec::new();
+    for y in 0..=num_y {
+        for x in 0..=num_x {
+            let pos = area.min + SPAWN_GRID_SIZE * Vec2::new(x as f32, y as f32);
+            if world::intersects_walls(&map.walls, pos, MIN_PLAYER_WALL_DIST)
+                || map.portal_exit(pos).is_some()
+                || positions.iter().any(|p| p.distance(pos) < MIN_PLAYER_DIST)
+            {
+                continue;
+            }
+            positions.push(pos);
+        }
+    }
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Portal;
+    use crate::world::PLAYER_COLORS;
+
+    fn editor(walls: Vec<Wall>) -> MapEditor {
+        MapEditor::new(Map {
+            name: "Test".into(),
+            walls,
+            portals: Vec::new(),
+        })
+    }
+
+    fn assert_spread_out(positions: &[Pos2]) {
+        for (i, a) in positions.iter().enumerate() {
+            for b in positions[i + 1..].iter() {
+                assert!(
+                    a.distance(*b) >= MIN_PLAYER_DIST,
+                    "{a:?} and {b:?} are too close"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn empty_map_has_room() {
+        let editor = editor(Vec::new());
+        assert!(editor.spawn_positions.len() >= 8);
+        assert_spread_out(&editor.spawn_positions);
+        assert_eq!(editor.validate(8), Ok(()));
+    }
+
+    #[test]
+    fn empty_map_fits_all_players() {
+        let editor = editor(Vec::new());
+        assert_eq!(editor.validate(PLAYER_COLORS.len()), Ok(()));
+    }
+
+    #[test]
+    fn spawns_avoid_walls() {
+        let center = 0.5 * WORLD_SIZE.to_pos2();
+        let walls = vec![
+            Wall::Circle {
+                center,
+                radius: 100.0,
+            },
+            Wall::Segment {
+                start: Pos2::new(0.0, 200.0),
+                end: Pos2::new(WORLD_SIZE.x, 200.0),
+                thickness: DEFAULT_WALL_THICKNESS,
+            },
+        ];
+        let editor = editor(walls.clone());
+        assert!(!editor.spawn_positions.is_empty());
+        assert_spread_out(&editor.spawn_positions);
+        for pos in editor.spawn_positions.iter() {
+            assert!(
+                pos.distance(center) >= 100.0 + MIN_PLAYER_WALL_DIST,
+                "{pos:?}"
+            );
+            assert!((pos.y - 200.0).abs() >= MIN_PLAYER_WALL_DIST, "{pos:?}");
+            assert!(!world::intersects_walls(&walls, *pos, MIN_PLAYER_WALL_DIST));
+        }
+    }
+
+    #[test]
+    fn spawns_avoid_portals() {
+        let mut editor = editor(Vec::new());
+        let portal = Portal {
+            a: Pos2::new(150.0, 150.0),
+            b: Pos2::new(1130.0, 570.0),
+            radius: 30.0,
+        };
+        editor.map.portals.push(portal.clone());
+        editor.update_spawn_positions();
+        for pos in editor.spawn_positions.iter() {
+            assert!(pos.distance(portal.a) >= portal.radius, "{pos:?}");
+            assert!(pos.distance(portal.b) >= portal.radius, "{pos:?}");
+        }
+    }
+
+    #[test]
+    fn too_few_spawn_slots() {
+        // a large circle in the middle leaves only the corners of the spawn area
+        let center = 0.5 * WORLD_SIZE.to_pos2();
+        let mut editor = editor(vec![Wall::Circle {
+            center,
+            radius: 350.0,
+        }]);
+        let slots = editor.spawn_positions.len();
+        assert!((1..8).contains(&slots), "{slots} slots");
+        assert_eq!(editor.validate(slots), Ok(()));
+        assert_eq!(
+            editor.validate(slots + 1),
+            Err(format!(
+                "not enough spawn area for {} players, only {slots} fit",
+                slots + 1
+            ))
+        );
+
+        editor.clear();
+        assert_eq!(editor.validate(slots + 1), Ok(()));
+    }
+
+    #[test]
+    fn no_spawn_slots() {
+        let editor = editor(vec![Wall::Circle {
+            center: 0.5 * WORLD_SIZE.to_pos2(),
+            radius: WORLD_SIZE.x,
+        }]);
+        assert!(editor.spawn_positions.is_empty());
+        assert!(editor.validate(1).is_err());
+        assert_eq!(editor.validate(0), Ok(()));
+    }
+
+    #[test]
+    fn validate_requires_name() {
+        let mut editor = editor(Vec::new());
+        editor.map.name = "  ".into();
+        assert_eq!(editor.validate(2), Err("the map needs a name".into()));
+    }
+
+    #[test]
+    fn drawing_updates_spawns() {
+        let mut editor = editor(Vec::new());
+        let slots = editor.spawn_positions.len();
+        editor.tool = EditorTool::Circle;
+        let center = 0.5 * WORLD_SIZE.to_pos2();
+        editor.pointer_pressed(center);
+        editor.pointer_released(center + Vec2::new(300.0, 0.0));
+        assert_eq!(editor.map.walls.len(), 1);
+        assert!(editor.spawn_positions.len() < slots);
+
+        editor.delete_at(center + Vec2::new(299.0, 0.0));
+        assert!(editor.map.walls.is_empty());
+        assert_eq!(editor.spawn_positions.len(), slots);
+    }
+}
@@ -0,0 +1,1522 @@
+use std::borrow::Cow;
+use std::f32::consts::TAU;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
+use anyhow::Context as _;
+use egui::{Color32, FontDefinitions, Pos2, Vec2};
+use tiny_skia::{
+    FillRule, LineCap, LineJoin, Paint, PathBuilder, Pixmap, PremultipliedColorU8, Stroke,
+    StrokeDash, Transform,
+};
+
+use crate::app::{ColorExt, PORTAL_COLORS, WALL_COLOR, WORLD_BG_COLOR};
+use crate::map::{Map, Wall};
+use crate::world::{
+    GameState, ItemKind, TrailPattern, TrailSection, World, BASE_THICKNESS, ITEM_RADIUS, WORLD_SIZE,
+};
+
+/// The egui default font used for text in exported images.
+const FONT_NAME: &str = "Ubuntu-Light";
+const HUD_FONT_SIZE: f32 = 14.0;
+const NAME_FONT_SIZE: f32 = 12.0;
+/// The angle step at which arcs are approximated by straight lines in raster images.
+const ARC_STEP: f32 = 0.01 * TAU;
+/// The simulated time between two recorded frames of a round.
+const RECORDING_INTERVAL: Duration = Duration::from_nanos(1_000_000_000 / 60);
+pub const GIF_FPS: u32 = 25;
+pub const GIF_WIDTHS: [u32; 3] = [480, 640, 1280];
+pub const GIF_SPEEDS: [u32; 3] = [1, 2, 4];
+/// The time the last frame of a GIF is shown, in hundredths of a second.
+const GIF_END_DELAY: u16 = 300;
+/// The speed of the color quantization, from 1 (best quality) to 30 (fastest).
+const GIF_QUANTIZE_SPEED: i32 = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportSettings {
+    /// Save a screenshot whenever a round ends.
+    pub auto_screenshot: bool,
+    /// Also save screenshots as SVG, next to the PNG.
+    pub svg: bool,
+    /// The resolution of PNG screenshots relative to [`WORLD_SIZE`].
+    pub scale: f32,
+    /// The width of exported GIFs in pixels, the height follows the aspect ratio of the world.
+    pub gif_width: u32,
+    /// The playback speed of exported GIFs, relative to the speed of the game.
+    pub gif_speed: u32,
+    pub gif_style: GifStyle,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            auto_screenshot: false,
+            svg: false,
+            scale: 1.0,
+            gif_width: GIF_WIDTHS[1],
+            gif_speed: GIF_SPEEDS[0],
+            gif_style: GifStyle::default(),
+        }
+    }
+}
+
+impl ExportSettings {
+    pub fn next_gif_width(&mut self) {
+        self.gif_width = next_value(&GIF_WIDTHS, self.gif_width);
+    }
+
+    pub fn next_gif_speed(&mut self) {
+        self.gif_speed = next_value(&GIF_SPEEDS, self.gif_speed);
+    }
+}
+
+fn next_value(values: &[u32], current: u32) -> u32 {
+    let idx = values
+        .iter()
+        .position(|v| *v == current)
+        .map_or(0, |i| i + 1);
+    values[idx % values.len()]
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GifStyle {
+    #[default]
+    TrailsOnly,
+    /// Also draw items, player dots and the scores.
+    WithHud,
+}
+
+impl GifStyle {
+    pub const fn name(&self) -> &str {
+        match self {
+            Self::TrailsOnly => "trails only",
+            Self::WithHud => "with hud",
+        }
+    }
+
+    pub fn next(&mut self) {
+        *self = match self {
+            Self::TrailsOnly => Self::WithHud,
+            Self::WithHud => Self::TrailsOnly,
+        };
+    }
+}
+
+/// The growth of all trails during a round, sampled every [`RECORDING_INTERVAL`].
+#[derive(Clone, Default)]
+pub struct Recording {
+    /// The start of the recorded round.
+    round_start: Option<Duration>,
+    map: Map,
+    players: Vec<PlayerRecording>,
+    frames: Vec<RecordedFrame>,
+}
+
+#[derive(Clone)]
+struct PlayerRecording {
+    /// The [`Player::id`], players can be added and removed during a round.
+    ///
+    /// [`Player::id`]: crate::world::Player::id
+    id: u16,
+    name: String,
+    color: Color32,
+    pattern: TrailPattern,
+    /// All finished trail sections of the round, including ones that were cleared.
+    sections: Vec<TrailSection>,
+    /// The trail generation the recorded sections belong to.
+    trail_generation: u32,
+    /// The index of the first section of the current trail inside `sections`.
+    trail_start: usize,
+    /// The number of sections of the current trail that were already recorded.
+    recorded: usize,
+}
+
+#[derive(Clone)]
+struct RecordedFrame {
+    /// The time since the start of the round.
+    time: Duration,
+    players: Vec<RecordedPlayer>,
+    items: Vec<(Pos2, ItemKind)>,
+}
+
+#[derive(Clone)]
+struct RecordedPlayer {
+    /// The index of the player in [`Recording::players`].
+    player: usize,
+    /// The range of [`PlayerRecording::sections`] that is visible.
+    start: usize,
+    end: usize,
+    /// The last, still growing section of the trail.
+    active: Option<TrailSection>,
+    pos: Pos2,
+    thickness: f32,
+    crashed: bool,
+    score: u16,
+}
+
+impl Recording {
+    /// Samples the world if enough time has passed since the last frame, a new recording is
+    /// started whenever a new round starts.
+    pub fn record(&mut self, world: &World) {
+        let start = match world.state {
+            GameState::Running(start) | GameState::Stopped(start) => start,
+            GameState::Starting(_) | GameState::Paused(_) => return,
+        };
+        if self.round_start != Some(start) {
+            if matches!(world.state, GameState::Stopped(_)) {
+                return;
+            }
+            *self = Recording {
+                round_start: Some(start),
+                map: (*world.map).clone(),
+                ..Default::default()
+            };
+        }
+
+        let time = world.clock.now.saturating_sub(start);
+        if let Some(last) = self.frames.last() {
+            let interval = match world.state {
+                GameState::Stopped(_) => Duration::from_nanos(1),
+                _ => RECORDING_INTERVAL,
+            };
+            if time < last.time + interval {
+                return;
+            }
+        }
+
+        let palette = world.settings.palette;
+        let mut players = Vec::with_capacity(world.players.len());
+        for p in world.players.iter() {
+            let i = match self.players.iter().position(|r| r.id == p.id) {
+                Some(i) => i,
+                None => {
+                    self.players.push(PlayerRecording {
+                        id: p.id,
+                        name: p.name.clone(),
+                        color: p.color.color32(palette),
+                        pattern: p.pattern,
+                        sections: Vec::new(),
+                        trail_generation: p.trail_generation,
+                        trail_start: 0,
+                        recorded: 0,
+                    });
+                    self.players.len() - 1
+                }
+            };
+            let rec = &mut self.players[i];
+            if rec.trail_generation != p.trail_generation {
+                rec.trail_generation = p.trail_generation;
+                rec.trail_start = rec.sections.len();
+                rec.recorded = 0;
+            }
+
+            // only the last section of a trail is still growing
+            let finished = p.trail.len().saturating_sub(1);
+            if rec.recorded < finished {
+                rec.sections.extend(
+                    p.trail
+                        .iter_from(rec.recorded)
+                        .take(finished - rec.recorded)
+                        .cloned(),
+                );
+                rec.recorded = finished;
+            }
+
+            players.push(RecordedPlayer {
+                player: i,
+                start: rec.trail_start,
+                end: rec.sections.len(),
+                active: p.trail.last().cloned(),
+                pos: p.pos,
+                thickness: p.thickness(),
+                crashed: p.crashed,
+                score: p.score,
+            });
+        }
+
+        let items = world.items.iter().map(|i| (i.pos, i.kind)).collect();
+        self.frames.push(RecordedFrame {
+            time,
+            players,
+            items,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+/// A resolution independent drawing primitive, in world coordinates.
+#[derive(Clone, Debug)]
+enum Element {
+    Path {
+        start: Pos2,
+        segments: Vec<Segment>,
+        width: f32,
+        color: Color32,
+        dash: Option<Dash>,
+    },
+    Circle {
+        center: Pos2,
+        radius: f32,
+        fill: Option<Color32>,
+        stroke: Option<(f32, Color32)>,
+    },
+    /// Text anchored at its top left corner.
+    Text {
+        pos: Pos2,
+        size: f32,
+        text: String,
+        color: Color32,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Line(Pos2),
+    Arc {
+        center: Pos2,
+        radius: f32,
+        start_angle: f32,
+        /// The signed angle swept, positive angles are clockwise.
+        angle_delta: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Dash {
+    Dashed { dash: f32, gap: f32 },
+    Dotted { spacing: f32 },
+}
+
+impl Dash {
+    fn intervals(&self) -> [f32; 2] {
+        match self {
+            Dash::Dashed { dash, gap } => [*dash, *gap],
+            // an almost zero length dash with round caps is a dot
+            Dash::Dotted { spacing } => [0.01, *spacing],
+        }
+    }
+}
+
+/// Saves the arena as a PNG, and optionally an SVG, into the [`screenshots_dir`] without
+/// blocking the caller.
+pub fn spawn_save_screenshot(world: Arc<World>) {
+    std::thread::spawn(move || match save_screenshot(&world) {
+        Ok(path) => tracing::info!("Saved screenshot to {}", path.display()),
+        Err(e) => tracing::error!("Error saving screenshot:\n{e:#}"),
+    });
+}
+
+/// Saves the recorded round as an animated GIF into the [`screenshots_dir`] without blocking the
+/// caller.
+pub fn spawn_save_gif(recording: Recording, settings: ExportSettings) {
+    std::thread::spawn(move || match save_gif(&recording, &settings) {
+        Ok(path) => tracing::info!("Saved GIF to {}", path.display()),
+        Err(e) => tracing::error!("Error saving GIF:\n{e:#}"),
+    });
+}
+
+/// Saves the arena as a PNG, and optionally an SVG, into the [`screenshots_dir`] and returns the
+/// path of the PNG.
+pub fn save_screenshot(world: &World) -> anyhow::Result<PathBuf> {
+    let font = load_font()?;
+    let elements = scene(world, &font);
+    let settings = &world.settings.export;
+
+    let path = export_path("png")?;
+    let pixmap = render_png(&elements, &font, settings.scale)?;
+    pixmap.save_png(&path)?;
+
+    if settings.svg {
+        let svg = render_svg(&elements)?;
+        std::fs::write(path.with_extension("svg"), svg)?;
+    }
+
+    Ok(path)
+}
+
+/// The directory screenshots and GIFs are saved to, inside the data directory of the user, e.g.
+/// `~/.local/share/curvefever/screenshots` on Linux.
+pub fn screenshots_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("curvefever").join("screenshots"))
+}
+
+fn export_path(extension: &str) -> anyhow::Result<PathBuf> {
+    let dir = screenshots_dir().context("no data directory to save screenshots to")?;
+    std::fs::create_dir_all(&dir)?;
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let path = dir
+        .join(format!("curvefever-{millis}"))
+        .with_extension(extension);
+    Ok(path)
+}
+
+/// Saves the recorded round as an animated GIF into the [`screenshots_dir`] and returns its path.
+pub fn save_gif(recording: &Recording, settings: &ExportSettings) -> anyhow::Result<PathBuf> {
+    if recording.is_empty() {
+        anyhow::bail!("no round was recorded");
+    }
+    let path = export_path("gif")?;
+    let file = File::create(&path)?;
+    encode_gif(recording, settings, file)?;
+    Ok(path)
+}
+
+/// Rasterizes the recorded round frame by frame and encodes it as a GIF, finished trail sections
+/// are only drawn once onto a base image that is shared by all following frames.
+fn encode_gif(
+    recording: &Recording,
+    settings: &ExportSettings,
+    writer: impl Write,
+) -> anyhow::Result<()> {
+    let Some(last_frame) = recording.frames.last() else {
+        anyhow::bail!("no round was recorded");
+    };
+
+    let font = load_font()?;
+    let scale = settings.gif_width as f32 / WORLD_SIZE.x;
+    let width = settings.gif_width;
+    let height = (scale * WORLD_SIZE.y).round() as u32;
+    let gif_width = u16::try_from(width).context("GIF width too large")?;
+    let gif_height = u16::try_from(height).context("GIF height too large")?;
+
+    let mut static_elements = Vec::new();
+    map_elements(&mut static_elements, &recording.map);
+    let mut base = Pixmap::new(width, height).context("invalid GIF size")?;
+    // the range of sections of each player recording that is drawn onto the base image
+    let mut drawn: Vec<Option<(usize, usize)>> = vec![None; recording.players.len()];
+    base.fill(skia_color(WORLD_BG_COLOR));
+    rasterize(&mut base, &static_elements, &font, scale);
+
+    let mut encoder = gif::Encoder::new(writer, gif_width, gif_height, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    let frame_time = settings.gif_speed * Duration::from_secs(1) / GIF_FPS;
+    let mut frame_idx = 0;
+    let mut time = Duration::ZERO;
+    loop {
+        let is_last = time >= last_frame.time;
+        while frame_idx + 1 < recording.frames.len() && recording.frames[frame_idx + 1].time <= time
+        {
+            frame_idx += 1;
+        }
+        let frame = &recording.frames[frame_idx];
+
+        // redraw the base image if a trail was cleared or its player removed
+        let stale = drawn.iter().enumerate().any(|(i, d)| {
+            let Some((start, _)) = d else {
+                return false;
+            };
+            let p = frame.players.iter().find(|p| p.player == i);
+            p.is_none_or(|p| p.start != *start)
+        });
+        if stale {
+            base.fill(skia_color(WORLD_BG_COLOR));
+            rasterize(&mut base, &static_elements, &font, scale);
+            drawn.fill(None);
+        }
+
+        // draw newly finished sections onto the base image
+        let mut elements = Vec::new();
+        for p in frame.players.iter() {
+            let rec = &recording.players[p.player];
+            let (_, end) = drawn[p.player].get_or_insert((p.start, p.start));
+            for (start, segments, thickness) in trail_runs(&rec.sections[*end..p.end]) {
+                trail_elements(
+                    &mut elements,
+                    start,
+                    segments,
+                    thickness,
+                    rec.color,
+                    rec.pattern,
+                );
+            }
+            *end = p.end;
+        }
+        rasterize(&mut base, &elements, &font, scale);
+
+        let mut pixmap = base.clone();
+        let mut elements = Vec::new();
+        for p in frame.players.iter() {
+            let rec = &recording.players[p.player];
+            let active = p.active.as_slice();
+            for (start, segments, thickness) in trail_runs(active) {
+                trail_elements(
+                    &mut elements,
+                    start,
+                    segments,
+                    thickness,
+                    rec.color,
+                    rec.pattern,
+                );
+            }
+        }
+        if settings.gif_style == GifStyle::WithHud {
+            hud_elements(&mut elements, recording, frame, &font);
+        }
+        rasterize(&mut pixmap, &elements, &font, scale);
+
+        // the world background is opaque, so the premultiplied data is plain RGBA
+        let mut rgba = pixmap.take();
+        let mut gif_frame =
+            gif::Frame::from_rgba_speed(gif_width, gif_height, &mut rgba, GIF_QUANTIZE_SPEED);
+        gif_frame.delay = if is_last {
+            GIF_END_DELAY
+        } else {
+            (100 / GIF_FPS) as u16
+        };
+        encoder.write_frame(&gif_frame)?;
+
+        if is_last {
+            break;
+        }
+        time += frame_time;
+    }
+
+    Ok(())
+}
+
+fn hud_elements(
+    elements: &mut Vec<Element>,
+    recording: &Recording,
+    frame: &RecordedFrame,
+    font: &FontArc,
+) {
+    for (pos, kind) in frame.items.iter() {
+        elements.push(Element::Circle {
+            center: *pos,
+            radius: ITEM_RADIUS,
+            fill: Some(kind.color32()),
+            stroke: Some((0.18 * ITEM_RADIUS, Color32::from_gray(16))),
+        });
+    }
+
+    let score_color = Color32::from_gray(160);
+    for (index, p) in frame.players.iter().enumerate() {
+        let rec = &recording.players[p.player];
+        let gap = p.active.as_ref().is_none_or(|s| s.gap());
+        if !p.crashed && gap {
+            elements.push(Element::Circle {
+                center: p.pos,
+                radius: 0.5 * p.thickness,
+                fill: Some(rec.color.with_alpha(80)),
+                stroke: None,
+            });
+        }
+
+        let pos = Pos2::new(20.0, 20.0 + index as f32 * 30.0);
+        let width = text_width(font, HUD_FONT_SIZE, &rec.name);
+        elements.push(Element::Text {
+            pos,
+            size: HUD_FONT_SIZE,
+            text: rec.name.clone(),
+            color: rec.color,
+        });
+        elements.push(Element::Text {
+            pos: pos + Vec2::new(width + 5.0, 0.0),
+            size: HUD_FONT_SIZE,
+            text: p.score.to_string(),
+            color: score_color,
+        });
+    }
+}
+
+fn load_font() -> anyhow::Result<FontArc> {
+    let mut fonts = FontDefinitions::default();
+    let data = fonts
+        .font_data
+        .remove(FONT_NAME)
+        .context("missing default font")?;
+    let font = match data.font {
+        Cow::Borrowed(bytes) => FontArc::try_from_slice(bytes)?,
+        Cow::Owned(bytes) => FontArc::try_from_vec(bytes)?,
+    };
+    Ok(font)
+}
+
+fn text_width(font: &FontArc, size: f32, text: &str) -> f32 {
+    let font = font.as_scaled(PxScale::from(size));
+    let mut width = 0.0;
+    let mut prev = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(prev) = prev {
+            width += font.kern(prev, id);
+        }
+        width += font.h_advance(id);
+        prev = Some(id);
+    }
+    width
+}
+
+/// The final arena of a round, like it is drawn by the app, with the score overlay.
+fn scene(world: &World, font: &FontArc) -> Vec<Element> {
+    let mut elements = Vec::new();
+    let palette = world.settings.palette;
+
+    map_elements(&mut elements, &world.map);
+
+    for item in world.items.iter() {
+        elements.push(Element::Circle {
+            center: item.pos,
+            radius: ITEM_RADIUS,
+            fill: Some(item.kind.color32()),
+            stroke: Some((0.18 * ITEM_RADIUS, Color32::from_gray(16))),
+        });
+    }
+
+    for p in world.players.iter() {
+        let color = p.color.color32(palette);
+        for (start, segments, thickness) in trail_runs(p.trail.iter()) {
+            trail_elements(&mut elements, start, segments, thickness, color, p.pattern);
+        }
+
+        if !p.crashed && (p.gap() || p.trail.is_empty()) {
+            let alpha = if p.gap() { 80 } else { 255 };
+            elements.push(Element::Circle {
+                center: p.pos,
+                radius: 0.5 * p.thickness(),
+                fill: Some(color.with_alpha(alpha)),
+                stroke: None,
+            });
+        }
+
+        // name label above the final position
+        let width = text_width(font, NAME_FONT_SIZE, &p.name);
+        elements.push(Element::Text {
+            pos: p.pos - Vec2::new(0.5 * width, p.thickness() + NAME_FONT_SIZE + 4.0),
+            size: NAME_FONT_SIZE,
+            text: p.name.clone(),
+            color,
+        });
+    }
+
+    // score overlay, laid out like the hud
+    let score_color = Color32::from_gray(160);
+    for (index, p) in world.players.iter().enumerate() {
+        let pos = Pos2::new(20.0, 20.0 + index as f32 * 30.0);
+        let width = text_width(font, HUD_FONT_SIZE, &p.name);
+        elements.push(Element::Text {
+            pos,
+            size: HUD_FONT_SIZE,
+            text: p.name.clone(),
+            color: p.color.color32(palette),
+        });
+        elements.push(Element::Text {
+            pos: pos + Vec2::new(width + 5.0, 0.0),
+            size: HUD_FONT_SIZE,
+            text: p.score.to_string(),
+            color: score_color,
+        });
+    }
+
+    elements
+}
+
+fn map_elements(elements: &mut Vec<Element>, map: &Map) {
+    for w in map.walls.iter() {
+        match w {
+            Wall::Segment {
+                start,
+                end,
+                thickness,
+            } => elements.push(Element::Path {
+                start: *start,
+                segments: vec![Segment::Line(*end)],
+                width: *thickness,
+                color: WALL_COLOR,
+                dash: None,
+            }),
+            Wall::Arc {
+                center,
+                radius,
+                start_angle,
+                end_angle,
+                thickness,
+            } => elements.push(Element::Path {
+                start: *center + *radius * Vec2::angled(*start_angle),
+                segments: vec![Segment::Arc {
+                    center: *center,
+                    radius: *radius,
+                    start_angle: *start_angle,
+                    angle_delta: (end_angle - start_angle).rem_euclid(TAU),
+                }],
+                width: *thickness,
+                color: WALL_COLOR,
+                dash: None,
+            }),
+            Wall::Circle { center, radius } => elements.push(Element::Circle {
+                center: *center,
+                radius: *radius,
+                fill: Some(WALL_COLOR),
+                stroke: None,
+            }),
+        }
+    }
+
+    for (i, p) in map.portals.iter().enumerate() {
+        let color = PORTAL_COLORS[i % PORTAL_COLORS.len()];
+        for center in [p.a, p.b] {
+            elements.push(Element::Circle {
+                center,
+                radius: p.radius,
+                fill: Some(color.with_alpha(40)),
+                stroke: Some((3.0, color)),
+            });
+        }
+    }
+}
+
+/// Splits a trail into continuous runs, separated by gaps and changes in thickness.
+fn trail_runs<'a>(
+    trail: impl IntoIterator<Item = &'a TrailSection>,
+) -> Vec<(Pos2, Vec<Segment>, f32)> {
+    let mut runs = Vec::new();
+    let mut current: Option<(Pos2, Vec<Segment>, f32)> = None;
+    let mut last_pos = None;
+    for s in trail {
+        if s.gap() {
+            runs.extend(current.take());
+            last_pos = None;
+            continue;
+        }
+
+        if let Some((_, _, thickness)) = &current {
+            if *thickness != s.thickness() || last_pos != Some(s.start_pos()) {
+                runs.extend(current.take());
+            }
+        }
+
+        let (_, segments, _) =
+            current.get_or_insert_with(|| (s.start_pos(), Vec::new(), s.thickness()));
+        let segment = match s {
+            TrailSection::Straight(s) => Segment::Line(s.end),
+            TrailSection::Arc(s) => Segment::Arc {
+                center: s.center_pos(),
+                radius: s.radius,
+                start_angle: s.arc_start_angle(),
+                angle_delta: s.angle_delta(),
+            },
+        };
+        segments.push(segment);
+        last_pos = Some(s.end_pos());
+    }
+    runs.extend(current);
+    runs
+}
+
+fn trail_elements(
+    elements: &mut Vec<Element>,
+    start: Pos2,
+    segments: Vec<Segment>,
+    thickness: f32,
+    color: Color32,
+    pattern: TrailPattern,
+) {
+    let dash = match pattern {
+        TrailPattern::Solid | TrailPattern::Outlined => None,
+        TrailPattern::Dashed => {
+            let dash = 3.0 * thickness.max(BASE_THICKNESS);
+            Some(Dash::Dashed {
+                dash,
+                gap: 0.5 * dash,
+            })
+        }
+        TrailPattern::Dotted => Some(Dash::Dotted {
+            spacing: 1.5 * thickness.max(BASE_THICKNESS),
+        }),
+    };
+
+    if pattern == TrailPattern::Outlined {
+        elements.push(Element::Path {
+            start,
+            segments: segments.clone(),
+            width: thickness,
+            color,
+            dash,
+        });
+        elements.push(Element::Path {
+            start,
+            segments,
+            width: 0.4 * thickness,
+            color: WORLD_BG_COLOR,
+            dash,
+        });
+    } else {
+        elements.push(Element::Path {
+            start,
+            segments,
+            width: thickness,
+            color,
+            dash,
+        });
+    }
+}
+
+fn render_png(elements: &[Element], font: &FontArc, scale: f32) -> anyhow::Result<Pixmap> {
+    let size = scale * WORLD_SIZE;
+    let mut pixmap = Pixmap::new(size.x.round() as u32, size.y.round() as u32)
+        .context("invalid screenshot size")?;
+    pixmap.fill(skia_color(WORLD_BG_COLOR));
+    rasterize(&mut pixmap, elements, font, scale);
+    Ok(pixmap)
+}
+
+/// Draws the elements on top of the pixmap.
+fn rasterize(pixmap: &mut Pixmap, elements: &[Element], font: &FontArc, scale: f32) {
+    let transform = Transform::from_scale(scale, scale);
+
+    for e in elements.iter() {
+        match e {
+            Element::Path {
+                start,
+                segments,
+                width,
+                color,
+                dash,
+            } => {
+                let mut pb = PathBuilder::new();
+                pb.move_to(start.x, start.y);
+                for s in segments.iter() {
+                    match s {
+                        Segment::Line(end) => pb.line_to(end.x, end.y),
+                        Segment::Arc {
+                            center,
+                            radius,
+                            start_angle,
+                            angle_delta,
+                        } => {
+                            let steps = (angle_delta.abs() / ARC_STEP).ceil().max(1.0) as u32;
+                            for i in 1..=steps {
+                                let angle = start_angle + angle_delta * i as f32 / steps as f32;
+                                let pos = *center + *radius * Vec2::angled(angle);
+                                pb.line_to(pos.x, pos.y);
+                            }
+                        }
+                    }
+                }
+                let Some(path) = pb.finish() else {
+                    continue;
+                };
+
+                let stroke = Stroke {
+                    width: *width,
+                    line_cap: LineCap::Round,
+                    line_join: LineJoin::Round,
+                    dash: dash.and_then(|d| StrokeDash::new(d.intervals().to_vec(), 0.0)),
+                    ..Default::default()
+                };
+                pixmap.stroke_path(&path, &paint(*color), &stroke, transform, None);
+            }
+            Element::Circle {
+                center,
+                radius,
+                fill,
+                stroke,
+            } => {
+                let Some(path) = PathBuilder::from_circle(center.x, center.y, *radius) else {
+                    continue;
+                };
+                if let Some(color) = fill {
+                    pixmap.fill_path(&path, &paint(*color), FillRule::Winding, transform, None);
+                }
+                if let Some((width, color)) = stroke {
+                    let stroke = Stroke {
+                        width: *width,
+                        ..Default::default()
+                    };
+                    pixmap.stroke_path(&path, &paint(*color), &stroke, transform, None);
+                }
+            }
+            Element::Text {
+                pos,
+                size,
+                text,
+                color,
+            } => {
+                draw_text(pixmap, font, scale * *pos, scale * *size, text, *color);
+            }
+        }
+    }
+}
+
+fn skia_color(color: Color32) -> tiny_skia::Color {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    tiny_skia::Color::from_rgba8(r, g, b, a)
+}
+
+fn paint(color: Color32) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color(skia_color(color));
+    paint.anti_alias = true;
+    paint
+}
+
+fn draw_text(
+    pixmap: &mut Pixmap,
+    font: &FontArc,
+    pos: Pos2,
+    size: f32,
+    text: &str,
+    color: Color32,
+) {
+    let scaled_font = font.as_scaled(PxScale::from(size));
+    let baseline = pos.y + scaled_font.ascent();
+    let mut x = pos.x;
+    let mut prev = None;
+    for c in text.chars() {
+        let id = scaled_font.glyph_id(c);
+        if let Some(prev) = prev {
+            x += scaled_font.kern(prev, id);
+        }
+        let glyph = id.with_scale_and_position(size, ab_glyph::point(x, baseline));
+        x += scaled_font.h_advance(id);
+        prev = Some(id);
+
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i32 + gx as i32;
+            let py = bounds.min.y as i32 + gy as i32;
+            blend_pixel(pixmap, px, py, color, coverage);
+        });
+    }
+}
+
+fn blend_pixel(pixmap: &mut Pixmap, x: i32, y: i32, color: Color32, coverage: f32) {
+    if x < 0 || y < 0 || x >= pixmap.width() as i32 || y >= pixmap.height() as i32 {
+        return;
+    }
+    let idx = y as usize * pixmap.width() as usize + x as usize;
+    let dst = pixmap.pixels()[idx];
+
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    let alpha = coverage.clamp(0.0, 1.0) * a as f32 / 255.0;
+    let blend = |src: u8, dst: u8| (src as f32 * alpha + dst as f32 * (1.0 - alpha)).round() as u8;
+    let out_a = blend(255, dst.alpha());
+    let out = PremultipliedColorU8::from_rgba(
+        blend(r, dst.red()).min(out_a),
+        blend(g, dst.green()).min(out_a),
+        blend(b, dst.blue()).min(out_a),
+        out_a,
+    );
+    if let Some(out) = out {
+        pixmap.pixels_mut()[idx] = out;
+    }
+}
+
+fn render_svg(elements: &[Element]) -> Result<String, std::fmt::Error> {
+    let mut svg = String::new();
+    let size = WORLD_SIZE;
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = size.x,
+        h = size.y,
+    )?;
+    writeln!(
+        svg,
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        svg_color(WORLD_BG_COLOR)
+    )?;
+
+    for e in elements.iter() {
+        match e {
+            Element::Path {
+                start,
+                segments,
+                width,
+                color,
+                dash,
+            } => {
+                let mut d = format!("M {} {}", start.x, start.y);
+                for s in segments.iter() {
+                    match s {
+                        Segment::Line(end) => write!(d, " L {} {}", end.x, end.y)?,
+                        Segment::Arc {
+                            center,
+                            radius,
+                            start_angle,
+                            angle_delta,
+                        } => {
+                            // split into two halves, so no half ever needs the large arc flag
+                            let sweep = (*angle_delta > 0.0) as u8;
+                            for i in 1..=2 {
+                                let angle = start_angle + 0.5 * i as f32 * angle_delta;
+                                let end = *center + *radius * Vec2::angled(angle);
+                                write!(d, " A {radius} {radius} 0 0 {sweep} {} {}", end.x, end.y)?;
+                            }
+                        }
+                    }
+                }
+
+                write!(
+                    svg,
+                    r#"<path d="{d}" fill="none" stroke="{}" stroke-opacity="{}" stroke-width="{width}" stroke-linecap="round" stroke-linejoin="round""#,
+                    svg_color(*color),
+                    svg_opacity(*color),
+                )?;
+                if let Some(dash) = dash {
+                    let [dash, gap] = dash.intervals();
+                    write!(svg, r#" stroke-dasharray="{dash} {gap}""#)?;
+                }
+                writeln!(svg, "/>")?;
+            }
+            Element::Circle {
+                center,
+                radius,
+                fill,
+                stroke,
+            } => {
+                write!(
+                    svg,
+                    r#"<circle cx="{}" cy="{}" r="{radius}""#,
+                    center.x, center.y
+                )?;
+                match fill {
+                    Some(color) => write!(
+                        svg,
+                        r#" fill="{}" fill-opacity="{}""#,
+                        svg_color(*color),
+                        svg_opacity(*color)
+                    )?,
+                    None => write!(svg, r#" fill="none""#)?,
+                }
+                if let Some((width, color)) = stroke {
+                    write!(
+                        svg,
+                        r#" stroke="{}" stroke-opacity="{}" stroke-width="{width}""#,
+                        svg_color(*color),
+                        svg_opacity(*color)
+                    )?;
+                }
+                writeln!(svg, "/>")?;
+            }
+            Element::Text {
+                pos,
+                size,
+                text,
+                color,
+            } => {
+                writeln!(
+                    svg,
+                    r#"<text x="{}" y="{}" font-family="sans-serif" font-size="{size}" dominant-baseline="hanging" fill="{}" fill-opacity="{}">{}</text>"#,
+                    pos.x,
+                    pos.y,
+                    svg_color(*color),
+                    svg_opacity(*color),
+                    escape_xml(text),
+                )?;
+            }
+        }
+    }
+
+    writeln!(svg, "</svg>")?;
+    Ok(svg)
+}
+
+fn svg_color(color: Color32) -> String {
+    let [r, g, b, _] = color.to_srgba_unmultiplied();
+    format!("rgb({r},{g},{b})")
+}
+
+fn svg_opacity(color: Color32) -> f32 {
+    color.to_srgba_unmultiplied()[3] as f32 / 255.0
+}
+
+fn escape_xml(text: &str) -> Cow<'_, str> {
+    if !text.contains(['<', '>', '&', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use egui::pos2;
+
+    use super::*;
+    use crate::world::{ArcTrailSection, StraightTrailSection, TurnDirection};
+
+    fn straight(start: Pos2, end: Pos2, gap: bool, thickness: f32) -> TrailSection {
+        TrailSection::Straight(StraightTrailSection::new(start, gap, thickness, end))
+    }
+
+    /// A right turn of three quarters of a circle, starting at `start` heading right.
+    fn arc(start: Pos2) -> ArcTrailSection {
+        ArcTrailSection::new(
+            start,
+            false,
+            BASE_THICKNESS,
+            TurnDirection::Right,
+            20.0,
+            0.0,
+            1.5 * PI,
+        )
+    }
+
+    fn small_trail() -> Vec<TrailSection> {
+        let arc = arc(pos2(30.0, 10.0));
+        let arc_end = arc.end_pos();
+        vec![
+            straight(pos2(10.0, 10.0), pos2(20.0, 10.0), false, BASE_THICKNESS),
+            straight(pos2(20.0, 10.0), pos2(30.0, 10.0), false, BASE_THICKNESS),
+            TrailSection::Arc(arc),
+            straight(
+                arc_end,
+                arc_end + Vec2::new(0.0, -10.0),
+                true,
+                BASE_THICKNESS,
+            ),
+            straight(pos2(100.0, 0.0), pos2(100.0, 10.0), false, BASE_THICKNESS),
+            straight(
+                pos2(100.0, 10.0),
+                pos2(100.0, 20.0),
+                false,
+                2.0 * BASE_THICKNESS,
+            ),
+            // moved through a portal
+            straight(
+                pos2(200.0, 200.0),
+                pos2(210.0, 200.0),
+                false,
+                2.0 * BASE_THICKNESS,
+            ),
+        ]
+    }
+
+    fn svg_paths(svg: &str) -> Vec<&str> {
+        svg.lines()
+            .filter(|l| l.starts_with("<path"))
+            .map(|l| {
+                let start = l.find("d=\"").unwrap() + 3;
+                let end = start + l[start..].find('"').unwrap();
+                &l[start..end]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trail_runs_split() {
+        let runs = trail_runs(small_trail().iter());
+        let lens: Vec<_> = runs.iter().map(|(_, s, _)| s.len()).collect();
+        assert_eq!(lens, [3, 1, 1, 1]);
+
+        let starts: Vec<_> = runs.iter().map(|(p, _, _)| *p).collect();
+        assert_eq!(
+            starts,
+            [
+                pos2(10.0, 10.0),
+                pos2(100.0, 0.0),
+                pos2(100.0, 10.0),
+                pos2(200.0, 200.0)
+            ]
+        );
+        assert_eq!(runs[2].2, 2.0 * BASE_THICKNESS);
+        assert!(matches!(runs[0].1[2], Segment::Arc { .. }));
+    }
+
+    #[test]
+    fn trail_runs_only_gaps() {
+        let trail = [straight(
+            pos2(0.0, 0.0),
+            pos2(10.0, 0.0),
+            true,
+            BASE_THICKNESS,
+        )];
+        assert!(trail_runs(trail.iter()).is_empty());
+        assert!(trail_runs([].iter()).is_empty());
+    }
+
+    #[test]
+    fn escape_xml_text() {
+        assert!(matches!(escape_xml("Player 1"), Cow::Borrowed("Player 1")));
+        assert_eq!(
+            escape_xml(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        assert_eq!(escape_xml("ä & ö"), "ä &amp; ö");
+    }
+
+    #[test]
+    fn svg_small_world() {
+        let mut elements = Vec::new();
+        for (start, segments, thickness) in trail_runs(small_trail().iter()) {
+            trail_elements(
+                &mut elements,
+                start,
+                segments,
+                thickness,
+                Color32::RED,
+                TrailPattern::Solid,
+            );
+        }
+        elements.push(Element::Text {
+            pos: pos2(0.0, 0.0),
+            size: NAME_FONT_SIZE,
+            text: "<b>&".into(),
+            color: Color32::WHITE,
+        });
+        let svg = render_svg(&elements).unwrap();
+
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains(">&lt;b&gt;&amp;</text>"));
+        let paths = svg_paths(&svg);
+        assert_eq!(paths.len(), 4);
+        assert_eq!(paths[0].matches(" L ").count(), 2);
+        assert_eq!(paths[0].matches(" A ").count(), 2);
+    }
+
+    #[test]
+    fn svg_outlined_trails_are_drawn_twice() {
+        let mut elements = Vec::new();
+        for (start, segments, thickness) in trail_runs(small_trail().iter()) {
+            trail_elements(
+                &mut elements,
+                start,
+                segments,
+                thickness,
+                Color32::RED,
+                TrailPattern::Outlined,
+            );
+        }
+        let svg = render_svg(&elements).unwrap();
+        assert_eq!(svg_paths(&svg).len(), 8);
+    }
+
+    #[test]
+    fn svg_arc_halves() {
+        for (dir, end_angle, sweep) in [
+            (TurnDirection::Right, 1.5 * PI, 1.0),
+            (TurnDirection::Left, -1.5 * PI, 0.0),
+        ] {
+            let arc = ArcTrailSection {
+                dir,
+                player_end_angle: end_angle,
+                ..arc(pos2(300.0, 300.0))
+            };
+            let runs = trail_runs([TrailSection::Arc(arc.clone())].iter());
+            let mut elements = Vec::new();
+            for (start, segments, thickness) in runs {
+                trail_elements(
+                    &mut elements,
+                    start,
+                    segments,
+                    thickness,
+                    Color32::RED,
+                    TrailPattern::Solid,
+                );
+            }
+            let svg = render_svg(&elements).unwrap();
+            let paths = svg_paths(&svg);
+            assert_eq!(paths.len(), 1);
+
+            // two halves, neither of them with the large arc flag set
+            let halves: Vec<Vec<f32>> = paths[0]
+                .split(" A ")
+                .skip(1)
+                .map(|a| a.split(' ').map(|v| v.parse().unwrap()).collect())
+                .collect();
+            assert_eq!(halves.len(), 2);
+            for h in halves.iter() {
+                assert_eq!(h[..5], [20.0, 20.0, 0.0, 0.0, sweep]);
+            }
+
+            // the first half ends halfway around the arc, the second one at its end
+            let center = arc.center_pos();
+            let radius = |h: &[f32]| pos2(h[5], h[6]).distance(center);
+            assert!((radius(&halves[0]) - 20.0).abs() < 1e-3);
+            let end = pos2(halves[1][5], halves[1][6]);
+            assert!(end.distance(arc.end_pos()) < 1e-3, "{end:?}");
+            let start = arc.start_pos;
+            let mid = pos2(halves[0][5], halves[0][6]);
+            assert!((mid.distance(start) - mid.distance(end)).abs() < 1e-3);
+        }
+    }
+
+    /// A running round without any trails or items.
+    fn recorded_world() -> World {
+        let mut world = World::new();
+        world.state = GameState::Running(Duration::ZERO);
+        world.items.clear();
+        for p in world.players.iter_mut() {
+            p.trail.clear();
+        }
+        world
+    }
+
+    /// Adds a straight section to the trail of every player, each player on their own row.
+    fn grow_trails(world: &mut World, thickness: f32) {
+        for (i, p) in world.players.iter_mut().enumerate() {
+            let y = 100.0 * (i + 1) as f32;
+            let x = 100.0 + 100.0 * p.trail.len() as f32;
+            p.trail
+                .push(straight(pos2(x, y), pos2(x + 100.0, y), false, thickness));
+        }
+    }
+
+    fn record_at(recording: &mut Recording, world: &mut World, time: Duration) {
+        world.clock.now = time;
+        recording.record(world);
+    }
+
+    #[test]
+    fn recording_frame_timing() {
+        let mut world = recorded_world();
+        let mut recording = Recording::default();
+
+        world.state = GameState::Starting(Duration::ZERO);
+        record_at(&mut recording, &mut world, Duration::ZERO);
+        assert!(recording.is_empty());
+
+        world.state = GameState::Running(Duration::ZERO);
+        record_at(&mut recording, &mut world, Duration::ZERO);
+        record_at(&mut recording, &mut world, RECORDING_INTERVAL / 2);
+        assert_eq!(recording.frames.len(), 1);
+        record_at(&mut recording, &mut world, RECORDING_INTERVAL);
+        assert_eq!(recording.frames.len(), 2);
+
+        world.state = GameState::Paused(Duration::ZERO);
+        record_at(&mut recording, &mut world, 3 * RECORDING_INTERVAL);
+        assert_eq!(recording.frames.len(), 2);
+
+        // the end of a round is always recorded, but only once
+        world.state = GameState::Stopped(Duration::ZERO);
+        let end = RECORDING_INTERVAL + Duration::from_millis(1);
+        record_at(&mut recording, &mut world, end);
+        record_at(&mut recording, &mut world, end);
+        let times: Vec<_> = recording.frames.iter().map(|f| f.time).collect();
+        assert_eq!(times, [Duration::ZERO, RECORDING_INTERVAL, end]);
+
+        // a stopped round doesn't start a new recording
+        let start = Duration::from_secs(10);
+        world.state = GameState::Stopped(start);
+        record_at(&mut recording, &mut world, start);
+        assert_eq!(recording.frames.len(), 3);
+
+        world.state = GameState::Running(start);
+        record_at(&mut recording, &mut world, start + RECORDING_INTERVAL);
+        assert_eq!(recording.round_start, Some(start));
+        let times: Vec<_> = recording.frames.iter().map(|f| f.time).collect();
+        assert_eq!(times, [RECORDING_INTERVAL]);
+    }
+
+    #[test]
+    fn recording_rebases_cleared_trails() {
+        let mut world = recorded_world();
+        let mut recording = Recording::default();
+        for _ in 0..3 {
+            grow_trails(&mut world, BASE_THICKNESS);
+        }
+        record_at(&mut recording, &mut world, Duration::ZERO);
+
+        // only finished sections are recorded, the last one is still growing
+        let frame = &recording.frames[0].players[0];
+        assert_eq!((frame.start, frame.end), (0, 2));
+        assert_eq!(frame.active.as_ref(), world.players[0].trail.last());
+        assert_eq!(recording.players[0].sections.len(), 2);
+
+        grow_trails(&mut world, BASE_THICKNESS);
+        record_at(&mut recording, &mut world, RECORDING_INTERVAL);
+        let frame = &recording.frames[1].players[0];
+        assert_eq!((frame.start, frame.end), (0, 3));
+
+        let p = &mut world.players[0];
+        p.trail.clear();
+        p.trail_generation += 1;
+        grow_trails(&mut world, BASE_THICKNESS);
+        grow_trails(&mut world, BASE_THICKNESS);
+        record_at(&mut recording, &mut world, 2 * RECORDING_INTERVAL);
+
+        // cleared sections are kept for earlier frames
+        let frames = &recording.frames[2].players;
+        assert_eq!((frames[0].start, frames[0].end), (3, 4));
+        assert_eq!(recording.players[0].sections.len(), 4);
+        assert_eq!(
+            recording.players[0].sections[3],
+            *world.players[0].trail.get(0).unwrap()
+        );
+        assert_eq!((frames[1].start, frames[1].end), (0, 5));
+        assert_eq!(recording.frames[0].players[0].end, 2);
+    }
+
+    #[test]
+    fn recording_follows_player_ids() {
+        let mut world = recorded_world();
+        world.add_player().unwrap();
+        let mut recording = Recording::default();
+        grow_trails(&mut world, BASE_THICKNESS);
+        grow_trails(&mut world, BASE_THICKNESS);
+        record_at(&mut recording, &mut world, Duration::ZERO);
+
+        world.remove_player(1);
+        grow_trails(&mut world, BASE_THICKNESS);
+        record_at(&mut recording, &mut world, RECORDING_INTERVAL);
+
+        let frame = recording.frames.last().unwrap();
+        assert_eq!(frame.players.len(), 2);
+        for (p, recorded) in world.players.iter().zip(frame.players.iter()) {
+            let rec = &recording.players[recorded.player];
+            assert_eq!(rec.id, p.id);
+            assert_eq!(rec.name, p.name);
+            assert_eq!(recorded.end, p.trail.len() - 1);
+        }
+        assert_eq!(frame.players[1].player, 2);
+    }
+
+    fn gif_settings(gif_width: u32, gif_speed: u32, gif_style: GifStyle) -> ExportSettings {
+        ExportSettings {
+            gif_width,
+            gif_speed,
+            gif_style,
+            ..Default::default()
+        }
+    }
+
+    fn decode_gif(bytes: &[u8]) -> Vec<gif::Frame<'static>> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(bytes).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push(frame.clone());
+        }
+        frames
+    }
+
+    fn encode(recording: &Recording, settings: &ExportSettings) -> Vec<gif::Frame<'static>> {
+        let mut bytes = Vec::new();
+        encode_gif(recording, settings, &mut bytes).unwrap();
+        decode_gif(&bytes)
+    }
+
+    /// The RGBA color of the pixel at a world position.
+    fn pixel<'a>(frame: &'a gif::Frame<'_>, pos: Pos2) -> &'a [u8] {
+        let scale = frame.width as f32 / WORLD_SIZE.x;
+        let (x, y) = ((scale * pos.x) as usize, (scale * pos.y) as usize);
+        let i = 4 * (y * frame.width as usize + x);
+        &frame.buffer[i..i + 4]
+    }
+
+    #[test]
+    fn gif_frame_timing() {
+        let mut world = recorded_world();
+        let mut recording = Recording::default();
+        grow_trails(&mut world, BASE_THICKNESS);
+        record_at(&mut recording, &mut world, Duration::ZERO);
+        grow_trails(&mut world, BASE_THICKNESS);
+        world.state = GameState::Stopped(Duration::ZERO);
+        record_at(&mut recording, &mut world, Duration::from_millis(200));
+
+        let frames = encode(&recording, &gif_settings(480, 1, GifStyle::TrailsOnly));
+        // one frame every 40ms, including both ends
+        assert_eq!(frames.len(), 6);
+        assert_eq!((frames[0].width, frames[0].height), (480, 270));
+        assert!(frames[..5].iter().all(|f| f.delay == 4));
+        assert_eq!(frames[5].delay, GIF_END_DELAY);
+
+        let frames = encode(&recording, &gif_settings(480, 2, GifStyle::TrailsOnly));
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[3].delay, GIF_END_DELAY);
+    }
+
+    #[test]
+    fn gif_without_frames() {
+        let settings = ExportSettings::default();
+        assert!(encode_gif(&Recording::default(), &settings, Vec::new()).is_err());
+        assert!(save_gif(&Recording::default(), &settings).is_err());
+    }
+
+    #[test]
+    fn gif_style_draws_the_hud() {
+        let mut world = recorded_world();
+        let mut recording = Recording::default();
+        grow_trails(&mut world, BASE_THICKNESS);
+        world.players[0].score = 3;
+        record_at(&mut recording, &mut world, Duration::ZERO);
+
+        let trails_only = encode(&recording, &gif_settings(640, 1, GifStyle::TrailsOnly));
+        let with_hud = encode(&recording, &gif_settings(640, 1, GifStyle::WithHud));
+        assert_eq!((trails_only.len(), with_hud.len()), (1, 1));
+        assert_ne!(trails_only[0].buffer, with_hud[0].buffer);
+
+        // the names and scores are drawn in the top left corner
+        let corner = |frame: &gif::Frame| -> Vec<u8> {
+            (20..80)
+                .flat_map(|x| (20..40).map(move |y| (x, y)))
+                .flat_map(|(x, y)| pixel(frame, pos2(x as f32, y as f32)).to_vec())
+                .collect()
+        };
+        let background = pixel(&trails_only[0], pos2(600.0, 20.0)).to_vec();
+        assert!(corner(&trails_only[0]).chunks(4).all(|c| c == background));
+        assert!(corner(&with_hud[0]).chunks(4).any(|c| c != background));
+    }
+
+    #[test]
+    fn gif_redraws_cleared_and_removed_trails() {
+        let mut world = recorded_world();
+        world.add_player().unwrap();
+        let mut recording = Recording::default();
+        for _ in 0..3 {
+            grow_trails(&mut world, 10.0);
+        }
+        record_at(&mut recording, &mut world, Duration::ZERO);
+
+        // the first player clears their trail and the third one is removed
+        let p = &mut world.players[0];
+        p.trail.clear();
+        p.trail_generation += 1;
+        world.remove_player(2);
+        grow_trails(&mut world, 10.0);
+        grow_trails(&mut world, 10.0);
+        world.state = GameState::Stopped(Duration::ZERO);
+        record_at(&mut recording, &mut world, Duration::from_millis(40));
+
+        let frames = encode(&recording, &gif_settings(640, 1, GifStyle::TrailsOnly));
+        assert_eq!(frames.len(), 2);
+        let (first, last) = (&frames[0], &frames[1]);
+        let background = |frame| pixel(frame, pos2(1200.0, 50.0)).to_vec();
+        // the finished sections of the first frame
+        let old_trails = [pos2(150.0, 100.0), pos2(250.0, 200.0), pos2(250.0, 300.0)];
+        for pos in old_trails {
+            assert_ne!(pixel(first, pos), background(first), "{pos:?}");
+        }
+
+        // the new trail of the first player only covers the start of the old one
+        assert_eq!(
+            pixel(last, pos2(250.0, 100.0)),
+            pixel(first, pos2(150.0, 100.0))
+        );
+        // the second player keeps their trail
+        assert_eq!(
+            pixel(last, pos2(250.0, 200.0)),
+            pixel(first, pos2(250.0, 200.0))
+        );
+        assert_eq!(pixel(last, pos2(250.0, 300.0)), background(last));
+        assert_eq!(pixel(last, pos2(350.0, 100.0)), background(last));
+    }
+}
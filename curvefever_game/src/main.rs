@@ -4,9 +4,16 @@ use egui::ViewportBuilder;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
-use app::CurvefeverApp;
+use app::{CurvefeverApp, ServerChannels};
 
+pub mod api;
 pub mod app;
+pub mod audio;
+pub mod editor;
+pub mod export;
+pub mod map;
+pub mod particles;
+pub mod script;
 pub mod server;
 pub mod world;
 
@@ -23,8 +30,19 @@ fn main() {
         let (server_kill_signal, server_kill_receiver) = tokio::sync::oneshot::channel();
         let (server_sender, server_receiver) = async_channel::unbounded();
         let (game_sender, game_receiver) = async_channel::unbounded();
+        let (api_sender, api_receiver) = async_channel::unbounded();
+        let (stream_sender, stream_receiver) = async_channel::unbounded();
+        let host_token = server::new_host_token();
+        let server_host_token = host_token.clone();
         let server_handle = scope.spawn(|| {
-            server::start_server(server_sender, game_receiver, server_kill_receiver);
+            server::start_server(
+                server_sender,
+                api_sender,
+                game_receiver,
+                stream_receiver,
+                server_kill_receiver,
+                server_host_token,
+            );
         });
 
         // start game
@@ -35,11 +53,22 @@ fn main() {
             ..Default::default()
         };
 
-        let a_game_sender = game_sender.clone();
+        let server_channels = ServerChannels {
+            server_events: server_receiver,
+            api_requests: api_receiver,
+            game_events: game_sender.clone(),
+            stream_events: stream_sender,
+        };
         let res = eframe::run_native(
             "curvefever",
             options,
-            Box::new(|c| Ok(Box::new(CurvefeverApp::new(c, server_receiver, a_game_sender)))),
+            Box::new(move |c| {
+                Ok(Box::new(CurvefeverApp::new(
+                    c,
+                    server_channels,
+                    &host_token,
+                )))
+            }),
         );
         if let Err(e) = res {
             println!("error running app: {e}");
@@ -0,0 +1,477 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use egui::Pos2;
+
+pub const MAP_FILE_EXTENSION: &str = "map";
+
+#[rustfmt::skip]
+const BUILTIN_MAPS: &[&str] = &[
+    include_str!("../maps/empty.map"),
+    include_str!("../maps/pillars.map"),
+    include_str!("../maps/cross.map"),
+    include_str!("../maps/arena.map"),
+    include_str!("../maps/portals.map"),
+];
+
+/// A static arena layout.
+///
+/// Maps are stored as plain text, one element per line, with all coordinates in world space and
+/// all angles in degrees:
+/// ```text
+/// # comment
+/// name <name>
+/// segment <start x> <start y> <end x> <end y> <thickness>
+/// arc <center x> <center y> <radius> <start angle> <end angle> <thickness>
+/// circle <center x> <center y> <radius>
+/// portal <a x> <a y> <b x> <b y> <radius>
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Map {
+    pub name: String,
+    pub walls: Vec<Wall>,
+    pub portals: Vec<Portal>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Wall {
+    Segment {
+        start: Pos2,
+        end: Pos2,
+        thickness: f32,
+    },
+    /// An arc going clockwise from `start_angle` to `end_angle`, angles are in radians.
+    Arc {
+        center: Pos2,
+        radius: f32,
+        start_angle: f32,
+        end_angle: f32,
+        thickness: f32,
+    },
+    Circle {
+        center: Pos2,
+        radius: f32,
+    },
+}
+
+/// A pair of linked portals, players entering one end will exit at the other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Portal {
+    pub a: Pos2,
+    pub b: Pos2,
+    pub radius: f32,
+}
+
+impl Map {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut map = Map::default();
+
+        for (line_idx, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (keyword, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let res = match keyword {
+                "name" => {
+                    map.name = args.trim().to_string();
+                    Ok(())
+                }
+                "segment" => parse_args::<5>(args).and_then(|[x1, y1, x2, y2, thickness]| {
+                    map.walls.push(Wall::Segment {
+                        start: Pos2::new(x1, y1),
+                        end: Pos2::new(x2, y2),
+                        thickness: non_negative(thickness, "thickness")?,
+                    });
+                    Ok(())
+                }),
+                "arc" => parse_args::<6>(args).and_then(|[x, y, radius, start, end, thickness]| {
+                    map.walls.push(Wall::Arc {
+                        center: Pos2::new(x, y),
+                        radius: non_negative(radius, "radius")?,
+                        start_angle: start.to_radians(),
+                        end_angle: end.to_radians(),
+                        thickness: non_negative(thickness, "thickness")?,
+                    });
+                    Ok(())
+                }),
+                "circle" => parse_args::<3>(args).and_then(|[x, y, radius]| {
+                    map.walls.push(Wall::Circle {
+                        center: Pos2::new(x, y),
+                        radius: non_negative(radius, "radius")?,
+                    });
+                    Ok(())
+                }),
+                "portal" => parse_args::<5>(args).and_then(|[ax, ay, bx, by, radius]| {
+                    map.portals.push(Portal {
+                        a: Pos2::new(ax, ay),
+                        b: Pos2::new(bx, by),
+                        radius: non_negative(radius, "radius")?,
+                    });
+                    Ok(())
+                }),
+                _ => Err(anyhow::anyhow!("unknown element `{keyword}`")),
+            };
+            res.with_context(|| format!("line {}", line_idx + 1))?;
+        }
+
+        if map.name.is_empty() {
+            anyhow::bail!("missing map name");
+        }
+
+        Ok(map)
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let input = std::fs::read_to_string(path)?;
+        Self::parse(&input)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// The path inside the [`maps_dir`] this map is saved to. Built-in maps can't be replaced, so
+    /// their names are refused.
+    pub fn file_path(&self) -> anyhow::Result<PathBuf> {
+        if is_builtin_name(&self.name) {
+            anyhow::bail!("`{}` is the name of a built-in map", self.name.trim());
+        }
+        let dir = maps_dir().context("no data directory to save maps to")?;
+        let file_name: String = self
+            .name
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | '0'..='9' | '-' | '_' => c,
+                'A'..='Z' => c.to_ascii_lowercase(),
+                _ => '_',
+            })
+            .collect();
+        Ok(dir.join(file_name).with_extension(MAP_FILE_EXTENSION))
+    }
+
+    /// The other end of the portal `pos` is in, if any.
+    pub fn portal_exit(&self, pos: Pos2) -> Option<Pos2> {
+        self.portals.iter().find_map(|p| {
+            if pos.distance(p.a) < p.radius {
+                Some(p.b)
+            } else if pos.distance(p.b) < p.radius {
+                Some(p.a)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "name {}", self.name)?;
+        for w in self.walls.iter() {
+            match w {
+                Wall::Segment {
+                    start,
+                    end,
+                    thickness,
+                } => writeln!(
+                    f,
+                    "segment {} {} {} {} {thickness}",
+                    start.x, start.y, end.x, end.y
+                )?,
+                Wall::Arc {
+                    center,
+                    radius,
+                    start_angle,
+                    end_angle,
+                    thickness,
+                } => writeln!(
+                    f,
+                    "arc {} {} {radius} {} {} {thickness}",
+                    center.x,
+                    center.y,
+                    start_angle.to_degrees(),
+                    end_angle.to_degrees()
+                )?,
+                Wall::Circle { center, radius } => {
+                    writeln!(f, "circle {} {} {radius}", center.x, center.y)?
+                }
+            }
+        }
+        for p in self.portals.iter() {
+            writeln!(
+                f,
+                "portal {} {} {} {} {}",
+                p.a.x, p.a.y, p.b.x, p.b.y, p.radius
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_args<const N: usize>(args: &str) -> anyhow::Result<[f32; N]> {
+    let mut values = [0.0_f32; N];
+    let mut iter = args.split_whitespace();
+    for (i, v) in values.iter_mut().enumerate() {
+        let Some(arg) = iter.next() else {
+            anyhow::bail!("expected {N} arguments, found {i}");
+        };
+        *v = arg
+            .parse()
+            .with_context(|| format!("invalid number `{arg}`"))?;
+        // `nan` and `inf` parse, but would break collision checks
+        if !v.is_finite() {
+            anyhow::bail!("invalid number `{arg}`");
+        }
+    }
+    if iter.next().is_some() {
+        anyhow::bail!("expected {N} arguments, found more");
+    }
+    Ok(values)
+}
+
+fn non_negative(value: f32, name: &str) -> anyhow::Result<f32> {
+    if value < 0.0 {
+        anyhow::bail!("negative {name} `{value}`");
+    }
+    Ok(value)
+}
+
+/// The directory custom maps are loaded from and saved to, inside the data directory of the
+/// user, e.g. `~/.local/share/curvefever/maps` on Linux.
+pub fn maps_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("curvefever").join("maps"))
+}
+
+/// Loads the built-in maps, followed by all custom maps inside the [`maps_dir`].
+pub fn load_maps() -> Vec<Map> {
+    let mut maps: Vec<Map> = BUILTIN_MAPS
+        .iter()
+        .map(|m| Map::parse(m).expect("built-in maps should be valid"))
+        .collect();
+    if let Some(dir) = maps_dir() {
+        load_custom_maps(&mut maps, &dir);
+    }
+    tracing::debug!("Loaded {} maps", maps.len());
+    maps
+}
+
+/// Whether `name` is the name of a built-in map, ignoring case.
+pub fn is_builtin_name(name: &str) -> bool {
+    BUILTIN_MAPS.iter().any(|m| {
+        let map = Map::parse(m).expect("built-in maps should be valid");
+        map.name.eq_ignore_ascii_case(name.trim())
+    })
+}
+
+/// Appends the maps inside `dir`, skipping maps with the same name as an already loaded one.
+fn load_custom_maps(maps: &mut Vec<Map>, dir: &Path) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Error reading maps directory:\n{e}");
+            }
+            return;
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == MAP_FILE_EXTENSION))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        match Map::load(&path) {
+            Ok(map) if maps.iter().any(|m| m.name.eq_ignore_ascii_case(&map.name)) => {
+                tracing::warn!(
+                    "Skipping map `{}`, a map named `{}` already exists",
+                    path.display(),
+                    map.name
+                );
+            }
+            Ok(map) => maps.push(map),
+            Err(e) => tracing::warn!("Error loading map `{}`:\n{e:#}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_4;
+
+    use egui::Vec2;
+
+    use super::*;
+    use crate::world::intersects_walls;
+
+    #[test]
+    fn builtin_maps_round_trip() {
+        for input in BUILTIN_MAPS {
+            let map = Map::parse(input).unwrap();
+            let reparsed = Map::parse(&map.to_string()).unwrap();
+            assert_eq!(reparsed.to_string(), map.to_string());
+            assert_eq!(reparsed.name, map.name);
+            assert_eq!(reparsed.walls.len(), map.walls.len());
+            assert_eq!(reparsed.portals, map.portals);
+        }
+    }
+
+    #[test]
+    fn parse_all_elements() {
+        let input = "\
+            # comment\n\
+            name Test map\n\
+            \n\
+            segment 10 20 30 40 8\n\
+            arc 640 360 100 0 90 4\n\
+            circle 50 60 5.5\n\
+            portal 1 2 3 4 20\n";
+        let map = Map::parse(input).unwrap();
+        assert_eq!(map.name, "Test map");
+        assert_eq!(
+            map.walls[0],
+            Wall::Segment {
+                start: Pos2::new(10.0, 20.0),
+                end: Pos2::new(30.0, 40.0),
+                thickness: 8.0,
+            }
+        );
+        let Wall::Arc {
+            start_angle,
+            end_angle,
+            ..
+        } = map.walls[1]
+        else {
+            panic!("expected an arc, found {:?}", map.walls[1]);
+        };
+        assert_eq!((start_angle, end_angle), (0.0, 90f32.to_radians()));
+        assert_eq!(
+            map.walls[2],
+            Wall::Circle {
+                center: Pos2::new(50.0, 60.0),
+                radius: 5.5,
+            }
+        );
+        assert_eq!(map.portals[0].radius, 20.0);
+
+        let reparsed = Map::parse(&map.to_string()).unwrap();
+        assert_eq!(reparsed.walls[0], map.walls[0]);
+        assert_eq!(reparsed.walls[2], map.walls[2]);
+        assert_eq!(reparsed.portals, map.portals);
+    }
+
+    fn parse_error(input: &str) -> String {
+        format!("{:#}", Map::parse(input).unwrap_err())
+    }
+
+    #[test]
+    fn parse_malformed_lines() {
+        let error = parse_error("name A\nsquare 1 2 3");
+        assert_eq!(error, "line 2: unknown element `square`");
+        let error = parse_error("name A\ncircle 1 2");
+        assert_eq!(error, "line 2: expected 3 arguments, found 2");
+        let error = parse_error("name A\ncircle 1 2 3 4");
+        assert_eq!(error, "line 2: expected 3 arguments, found more");
+        let error = parse_error("name A\n\nsegment 1 2 x 4 5");
+        assert!(error.starts_with("line 3: invalid number `x`"), "{error}");
+        let error = parse_error("circle 1 2 3");
+        assert_eq!(error, "missing map name");
+
+        for number in ["nan", "NaN", "inf", "-inf", "infinity"] {
+            let error = parse_error(&format!("name A\ncircle 1 {number} 3"));
+            assert_eq!(error, format!("line 2: invalid number `{number}`"));
+        }
+        let error = parse_error("name A\nsegment 1 2 3 4 -5");
+        assert_eq!(error, "line 2: negative thickness `-5`");
+        let error = parse_error("name A\narc 1 2 -3 0 90 4");
+        assert_eq!(error, "line 2: negative radius `-3`");
+        let error = parse_error("name A\narc 1 2 3 0 90 -0.5");
+        assert_eq!(error, "line 2: negative thickness `-0.5`");
+        let error = parse_error("name A\ncircle 1 2 -3");
+        assert_eq!(error, "line 2: negative radius `-3`");
+        let error = parse_error("name A\nportal 1 2 3 4 -5");
+        assert_eq!(error, "line 2: negative radius `-5`");
+        // coordinates and angles may be negative
+        assert!(Map::parse("name A\nsegment -1 -2 3 4 0\narc 1 2 3 -90 -45 4").is_ok());
+    }
+
+    #[test]
+    fn arc_collision() {
+        // a quarter circle from the right to the bottom
+        let walls = [Wall::Arc {
+            center: Pos2::new(100.0, 100.0),
+            radius: 50.0,
+            start_angle: 0.0,
+            end_angle: 90f32.to_radians(),
+            thickness: 4.0,
+        }];
+        let on_arc = |angle: f32, radius: f32| {
+            Pos2::new(100.0, 100.0) + radius * Vec2::new(angle.cos(), angle.sin())
+        };
+
+        assert!(intersects_walls(&walls, on_arc(FRAC_PI_4, 50.0), 1.0));
+        assert!(intersects_walls(&walls, on_arc(FRAC_PI_4, 52.5), 1.0));
+        assert!(!intersects_walls(&walls, on_arc(FRAC_PI_4, 40.0), 1.0));
+        assert!(!intersects_walls(&walls, Pos2::new(100.0, 100.0), 1.0));
+        // the other three quarters of the circle are open
+        assert!(!intersects_walls(&walls, on_arc(-FRAC_PI_4, 50.0), 1.0));
+        assert!(!intersects_walls(
+            &walls,
+            on_arc(3.0 * FRAC_PI_4, 50.0),
+            1.0
+        ));
+        assert!(!intersects_walls(
+            &walls,
+            on_arc(5.0 * FRAC_PI_4, 50.0),
+            1.0
+        ));
+    }
+
+    #[test]
+    fn builtin_names_are_not_saved() {
+        let map = |name: &str| Map {
+            name: name.into(),
+            ..Default::default()
+        };
+        assert!(is_builtin_name("Pillars"));
+        assert!(is_builtin_name(" pillars "));
+        assert!(!is_builtin_name("Pillars 2"));
+
+        let error = map("ARENA").file_path().unwrap_err();
+        assert_eq!(error.to_string(), "`ARENA` is the name of a built-in map");
+        if let Ok(path) = map("My Map!").file_path() {
+            assert!(path.ends_with("curvefever/maps/my_map_.map"), "{path:?}");
+        }
+    }
+
+    #[test]
+    fn custom_maps_skip_existing_names() {
+        let dir = std::env::temp_dir().join(format!("curvefever-maps-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a_empty.map"), "name empty\ncircle 1 2 3").unwrap();
+        std::fs::write(dir.join("b_custom.map"), "name Custom\ncircle 1 2 3").unwrap();
+        std::fs::write(dir.join("c_custom.map"), "name Custom\ncircle 4 5 6").unwrap();
+        std::fs::write(dir.join("d_invalid.map"), "circle 1 2 3").unwrap();
+        std::fs::write(dir.join("notes.txt"), "name Notes").unwrap();
+
+        let mut maps = vec![Map::parse(BUILTIN_MAPS[0]).unwrap()];
+        load_custom_maps(&mut maps, &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<&str> = maps.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["Empty", "Custom"]);
+        assert_eq!(
+            maps[1].walls,
+            [Wall::Circle {
+                center: Pos2::new(1.0, 2.0),
+                radius: 3.0,
+            }]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use egui::{Color32, Pos2, Vec2};
+use rand::Rng;
+
+use curvefever_derive::{EnumMembersArray, FromJson, ToJson};
+
+use crate::world::{Palette, WorldEvent};
+
+pub const VISUAL_EFFECTS: &[VisualEffects] = VisualEffects::members();
+const CRASH_SPARKS: usize = 40;
+const SPARK_LIFETIME: Duration = Duration::from_millis(900);
+const SPARK_MIN_SPEED: f32 = 40.0;
+const SPARK_MAX_SPEED: f32 = 260.0;
+/// How quickly sparks slow down, the fraction of the speed lost per second is `1 - e^-drag`.
+const SPARK_DRAG: f32 = 4.0;
+const RING_LIFETIME: Duration = Duration::from_millis(500);
+const RING_MAX_RADIUS: f32 = 40.0;
+/// The number of times per second the wall teleporting outline pulses.
+pub const PULSE_FREQUENCY: f32 = 1.5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumMembersArray, ToJson, FromJson)]
+pub enum VisualEffects {
+    Off = 0,
+    /// Particles and animated outlines.
+    Low = 1,
+    /// Additionally draws a glow around trails.
+    #[default]
+    High = 2,
+}
+
+impl VisualEffects {
+    pub const fn name(&self) -> &str {
+        match self {
+            Self::Off => "off",
+            Self::Low => "low",
+            Self::High => "high",
+        }
+    }
+
+    pub fn next(&mut self) {
+        let idx = (*self as usize + 1) % VISUAL_EFFECTS.len();
+        *self = VISUAL_EFFECTS[idx];
+    }
+
+    pub fn particles(&self) -> bool {
+        *self != Self::Off
+    }
+
+    pub fn trail_glow(&self) -> bool {
+        *self == Self::High
+    }
+}
+
+struct Spark {
+    start: Duration,
+    pos: Pos2,
+    velocity: Vec2,
+    radius: f32,
+    color: Color32,
+}
+
+struct Ring {
+    start: Duration,
+    pos: Pos2,
+    color: Color32,
+}
+
+/// A particle to draw, in world space.
+pub enum Particle {
+    Dot {
+        pos: Pos2,
+        radius: f32,
+        color: Color32,
+    },
+    Ring {
+        pos: Pos2,
+        radius: f32,
+        width: f32,
+        color: Color32,
+    },
+}
+
+/// Short-lived decorative particles, animated using the simulated time of the world, so they
+/// freeze while the game is paused.
+#[derive(Default)]
+pub struct Particles {
+    sparks: Vec<Spark>,
+    rings: Vec<Ring>,
+}
+
+impl Particles {
+    /// Spawns particles for the events and removes finished ones.
+    pub fn handle_events(&mut self, events: &[WorldEvent], now: Duration, palette: Palette) {
+        self.sparks.retain(|s| now < s.start + SPARK_LIFETIME);
+        self.rings.retain(|r| now < r.start + RING_LIFETIME);
+
+        for e in events.iter() {
+            match e {
+                WorldEvent::Crashed { pos, message, .. } => {
+                    self.crash_burst(now, *pos, message.crashed_color().color32(palette));
+                }
+                WorldEvent::ItemCollected { pos, kind, .. } => {
+                    self.rings.push(Ring {
+                        start: now,
+                        pos: *pos,
+                        color: kind.color32(),
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+
+    pub fn crash_burst(&mut self, now: Duration, pos: Pos2, color: Color32) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..CRASH_SPARKS {
+            let angle = rng.gen_range(0.0..TAU);
+            let speed = rng.gen_range(SPARK_MIN_SPEED..SPARK_MAX_SPEED);
+            self.sparks.push(Spark {
+                start: now,
+                pos,
+                velocity: speed * Vec2::angled(angle),
+                radius: rng.gen_range(1.0..3.0),
+                color,
+            });
+        }
+    }
+
+    /// The particles at time `now`.
+    pub fn particles(&self, now: Duration) -> impl Iterator<Item = Particle> + '_ {
+        let sparks = self.sparks.iter().filter_map(move |s| {
+            let t = now.checked_sub(s.start)?.as_secs_f32();
+            let progress = t / SPARK_LIFETIME.as_secs_f32();
+            if progress >= 1.0 {
+                return None;
+            }
+            let travelled = (1.0 - (-SPARK_DRAG * t).exp()) / SPARK_DRAG;
+            Some(Particle::Dot {
+                pos: s.pos + travelled * s.velocity,
+                radius: s.radius * (1.0 - 0.5 * progress),
+                color: s.color.gamma_multiply(1.0 - progress),
+            })
+        });
+        let rings = self.rings.iter().filter_map(move |r| {
+            let t = now.checked_sub(r.start)?.as_secs_f32();
+            let progress = t / RING_LIFETIME.as_secs_f32();
+            if progress >= 1.0 {
+                return None;
+            }
+            // ease out, so the ring expands quickly at first
+            let radius = RING_MAX_RADIUS * (1.0 - (1.0 - progress).powi(3));
+            Some(Particle::Ring {
+                pos: r.pos,
+                radius,
+                width: 3.0 * (1.0 - progress) + 0.5,
+                color: r.color.gamma_multiply(1.0 - progress),
+            })
+        });
+        sparks.chain(rings)
+    }
+}
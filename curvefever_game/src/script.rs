@@ -0,0 +1,591 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+
+use egui::{Color32, Pos2};
+use rand::Rng;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST, FLOAT, INT};
+
+use crate::world::{
+    CrashMessage, GameState, ItemKind, PlayerEffect, TrailSection, World, WorldEvent, ITEM_KINDS,
+    WORLD_SIZE,
+};
+
+pub const MODE_FILE_EXTENSION: &str = "rhai";
+/// The maximum nesting depth of expressions in scripts.
+const MAX_EXPR_DEPTH: usize = 64;
+/// The maximum number of operations a single hook may run, so a script that doesn't terminate
+/// can't stall the simulation.
+const MAX_OPERATIONS: u64 = 100_000;
+
+#[rustfmt::skip]
+const BUILTIN_MODES: &[(&str, &str)] = &[
+    ("king of the hill", include_str!("../modes/king_of_the_hill.rhai")),
+    ("tag", include_str!("../modes/tag.rhai")),
+    ("capture points", include_str!("../modes/capture_points.rhai")),
+];
+
+/// A game mode, which changes the rules of the classic game using a [Rhai](https://rhai.rs)
+/// script.
+///
+/// Scripts can define any of these hooks, which are called by the [`ScriptHost`]:
+/// ```text
+/// fn on_round_start() {}
+/// fn on_tick(dt) {}                         // dt is the simulated time in seconds
+/// fn on_crash(player_id, kind) {}           // kind is "wall", "own" or "other"
+/// fn on_item_collected(player_id, kind) {}  // kind is the script name of the item
+/// ```
+/// Inside hooks `this` is an object map that keeps its fields for as long as the mode is
+/// selected. Scripts can use these functions:
+/// ```text
+/// players()                  // array of #{ id, name, x, y, angle, color, score, crashed, gap }
+/// trail(player_id)           // array of #{ x1, y1, x2, y2, gap } sections
+/// items()                    // array of #{ kind, x, y }
+/// world_size()               // [width, height]
+/// time()                     // seconds since the round started
+/// random(min, max)           // a random float in min..max
+/// add_effect(player_id, kind, secs)          // kind is "ghost" or "no_gap"
+/// add_effect(player_id, kind, amount, secs)  // kind is "speed", "turning" or "size"
+/// spawn_item(kind, x, y)
+/// award_score(player_id, points)
+/// draw_circle(x, y, radius, color)           // drawn until the next tick, color is 0xRRGGBB
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameMode {
+    pub name: String,
+    /// The rhai source, `None` for the classic game.
+    pub script: Option<String>,
+}
+
+impl GameMode {
+    pub fn classic() -> Self {
+        Self {
+            name: "classic".to_string(),
+            script: None,
+        }
+    }
+}
+
+/// A circle drawn on top of the arena by a game mode, for example to show zones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marker {
+    pub pos: Pos2,
+    pub radius: f32,
+    pub color: Color32,
+}
+
+/// The directory custom game mode scripts are loaded from, inside the data directory of the user,
+/// e.g. `~/.local/share/curvefever/modes` on Linux.
+pub fn modes_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("curvefever").join("modes"))
+}
+
+/// Loads the classic mode and built-in modes, followed by all custom modes inside the
+/// [`modes_dir`].
+pub fn load_modes() -> Vec<GameMode> {
+    let mut modes = vec![GameMode::classic()];
+    modes.extend(BUILTIN_MODES.iter().map(|(name, script)| GameMode {
+        name: name.to_string(),
+        script: Some(script.to_string()),
+    }));
+
+    let Some(dir) = modes_dir() else {
+        return modes;
+    };
+    let entries = match std::fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Error reading modes directory:\n{e}");
+            }
+            return modes;
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == MODE_FILE_EXTENSION))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let Some(stem) = path.file_stem() else {
+            continue;
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(script) => modes.push(GameMode {
+                name: stem.to_string_lossy().replace('_', " "),
+                script: Some(script),
+            }),
+            Err(e) => tracing::warn!("Error loading mode `{}`:\n{e}", path.display()),
+        }
+    }
+    tracing::debug!("Loaded {} modes", modes.len());
+
+    modes
+}
+
+/// The state of the world visible to scripts, and the changes they requested.
+#[derive(Default)]
+struct ScriptView {
+    round_start: Duration,
+    now: Duration,
+    players: Vec<PlayerView>,
+    items: Vec<(ItemKind, Pos2)>,
+    commands: Vec<ScriptCommand>,
+    markers: Vec<Marker>,
+}
+
+struct PlayerView {
+    id: u16,
+    name: String,
+    pos: Pos2,
+    angle: f32,
+    color: Color32,
+    score: u16,
+    crashed: bool,
+    gap: bool,
+    trail_generation: u32,
+    trail: Vec<TrailSection>,
+}
+
+enum ScriptCommand {
+    AddEffect {
+        player_id: u16,
+        kind: PlayerEffect,
+        duration: Duration,
+    },
+    SpawnItem {
+        kind: ItemKind,
+        pos: Pos2,
+    },
+    AwardScore {
+        player_id: u16,
+        points: i32,
+    },
+}
+
+impl ScriptView {
+    fn update(&mut self, world: &World) {
+        self.now = world.clock.now;
+        if let GameState::Starting(start) | GameState::Running(start) = world.state {
+            self.round_start = start;
+        }
+        self.items = world.items.iter().map(|i| (i.kind, i.pos)).collect();
+
+        let palette = world.settings.palette;
+        self.players
+            .retain(|v| world.players.iter().any(|p| p.id == v.id));
+        for p in world.players.iter() {
+            let idx = match self.players.iter().position(|v| v.id == p.id) {
+                Some(idx) => idx,
+                None => {
+                    self.players.push(PlayerView {
+                        id: p.id,
+                        name: String::new(),
+                        pos: p.pos,
+                        angle: p.angle,
+                        color: Color32::WHITE,
+                        score: 0,
+                        crashed: false,
+                        gap: false,
+                        trail_generation: p.trail_generation,
+                        trail: Vec::new(),
+                    });
+                    self.players.len() - 1
+                }
+            };
+            let v = &mut self.players[idx];
+            v.name.clone_from(&p.name);
+            v.pos = p.pos;
+            v.angle = p.angle;
+            v.color = p.color.color32(palette);
+            v.score = p.score;
+            v.crashed = p.crashed;
+            v.gap = p.gap();
+
+            // trails only grow until they're cleared, and only the last section changes
+            if v.trail_generation != p.trail_generation || v.trail.len() > p.trail.len() {
+                v.trail_generation = p.trail_generation;
+                v.trail.clear();
+            }
+            let unchanged = v.trail.len().saturating_sub(1);
+            v.trail.truncate(unchanged);
+            v.trail.extend(p.trail.iter_from(unchanged).cloned());
+        }
+    }
+}
+
+/// Runs the hooks of a game mode script.
+pub struct ScriptHost {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    /// The object map bound to `this` inside hooks.
+    this: Dynamic,
+    view: Rc<RefCell<ScriptView>>,
+    /// Set after a hook failed, the script is disabled until the next round starts.
+    failed: bool,
+}
+
+impl ScriptHost {
+    pub fn new(script: &str) -> anyhow::Result<Self> {
+        let view = Rc::new(RefCell::new(ScriptView::default()));
+        let mut engine = Engine::new();
+        // the defaults are much lower in debug builds
+        engine.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH);
+        // exceeding the limit is an error, which disables the script like any other failed hook
+        engine.set_max_operations(MAX_OPERATIONS);
+        register_api(&mut engine, &view);
+
+        let ast = engine
+            .compile(script)
+            .map_err(|e| anyhow::anyhow!("error compiling script: {e}"))?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| anyhow::anyhow!("error running script: {e}"))?;
+
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            this: Dynamic::from_map(Map::new()),
+            view,
+            failed: false,
+        })
+    }
+
+    /// Calls the hooks for the `events` of the last update, and applies the requested changes to
+    /// the world.
+    pub fn run(&mut self, world: &mut World, events: &[WorldEvent], dt: Duration) {
+        self.view.borrow_mut().update(world);
+
+        for e in events.iter() {
+            match e {
+                WorldEvent::StateChanged {
+                    from: GameState::Starting(_),
+                    to: GameState::Running(_),
+                } => {
+                    self.failed = false;
+                    self.call("on_round_start", ());
+                }
+                WorldEvent::Crashed {
+                    player_id, message, ..
+                } => {
+                    let kind = match message {
+                        CrashMessage::Wall { .. } => "wall",
+                        CrashMessage::Own { .. } => "own",
+                        CrashMessage::Other { .. } => "other",
+                    };
+                    self.call("on_crash", (*player_id as INT, kind));
+                }
+                WorldEvent::ItemCollected {
+                    player_id, kind, ..
+                } => {
+                    self.call("on_item_collected", (*player_id as INT, item_name(*kind)));
+                }
+                _ => (),
+            }
+        }
+
+        if matches!(world.state, GameState::Running(_)) {
+            self.view.borrow_mut().markers.clear();
+            self.call("on_tick", (dt.as_secs_f64() as FLOAT,));
+        }
+
+        let mut view = self.view.borrow_mut();
+        for c in view.commands.drain(..) {
+            match c {
+                ScriptCommand::AddEffect {
+                    player_id,
+                    kind,
+                    duration,
+                } => world.add_player_effect(player_id, kind, duration),
+                ScriptCommand::SpawnItem { kind, pos } => world.spawn_item(kind, pos),
+                ScriptCommand::AwardScore { player_id, points } => {
+                    world.award_score(player_id, points)
+                }
+            }
+        }
+        world.markers.clone_from(&view.markers);
+    }
+
+    fn call(&mut self, name: &str, args: impl rhai::FuncArgs) {
+        if self.failed || !self.ast.iter_functions().any(|f| f.name == name) {
+            return;
+        }
+
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.this);
+        let res = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut self.scope,
+            &self.ast,
+            name,
+            args,
+        );
+        if let Err(e) = res {
+            tracing::warn!(
+                "Error in `{name}` of game mode script, disabling it for this round:\n{e}"
+            );
+            self.failed = true;
+        }
+    }
+}
+
+fn register_api(engine: &mut Engine, view: &Rc<RefCell<ScriptView>>) {
+    let v = Rc::clone(view);
+    engine.register_fn("players", move || -> Array {
+        let view = v.borrow();
+        view.players
+            .iter()
+            .map(|p| {
+                let mut map = Map::new();
+                map.insert("id".into(), Dynamic::from_int(p.id as INT));
+                map.insert("name".into(), Dynamic::from(p.name.clone()));
+                map.insert("x".into(), Dynamic::from_float(p.pos.x as FLOAT));
+                map.insert("y".into(), Dynamic::from_float(p.pos.y as FLOAT));
+                map.insert("angle".into(), Dynamic::from_float(p.angle as FLOAT));
+                map.insert("color".into(), Dynamic::from_int(color_to_int(p.color)));
+                map.insert("score".into(), Dynamic::from_int(p.score as INT));
+                map.insert("crashed".into(), Dynamic::from_bool(p.crashed));
+                map.insert("gap".into(), Dynamic::from_bool(p.gap));
+                Dynamic::from_map(map)
+            })
+            .collect()
+    });
+
+    let v = Rc::clone(view);
+    engine.register_fn("trail", move |player_id: INT| -> Array {
+        let view = v.borrow();
+        let Some(p) = view.players.iter().find(|p| p.id as INT == player_id) else {
+            return Array::new();
+        };
+        p.trail
+            .iter()
+            .map(|s| {
+                let (start, end) = (s.start_pos(), s.end_pos());
+                let mut map = Map::new();
+                map.insert("x1".into(), Dynamic::from_float(start.x as FLOAT));
+                map.insert("y1".into(), Dynamic::from_float(start.y as FLOAT));
+                map.insert("x2".into(), Dynamic::from_float(end.x as FLOAT));
+                map.insert("y2".into(), Dynamic::from_float(end.y as FLOAT));
+                map.insert("gap".into(), Dynamic::from_bool(s.gap()));
+                Dynamic::from_map(map)
+            })
+            .collect()
+    });
+
+    let v = Rc::clone(view);
+    engine.register_fn("items", move || -> Array {
+        let view = v.borrow();
+        view.items
+            .iter()
+            .map(|(kind, pos)| {
+                let mut map = Map::new();
+                map.insert("kind".into(), Dynamic::from(item_name(*kind).to_string()));
+                map.insert("x".into(), Dynamic::from_float(pos.x as FLOAT));
+                map.insert("y".into(), Dynamic::from_float(pos.y as FLOAT));
+                Dynamic::from_map(map)
+            })
+            .collect()
+    });
+
+    engine.register_fn("world_size", || -> Array {
+        vec![
+            Dynamic::from_float(WORLD_SIZE.x as FLOAT),
+            Dynamic::from_float(WORLD_SIZE.y as FLOAT),
+        ]
+    });
+
+    let v = Rc::clone(view);
+    engine.register_fn("time", move || -> FLOAT {
+        let view = v.borrow();
+        view.now.saturating_sub(view.round_start).as_secs_f64() as FLOAT
+    });
+
+    engine.register_fn("random", |min: FLOAT, max: FLOAT| -> FLOAT {
+        if min < max {
+            rand::thread_rng().gen_range(min..max)
+        } else {
+            min
+        }
+    });
+
+    let v = Rc::clone(view);
+    engine.register_fn(
+        "add_effect",
+        move |player_id: INT, kind: &str, secs: FLOAT| -> Result<(), Box<rhai::EvalAltResult>> {
+            let kind = match kind {
+                "ghost" => PlayerEffect::Ghost,
+                "no_gap" => PlayerEffect::NoGap,
+                _ => return Err(format!("unknown effect `{kind}` without an amount").into()),
+            };
+            push_effect(&v, player_id, kind, secs)
+        },
+    );
+
+    let v = Rc::clone(view);
+    engine.register_fn(
+        "add_effect",
+        move |player_id: INT,
+              kind: &str,
+              amount: FLOAT,
+              secs: FLOAT|
+              -> Result<(), Box<rhai::EvalAltResult>> {
+            let amount = amount as f32;
+            let kind = match kind {
+                "speed" => PlayerEffect::Speed(amount),
+                "turning" => PlayerEffect::Turning(amount),
+                "size" => PlayerEffect::Size(amount),
+                _ => return Err(format!("unknown effect `{kind}` with an amount").into()),
+            };
+            push_effect(&v, player_id, kind, secs)
+        },
+    );
+
+    let v = Rc::clone(view);
+    engine.register_fn(
+        "spawn_item",
+        move |kind: &str, x: FLOAT, y: FLOAT| -> Result<(), Box<rhai::EvalAltResult>> {
+            let Some(kind) = ITEM_KINDS.iter().find(|k| item_name(**k) == kind) else {
+                return Err(format!("unknown item `{kind}`").into());
+            };
+            v.borrow_mut().commands.push(ScriptCommand::SpawnItem {
+                kind: *kind,
+                pos: Pos2::new(x as f32, y as f32),
+            });
+            Ok(())
+        },
+    );
+
+    let v = Rc::clone(view);
+    engine.register_fn("award_score", move |player_id: INT, points: INT| {
+        let Ok(player_id) = u16::try_from(player_id) else {
+            return;
+        };
+        v.borrow_mut().commands.push(ScriptCommand::AwardScore {
+            player_id,
+            points: points.clamp(i32::MIN as INT, i32::MAX as INT) as i32,
+        });
+    });
+
+    let v = Rc::clone(view);
+    engine.register_fn(
+        "draw_circle",
+        move |x: FLOAT, y: FLOAT, radius: FLOAT, color: INT| {
+            let [_, r, g, b] = (color as u32).to_be_bytes();
+            v.borrow_mut().markers.push(Marker {
+                pos: Pos2::new(x as f32, y as f32),
+                radius: radius as f32,
+                color: Color32::from_rgb(r, g, b),
+            });
+        },
+    );
+}
+
+fn push_effect(
+    view: &RefCell<ScriptView>,
+    player_id: INT,
+    kind: PlayerEffect,
+    secs: FLOAT,
+) -> Result<(), Box<rhai::EvalAltResult>> {
+    let Ok(player_id) = u16::try_from(player_id) else {
+        return Err(format!("invalid player id `{player_id}`").into());
+    };
+    let Ok(duration) = Duration::try_from_secs_f64(secs) else {
+        return Err(format!("invalid duration `{secs}`").into());
+    };
+    view.borrow_mut().commands.push(ScriptCommand::AddEffect {
+        player_id,
+        kind,
+        duration,
+    });
+    Ok(())
+}
+
+/// The name of an item kind used by scripts.
+pub const fn item_name(kind: ItemKind) -> &'static str {
+    match kind {
+        ItemKind::Speedup => "speedup",
+        ItemKind::Slowdown => "slowdown",
+        ItemKind::FastTurning => "fast_turning",
+        ItemKind::SlowTurning => "slow_turning",
+        ItemKind::Expand => "expand",
+        ItemKind::Shrink => "shrink",
+        ItemKind::Ghost => "ghost",
+        ItemKind::NoGap => "no_gap",
+        ItemKind::WallTeleporting => "wall_teleporting",
+        ItemKind::Clear => "clear",
+    }
+}
+
+fn color_to_int(color: Color32) -> INT {
+    let [r, g, b, _] = color.to_array();
+    u32::from_be_bytes([0, r, g, b]) as INT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running_world() -> World {
+        let mut world = World::new();
+        for _ in 0..3 {
+            world.add_player();
+        }
+        world.state = GameState::Running(Duration::ZERO);
+        world
+    }
+
+    fn round_start() -> [WorldEvent; 1] {
+        [WorldEvent::StateChanged {
+            from: GameState::Starting(Duration::ZERO),
+            to: GameState::Running(Duration::ZERO),
+        }]
+    }
+
+    #[test]
+    fn builtin_modes_run() {
+        let mut world = running_world();
+        for (name, script) in BUILTIN_MODES.iter() {
+            let mut host = ScriptHost::new(script).unwrap();
+            host.run(&mut world, &round_start(), Duration::ZERO);
+            for _ in 0..10 {
+                host.run(&mut world, &[], Duration::from_millis(100));
+            }
+            assert!(!host.failed, "`{name}` failed");
+        }
+    }
+
+    #[test]
+    fn endless_hook_is_disabled() {
+        let script = "
+            fn on_tick(dt) {
+                this.ticks += 1;
+                loop {}
+            }
+            fn on_round_start() {
+                this.ticks = 0;
+            }
+        ";
+        let mut world = running_world();
+        let mut host = ScriptHost::new(script).unwrap();
+        host.run(&mut world, &round_start(), Duration::from_millis(100));
+        assert!(host.failed);
+
+        // disabled for the rest of the round
+        host.run(&mut world, &[], Duration::from_millis(100));
+        let ticks = host.this.read_lock::<Map>().unwrap()["ticks"].as_int();
+        assert_eq!(ticks, Ok(1));
+    }
+
+    #[test]
+    fn endless_script_is_rejected() {
+        let res = ScriptHost::new("let i = 0; while true { i += 1; }");
+        assert!(res.is_err());
+    }
+}
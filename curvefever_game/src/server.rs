@@ -1,18 +1,28 @@
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use async_channel::{Receiver, Sender};
+use async_channel::{Receiver, Sender, TrySendError};
 use axum::body::Body;
 use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{State, WebSocketUpgrade};
-use axum::http::{header, HeaderValue, Response};
+use axum::extract::{RawQuery, Request, State, WebSocketUpgrade};
+use axum::http::{header, HeaderValue, Response, StatusCode};
+use axum::middleware::Next;
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::IntoResponse;
-use axum::routing::{get, MethodRouter};
+use axum::routing::{get, post, MethodRouter};
 use axum::Router;
-use curvefever_common::{ClientEvent, GameEvent};
+use curvefever_common::json::{FromJson, JsonValue, ToJson};
+use curvefever_common::{ClientEvent, Decode, Encode, GameEvent, POOR_RTT_MS};
 use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
 use tokio::net::TcpListener;
-use tokio::sync::RwLock;
+use tokio::sync::{oneshot, RwLock};
+
+use crate::api::{
+    AddPlayer, ApiError, ApiRequest, ApiSettings, HostAction, HostCommand, HostPlayer, ServerEvent,
+    StreamEvent,
+};
 
 #[rustfmt::skip]
 mod files {
@@ -21,12 +31,19 @@ mod files {
     pub const APP_WASM: &[u8] = include_bytes!("../../curvefever_remote/dist/curvefever_remote_bg.wasm");
     pub const MANIFEST_JSON: &[u8] = include_bytes!("../../curvefever_remote/dist/manifest.json");
     pub const SW_JS: &[u8] = include_bytes!("../../curvefever_remote/dist/sw.js");
+    pub const HOST_HTML: &[u8] = include_bytes!("../host/index.html");
 }
 
 struct AppState {
     next_session_id: u64,
-    server_sender: Sender<ClientEvent>,
+    server_sender: Sender<ServerEvent>,
+    api_sender: Sender<ApiRequest>,
     sessions: Vec<Session>,
+    event_streams: Vec<Sender<StreamEvent>>,
+    /// The timestamps of pings are milliseconds since this instant.
+    start: Instant,
+    /// Required by the host dashboard, see [`require_host_token`].
+    host_token: String,
 }
 
 impl AppState {
@@ -40,12 +57,57 @@ impl AppState {
 struct Session {
     id: u64,
     sender: Sender<GameEvent>,
+    /// The players this session sent events for.
+    player_ids: Vec<u16>,
+    /// The rolling round trip time, `None` until the first pong is received.
+    rtt: Option<Duration>,
+}
+
+/// The number of stream events buffered for an `/events` subscriber, further events are dropped
+/// until it catches up.
+const EVENT_STREAM_CAPACITY: usize = 256;
+/// How often sessions are pinged to measure their round trip time.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+/// The rolling round trip time moves by `1 / RTT_SMOOTHING` of the difference to each new sample.
+const RTT_SMOOTHING: u32 = 8;
+/// The header the host dashboard sends its token in, the page itself is opened with a `token`
+/// query parameter.
+const HOST_TOKEN_HEADER: &str = "x-host-token";
+
+/// The encoding of game events sent to a client, chosen when connecting using
+/// `/join?protocol=json`. Client events are accepted in both encodings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Protocol {
+    /// Binary messages, see [`curvefever_common::Encode`].
+    Binary,
+    /// Text messages, see [`curvefever_common::json`].
+    Json,
+}
+
+impl Protocol {
+    fn from_query(query: Option<&str>) -> Self {
+        let json = query.is_some_and(|q| q.split('&').any(|p| p == "protocol=json"));
+        if json {
+            Self::Json
+        } else {
+            Self::Binary
+        }
+    }
+}
+
+/// A random token, which has to be passed to the host dashboard.
+pub fn new_host_token() -> String {
+    let bytes: [u8; 16] = rand::random();
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
 }
 
 pub fn start_server(
-    server_sender: Sender<ClientEvent>,
+    server_sender: Sender<ServerEvent>,
+    api_sender: Sender<ApiRequest>,
     game_receiver: Receiver<GameEvent>,
-    kill_signal: tokio::sync::oneshot::Receiver<()>,
+    stream_receiver: Receiver<StreamEvent>,
+    kill_signal: oneshot::Receiver<()>,
+    host_token: String,
 ) {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_io()
@@ -54,67 +116,177 @@ pub fn start_server(
         .unwrap();
 
     runtime.block_on(async {
-        let state = Arc::new(RwLock::new(AppState {
-            next_session_id: 0,
+        let listener = TcpListener::bind(&"0.0.0.0:8910").await.unwrap();
+        serve(
+            listener,
             server_sender,
-            sessions: Vec::new(),
-        }));
-
-        let state_ref = Arc::clone(&state);
-        tokio::spawn(async move {
-            loop {
-                let Ok(event) = game_receiver.recv().await else {
-                    tracing::debug!("Exiting game message loop");
-                    break;
-                };
-
-                let state = state_ref.read().await;
-                for c in state.sessions.iter() {
-                    let res = c.sender.send(event.clone()).await;
-                    if let Err(e) = res {
-                        tracing::error!("Error sending game event to client session:\n{e}");
+            api_sender,
+            game_receiver,
+            stream_receiver,
+            kill_signal,
+            host_token,
+        )
+        .await;
+    });
+}
+
+async fn serve(
+    listener: TcpListener,
+    server_sender: Sender<ServerEvent>,
+    api_sender: Sender<ApiRequest>,
+    game_receiver: Receiver<GameEvent>,
+    stream_receiver: Receiver<StreamEvent>,
+    kill_signal: oneshot::Receiver<()>,
+    host_token: String,
+) {
+    let state = Arc::new(RwLock::new(AppState {
+        next_session_id: 0,
+        server_sender,
+        api_sender,
+        sessions: Vec::new(),
+        event_streams: Vec::new(),
+        start: Instant::now(),
+        host_token,
+    }));
+
+    let state_ref = Arc::clone(&state);
+    tokio::spawn(async move {
+        loop {
+            let Ok(event) = game_receiver.recv().await else {
+                tracing::debug!("Exiting game message loop");
+                break;
+            };
+
+            let state = state_ref.read().await;
+            for c in state.sessions.iter() {
+                if let GameEvent::PlayerFeedback { player_id, .. } = &event {
+                    if !c.player_ids.contains(player_id) {
+                        continue;
                     }
                 }
+                let res = c.sender.send(event.clone()).await;
+                if let Err(e) = res {
+                    tracing::error!("Error sending game event to client session:\n{e}");
+                }
             }
-        });
+        }
+    });
 
-        let app = Router::new()
-            .route(
-                "/",
-                get_embedded_file("text/html; charset=utf-8", files::INDEX_HTML),
-            )
-            .route(
-                "/index.html",
-                get_embedded_file("text/html; charset=utf-8", files::INDEX_HTML),
-            )
-            .route(
-                "/curvefever_remote.js",
-                get_embedded_file("text/javascript; charset=utf-8", files::APP_JS),
-            )
-            .route(
-                "/curvefever_remote_bg.wasm",
-                get_embedded_file("application/wasm", files::APP_WASM),
-            )
-            .route(
-                "/manifest.json",
-                get_embedded_file("application/json", files::MANIFEST_JSON),
-            )
-            .route("/sw.js", get_embedded_file("text/javascript", files::SW_JS))
-            .route("/join", get(ws_handler))
-            .with_state(state);
+    let state_ref = Arc::clone(&state);
+    tokio::spawn(async move {
+        loop {
+            let Ok(event) = stream_receiver.recv().await else {
+                tracing::debug!("Exiting stream event loop");
+                break;
+            };
 
-        let listener = TcpListener::bind(&"0.0.0.0:8910").await.unwrap();
-        axum::serve(listener, app)
-            .with_graceful_shutdown(async { kill_signal.await.unwrap() })
-            .await
-            .unwrap();
+            let mut state = state_ref.write().await;
+            state
+                .event_streams
+                .retain(|s| match s.try_send(event.clone()) {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) => {
+                        tracing::warn!("Event stream is lagging, dropping event");
+                        true
+                    }
+                    Err(TrySendError::Closed(_)) => false,
+                });
+        }
     });
+
+    let state_ref = Arc::clone(&state);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PING_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let state = state_ref.read().await;
+            let timestamp = state.start.elapsed().as_millis() as u64;
+            for c in state.sessions.iter() {
+                // fails if the session is closing, which is handled by the receiver task
+                _ = c.sender.try_send(GameEvent::Ping { timestamp });
+            }
+        }
+    });
+
+    let app = Router::new()
+        .route(
+            "/",
+            get_embedded_file("text/html; charset=utf-8", files::INDEX_HTML),
+        )
+        .route(
+            "/index.html",
+            get_embedded_file("text/html; charset=utf-8", files::INDEX_HTML),
+        )
+        .route(
+            "/curvefever_remote.js",
+            get_embedded_file("text/javascript; charset=utf-8", files::APP_JS),
+        )
+        .route(
+            "/curvefever_remote_bg.wasm",
+            get_embedded_file("application/wasm", files::APP_WASM),
+        )
+        .route(
+            "/manifest.json",
+            get_embedded_file("application/json", files::MANIFEST_JSON),
+        )
+        .route("/sw.js", get_embedded_file("text/javascript", files::SW_JS))
+        .route("/join", get(ws_handler))
+        .route("/events", get(event_stream))
+        .route("/api/state", get(api_state))
+        .route("/api/players", get(api_players).post(api_add_player))
+        .route("/api/restart", post(api_restart))
+        .route("/api/pause", post(api_pause))
+        .route("/api/settings", get(api_settings).put(api_update_settings))
+        .merge(host_routes(&state))
+        .with_state(state);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async { kill_signal.await.unwrap() })
+        .await
+        .unwrap();
+}
+
+/// The host dashboard can change and kick every player, so it's only available with the host
+/// token.
+fn host_routes(state: &Arc<RwLock<AppState>>) -> Router<Arc<RwLock<AppState>>> {
+    Router::new()
+        .route(
+            "/host",
+            get_embedded_file("text/html; charset=utf-8", files::HOST_HTML),
+        )
+        .route("/api/host", post(api_host_action))
+        .route("/api/host/players", get(api_host_players))
+        .route_layer(axum::middleware::from_fn_with_state(
+            Arc::clone(state),
+            require_host_token,
+        ))
+}
+
+/// Accepts the host token either in the [`HOST_TOKEN_HEADER`] or as the `token` query parameter.
+async fn require_host_token(
+    State(state): State<Arc<RwLock<AppState>>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    let header = request
+        .headers()
+        .get(HOST_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let query = request.uri().query().unwrap_or_default();
+    let param = query.split('&').find_map(|p| p.strip_prefix("token="));
+    let token = header.or(param);
+
+    if token != Some(state.read().await.host_token.as_str()) {
+        return error_response(StatusCode::UNAUTHORIZED, "invalid host token");
+    }
+    next.run(request).await
 }
 
 fn get_embedded_file<T>(
     content_type: &'static str,
     bytes: &'static [u8],
-) -> MethodRouter<T, std::convert::Infallible>
+) -> MethodRouter<T, Infallible>
 where
     T: Clone + Send + Sync + 'static,
 {
@@ -134,63 +306,272 @@ async fn serve_embedded_file(
     resp
 }
 
+fn json_response(status: StatusCode, value: &impl ToJson) -> Response<Body> {
+    let mut resp = Response::new(Body::from(value.to_json().to_string()));
+    *resp.status_mut() = status;
+    let headers = resp.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    resp
+}
+
+fn error_response(status: StatusCode, msg: &str) -> Response<Body> {
+    let error = JsonValue::Object(vec![("error".into(), msg.to_json())]);
+    json_response(status, &error)
+}
+
+fn api_error_response(error: ApiError) -> Response<Body> {
+    match error {
+        ApiError::Invalid(msg) => error_response(StatusCode::BAD_REQUEST, &msg),
+        ApiError::Conflict(msg) => error_response(StatusCode::CONFLICT, msg),
+        ApiError::Unavailable => {
+            error_response(StatusCode::SERVICE_UNAVAILABLE, "the game isn't running")
+        }
+    }
+}
+
+/// Sends a request to the simulation and waits for the reply.
+async fn api_request<T>(
+    state: &RwLock<AppState>,
+    request: impl FnOnce(oneshot::Sender<T>) -> ApiRequest,
+) -> Result<T, ApiError> {
+    let (reply, receiver) = oneshot::channel();
+    let api_sender = state.read().await.api_sender.clone();
+    let res = api_sender.send(request(reply)).await;
+    res.map_err(|_| ApiError::Unavailable)?;
+    receiver.await.map_err(|_| ApiError::Unavailable)
+}
+
+fn parse_body<T: FromJson>(body: &str) -> Result<T, ApiError> {
+    T::from_json_str(body).map_err(|e| ApiError::Invalid(e.to_string()))
+}
+
+async fn api_state(State(state): State<Arc<RwLock<AppState>>>) -> Response<Body> {
+    match api_request(&state, ApiRequest::State).await {
+        Ok(s) => json_response(StatusCode::OK, &s),
+        Err(e) => api_error_response(e),
+    }
+}
+
+async fn api_players(State(state): State<Arc<RwLock<AppState>>>) -> Response<Body> {
+    match api_request(&state, ApiRequest::Players).await {
+        Ok(players) => json_response(StatusCode::OK, &players),
+        Err(e) => api_error_response(e),
+    }
+}
+
+/// The body is optional, see [`AddPlayer`].
+async fn api_add_player(
+    State(state): State<Arc<RwLock<AppState>>>,
+    body: String,
+) -> Response<Body> {
+    let add = if body.trim().is_empty() {
+        Ok(AddPlayer::default())
+    } else {
+        parse_body(&body)
+    };
+    let res = match add {
+        Ok(add) => api_request(&state, |reply| ApiRequest::AddPlayer(add, reply)).await,
+        Err(e) => Err(e),
+    };
+    match res.and_then(|r| r) {
+        Ok(player) => json_response(StatusCode::CREATED, &player),
+        Err(e) => api_error_response(e),
+    }
+}
+
+async fn api_restart(State(state): State<Arc<RwLock<AppState>>>) -> Response<Body> {
+    match api_request(&state, ApiRequest::Restart)
+        .await
+        .and_then(|r| r)
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => api_error_response(e),
+    }
+}
+
+async fn api_pause(State(state): State<Arc<RwLock<AppState>>>) -> Response<Body> {
+    match api_request(&state, ApiRequest::TogglePause)
+        .await
+        .and_then(|r| r)
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => api_error_response(e),
+    }
+}
+
+async fn api_settings(State(state): State<Arc<RwLock<AppState>>>) -> Response<Body> {
+    match api_request(&state, ApiRequest::Settings).await {
+        Ok(settings) => json_response(StatusCode::OK, &settings),
+        Err(e) => api_error_response(e),
+    }
+}
+
+/// Only the fields present in the body are changed, see [`ApiSettings`].
+async fn api_update_settings(
+    State(state): State<Arc<RwLock<AppState>>>,
+    body: String,
+) -> Response<Body> {
+    let res = match parse_body::<ApiSettings>(&body) {
+        Ok(update) => api_request(&state, |reply| ApiRequest::UpdateSettings(update, reply)).await,
+        Err(e) => Err(e),
+    };
+    match res.and_then(|r| r) {
+        Ok(settings) => json_response(StatusCode::OK, &settings),
+        Err(e) => api_error_response(e),
+    }
+}
+
+/// The players with the sessions controlling them.
+async fn api_host_players(State(state): State<Arc<RwLock<AppState>>>) -> Response<Body> {
+    let mut players: Vec<HostPlayer> = match api_request(&state, ApiRequest::HostPlayers).await {
+        Ok(players) => players,
+        Err(e) => return api_error_response(e),
+    };
+    let state = state.read().await;
+    for p in players.iter_mut() {
+        let sessions = state
+            .sessions
+            .iter()
+            .filter(|s| s.player_ids.contains(&p.id));
+        p.sessions = sessions.map(|s| s.id).collect();
+    }
+    json_response(StatusCode::OK, &players)
+}
+
+/// Most changes are applied by the simulation like client events, so this doesn't wait for them.
+/// Removing a player waits for the simulation, since it can be rejected.
+async fn api_host_action(
+    State(state): State<Arc<RwLock<AppState>>>,
+    body: String,
+) -> Response<Body> {
+    let command = match parse_body::<HostAction>(&body).and_then(HostAction::into_command) {
+        Ok(c) => c,
+        Err(e) => return api_error_response(e),
+    };
+
+    match command {
+        HostCommand::Event(event) => {
+            let server_sender = state.read().await.server_sender.clone();
+            if server_sender.send(event).await.is_err() {
+                return api_error_response(ApiError::Unavailable);
+            }
+            StatusCode::ACCEPTED.into_response()
+        }
+        HostCommand::RemovePlayer { player_id, kick } => {
+            let res = api_request(&state, |reply| ApiRequest::RemovePlayer(player_id, reply)).await;
+            if let Err(e) = res.and_then(|r| r) {
+                return api_error_response(e);
+            }
+
+            // only disconnect the remotes once the player is actually gone
+            if kick {
+                let state = state.read().await;
+                let sessions = state.sessions.iter();
+                for s in sessions.filter(|s| s.player_ids.contains(&player_id)) {
+                    tracing::debug!("Kicking session with id {}", s.id);
+                    s.sender.close();
+                }
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+}
+
+/// Streams gameplay events as Server-Sent Events, each containing a JSON [`StreamEvent`].
+async fn event_stream(State(state): State<Arc<RwLock<AppState>>>) -> impl IntoResponse {
+    let (sender, receiver) = async_channel::bounded(EVENT_STREAM_CAPACITY);
+    state.write().await.event_streams.push(sender);
+
+    let events =
+        receiver.map(|e| Ok::<_, Infallible>(Event::default().data(e.to_json().to_string())));
+    // allow overlays loaded from other origins, e.g. local files in OBS
+    let cors = [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")];
+    (cors, Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 async fn ws_handler(
     ws: WebSocketUpgrade,
+    RawQuery(query): RawQuery,
     State(state): State<Arc<RwLock<AppState>>>,
 ) -> impl IntoResponse {
+    let protocol = Protocol::from_query(query.as_deref());
     let (sender, receiver) = async_channel::unbounded();
     let server_sender;
     let id;
     {
         let mut state = state.write().await;
         id = state.next_session_id();
-        state.sessions.push(Session { id, sender });
+        state.sessions.push(Session {
+            id,
+            sender,
+            player_ids: Vec::new(),
+            rtt: None,
+        });
         server_sender = state.server_sender.clone();
     }
-    tracing::debug!("Session with id {} connected", id);
+    tracing::debug!("Session with id {} connected using {:?}", id, protocol);
 
-    ws.on_upgrade(move |socket| handle_socket(id, state, socket, server_sender, receiver))
+    ws.on_upgrade(move |socket| handle_socket(id, state, socket, protocol, server_sender, receiver))
 }
 
 async fn handle_socket(
     id: u64,
     state: Arc<RwLock<AppState>>,
     socket: WebSocket,
-    server_sender: Sender<ClientEvent>,
+    protocol: Protocol,
+    server_sender: Sender<ServerEvent>,
     game_receiver: Receiver<GameEvent>,
 ) {
     let (sender, receiver) = socket.split();
 
     tokio::spawn(receiver_task(id, state, receiver, server_sender));
-    tokio::spawn(sender_task(sender, game_receiver));
+    tokio::spawn(sender_task(sender, protocol, game_receiver));
 }
 
 async fn receiver_task(
     id: u64,
     state: Arc<RwLock<AppState>>,
     mut socket: SplitStream<WebSocket>,
-    server_sender: Sender<ClientEvent>,
+    server_sender: Sender<ServerEvent>,
 ) {
+    let mut player_ids = Vec::new();
     while let Some(Ok(msg)) = socket.next().await {
-        let data = match msg {
-            Message::Binary(data) => data,
+        let res = match &msg {
+            Message::Binary(data) => ClientEvent::decode_exact(data).map_err(|e| e.to_string()),
+            Message::Text(text) => ClientEvent::from_json_str(text).map_err(|e| e.to_string()),
             Message::Close(_) => break,
-            Message::Text(_) | Message::Ping(_) | Message::Pong(_) => {
-                tracing::warn!("Expected binary message: {:?}", msg);
+            Message::Ping(_) | Message::Pong(_) => {
+                tracing::warn!("Expected binary or text message: {:?}", msg);
                 continue;
             }
         };
-
-        let mut cursor = std::io::Cursor::new(&data);
-        let event = match ClientEvent::decode(&mut cursor) {
+        let event = match res {
             Ok(e) => e,
             Err(e) => {
-                tracing::warn!("Error decoding message `{:?}`:\n{e}", data.as_slice());
+                tracing::warn!("Error decoding message `{:?}`:\n{e}", msg);
                 continue;
             }
         };
 
-        let res = server_sender.send(event).await;
+        if let ClientEvent::Pong { timestamp } = event {
+            handle_pong(id, &state, &server_sender, timestamp).await;
+            continue;
+        }
+
+        if let Some(player_id) = event_player_id(&event) {
+            if !player_ids.contains(&player_id) {
+                player_ids.push(player_id);
+                let mut state = state.write().await;
+                if let Some(s) = state.sessions.iter_mut().find(|s| s.id == id) {
+                    s.player_ids.push(player_id);
+                }
+            }
+        }
+
+        let res = server_sender.send(ServerEvent::Client(event)).await;
         if let Err(e) = res {
             tracing::error!("Error sending client event to server:\n{e}");
         }
@@ -201,11 +582,99 @@ async fn receiver_task(
         tracing::debug!("Session with id {} disconnected", id);
         let session = state.sessions.remove(i);
         session.sender.close();
+        drop(state);
+
+        for player_id in session.player_ids {
+            let res = server_sender
+                .send(ServerEvent::Latency {
+                    player_id,
+                    rtt: None,
+                })
+                .await;
+            if let Err(e) = res {
+                tracing::error!("Error sending latency to server:\n{e}");
+            }
+        }
+    }
+}
+
+/// Updates the rolling round trip time of a session, and reports it to the client and the
+/// simulation.
+async fn handle_pong(
+    id: u64,
+    state: &RwLock<AppState>,
+    server_sender: &Sender<ServerEvent>,
+    timestamp: u64,
+) {
+    let rtt;
+    let player_ids;
+    {
+        let mut state = state.write().await;
+        let elapsed = state.start.elapsed();
+        let Some(sample) = elapsed.checked_sub(Duration::from_millis(timestamp)) else {
+            tracing::warn!("Received pong from the future: {timestamp}");
+            return;
+        };
+        let Some(session) = state.sessions.iter_mut().find(|s| s.id == id) else {
+            return;
+        };
+
+        let prev_rtt = session.rtt;
+        rtt = match prev_rtt {
+            Some(prev) => (prev * (RTT_SMOOTHING - 1) + sample) / RTT_SMOOTHING,
+            None => sample,
+        };
+        session.rtt = Some(rtt);
+        player_ids = session.player_ids.clone();
+
+        if is_poor_rtt(rtt) && !prev_rtt.is_some_and(is_poor_rtt) {
+            tracing::warn!(
+                "Session with id {} has a poor connection, round trip time {} ms",
+                id,
+                rtt.as_millis()
+            );
+        }
+
+        let rtt_ms = u32::try_from(rtt.as_millis()).unwrap_or(u32::MAX);
+        // fails if the session is closing, which is handled by the receiver task
+        _ = session.sender.try_send(GameEvent::Latency { rtt_ms });
+    }
+
+    for player_id in player_ids {
+        let rtt = Some(rtt);
+        let res = server_sender
+            .send(ServerEvent::Latency { player_id, rtt })
+            .await;
+        if let Err(e) = res {
+            tracing::error!("Error sending latency to server:\n{e}");
+        }
+    }
+}
+
+fn is_poor_rtt(rtt: Duration) -> bool {
+    rtt > Duration::from_millis(POOR_RTT_MS as u64)
+}
+
+fn event_player_id(event: &ClientEvent) -> Option<u16> {
+    match event {
+        ClientEvent::Input { player_id, .. }
+        | ClientEvent::AnalogInput { player_id, .. }
+        | ClientEvent::Rename { player_id, .. }
+        | ClientEvent::PrevColor { player_id }
+        | ClientEvent::NextColor { player_id } => Some(*player_id),
+        ClientEvent::SyncPlayers
+        | ClientEvent::AddPlayer { .. }
+        | ClientEvent::Restart
+        | ClientEvent::Pause
+        | ClientEvent::Share
+        | ClientEvent::Help
+        | ClientEvent::Pong { .. } => None,
     }
 }
 
 async fn sender_task(
     mut socket: SplitSink<WebSocket, Message>,
+    protocol: Protocol,
     game_receiver: Receiver<GameEvent>,
 ) {
     loop {
@@ -213,13 +682,489 @@ async fn sender_task(
             break;
         };
 
-        let mut buf = Vec::new();
-        event.encode(&mut buf).expect("should always succeed");
-        let msg = Message::Binary(buf);
+        let msg = match protocol {
+            Protocol::Binary => {
+                let mut buf = Vec::new();
+                if let Err(e) = event.encode(&mut buf) {
+                    tracing::error!("Error encoding game event `{event:?}`:\n{e}");
+                    continue;
+                }
+                Message::Binary(buf)
+            }
+            Protocol::Json => Message::Text(event.to_json().to_string()),
+        };
 
         let res = socket.send(msg).await;
         if let Err(e) = res {
             tracing::warn!("Error sending game event to client socket: {e}");
         }
     }
+
+    // the session was closed by the server, e.g. because it was kicked, this fails if the
+    // client already disconnected
+    _ = socket.send(Message::Close(None)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::api::{self, HostEvent};
+    use crate::world::World;
+
+    const HOST_TOKEN: &str = "0123456789abcdef";
+
+    /// A server on a random local port, with a simulation thread answering API requests.
+    struct TestInstance {
+        addr: SocketAddr,
+        server_receiver: Receiver<ServerEvent>,
+        stream_sender: Sender<StreamEvent>,
+        kill_signal: Option<oneshot::Sender<()>>,
+    }
+
+    impl TestInstance {
+        fn start() -> Self {
+            let (server_sender, server_receiver) = async_channel::unbounded();
+            let (api_sender, api_receiver) = async_channel::unbounded();
+            let (_game_sender, game_receiver) = async_channel::unbounded();
+            let (stream_sender, stream_receiver) = async_channel::unbounded();
+            let (kill_signal, kill_receiver) = oneshot::channel();
+
+            std::thread::spawn(move || {
+                let mut world = World::new();
+                while let Ok(r) = api_receiver.recv_blocking() {
+                    api::handle_request(&mut world, r);
+                }
+            });
+
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .enable_time()
+                .build()
+                .unwrap();
+            let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+            let addr = listener.local_addr().unwrap();
+            std::thread::spawn(move || {
+                runtime.block_on(serve(
+                    listener,
+                    server_sender,
+                    api_sender,
+                    game_receiver,
+                    stream_receiver,
+                    kill_receiver,
+                    HOST_TOKEN.into(),
+                ));
+            });
+
+            Self {
+                addr,
+                server_receiver,
+                stream_sender,
+                kill_signal: Some(kill_signal),
+            }
+        }
+
+        /// Sends a request with extra `headers`, each ending in `\r\n`, and returns the status code
+        /// and the body.
+        fn raw_request(
+            &self,
+            method: &str,
+            path: &str,
+            headers: &str,
+            body: &str,
+        ) -> (u16, String) {
+            let mut stream = std::net::TcpStream::connect(self.addr).unwrap();
+            write!(
+                stream,
+                "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                {headers}Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+            (status, body.to_string())
+        }
+
+        /// Sends a request and returns the status code and the parsed body.
+        fn request(&self, method: &str, path: &str, body: &str) -> (u16, JsonValue) {
+            self.json_request(method, path, "", body)
+        }
+
+        /// Sends a request of the host dashboard, with the host token.
+        fn host_request(&self, method: &str, path: &str, body: &str) -> (u16, JsonValue) {
+            let header = format!("{HOST_TOKEN_HEADER}: {HOST_TOKEN}\r\n");
+            self.json_request(method, path, &header, body)
+        }
+
+        fn json_request(
+            &self,
+            method: &str,
+            path: &str,
+            headers: &str,
+            body: &str,
+        ) -> (u16, JsonValue) {
+            let (status, body) = self.raw_request(method, path, headers, body);
+            let body = if body.is_empty() {
+                JsonValue::Null
+            } else {
+                JsonValue::parse(&body).unwrap()
+            };
+            (status, body)
+        }
+    }
+
+    impl Drop for TestInstance {
+        fn drop(&mut self) {
+            if let Some(kill_signal) = self.kill_signal.take() {
+                _ = kill_signal.send(());
+            }
+        }
+    }
+
+    fn string(value: &JsonValue, key: &str) -> String {
+        curvefever_common::json::field(value, key).unwrap()
+    }
+
+    #[test]
+    fn state() {
+        let instance = TestInstance::start();
+        let (status, state) = instance.request("GET", "/api/state", "");
+        assert_eq!(status, 200);
+        assert_eq!(string(&state, "state"), "stopped");
+        let players: Vec<JsonValue> = curvefever_common::json::field(&state, "players").unwrap();
+        assert_eq!(players.len(), 2);
+        assert_eq!(string(&players[0], "name"), "Player1");
+    }
+
+    #[test]
+    fn add_player() {
+        let instance = TestInstance::start();
+        let (status, player) = instance.request("POST", "/api/players", r#"{"name":"Bot"}"#);
+        assert_eq!(status, 201);
+        assert_eq!(string(&player, "name"), "Bot");
+
+        let (status, _) = instance.request("POST", "/api/players", "");
+        assert_eq!(status, 201);
+
+        let (status, players) = instance.request("GET", "/api/players", "");
+        assert_eq!(status, 200);
+        let JsonValue::Array(players) = players else {
+            panic!("expected array");
+        };
+        assert_eq!(players.len(), 4);
+
+        let (status, error) = instance.request("POST", "/api/players", r#"{"name":1}"#);
+        assert_eq!(status, 400);
+        assert!(error.get("error").is_some());
+    }
+
+    #[test]
+    fn restart_and_pause() {
+        let instance = TestInstance::start();
+        let (status, _) = instance.request("POST", "/api/pause", "");
+        assert_eq!(status, 409);
+
+        let (status, _) = instance.request("POST", "/api/restart", "");
+        assert_eq!(status, 204);
+        let (_, state) = instance.request("GET", "/api/state", "");
+        assert_eq!(string(&state, "state"), "starting");
+
+        let (status, _) = instance.request("POST", "/api/restart", "");
+        assert_eq!(status, 409);
+        let (status, _) = instance.request("POST", "/api/players", "");
+        assert_eq!(status, 409);
+    }
+
+    #[test]
+    fn settings() {
+        let instance = TestInstance::start();
+        let (status, settings) = instance.request("GET", "/api/settings", "");
+        assert_eq!(status, 200);
+        assert_eq!(string(&settings, "palette"), "default");
+
+        let update = r#"{"palette":"high_contrast","volume":0.25,"sudden_death":"shrink"}"#;
+        let (status, settings) = instance.request("PUT", "/api/settings", update);
+        assert_eq!(status, 200);
+        assert_eq!(string(&settings, "palette"), "high_contrast");
+        assert_eq!(string(&settings, "sudden_death"), "shrink");
+        let volume: f32 = curvefever_common::json::field(&settings, "volume").unwrap();
+        assert_eq!(volume, 0.25);
+
+        // invalid updates don't change anything
+        let update = r#"{"palette":"default","volume":2}"#;
+        let (status, _) = instance.request("PUT", "/api/settings", update);
+        assert_eq!(status, 400);
+        let (_, settings) = instance.request("GET", "/api/settings", "");
+        assert_eq!(string(&settings, "palette"), "high_contrast");
+
+        let (status, _) = instance.request("PUT", "/api/settings", "{");
+        assert_eq!(status, 400);
+    }
+
+    /// Reads from the `stream` until the received text contains `pat`.
+    fn read_until(stream: &mut std::net::TcpStream, pat: &str) -> String {
+        let mut received = Vec::new();
+        let mut buf = [0; 1024];
+        while !String::from_utf8_lossy(&received).contains(pat) {
+            let n = stream.read(&mut buf).unwrap();
+            assert!(n > 0, "connection closed");
+            received.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8(received).unwrap()
+    }
+
+    #[test]
+    fn event_stream() {
+        let instance = TestInstance::start();
+        let mut stream = std::net::TcpStream::connect(instance.addr).unwrap();
+        write!(stream, "GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        let head = read_until(&mut stream, "\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(head.contains("text/event-stream"));
+
+        let event = StreamEvent::ScoreChanged {
+            player_id: 1,
+            score: 3,
+        };
+        instance.stream_sender.send_blocking(event).unwrap();
+        let response = read_until(&mut stream, "}\n\n");
+        let data = response.split("data: ").nth(1).unwrap();
+        let data = JsonValue::parse(data.split('\n').next().unwrap()).unwrap();
+        assert_eq!(string(&data, "type"), "score_changed");
+        let score: u16 = curvefever_common::json::field(&data, "score").unwrap();
+        assert_eq!(score, 3);
+    }
+
+    /// A minimal websocket client for `/join?protocol=json`, only sending and receiving short text
+    /// frames.
+    struct TestSocket(std::net::TcpStream);
+
+    impl TestSocket {
+        fn connect(addr: SocketAddr) -> Self {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "GET /join?protocol=json HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n\
+                Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+            )
+            .unwrap();
+
+            // read byte by byte, so no frames sent right after the response are consumed
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                stream.read_exact(&mut byte).unwrap();
+                head.push(byte[0]);
+            }
+            assert!(head.starts_with(b"HTTP/1.1 101"));
+            Self(stream)
+        }
+
+        fn send(&mut self, text: &str) {
+            assert!(text.len() < 126);
+            // client frames have to be masked, a zero mask leaves the payload unchanged
+            let mut frame = vec![0x81, 0x80 | text.len() as u8, 0, 0, 0, 0];
+            frame.extend_from_slice(text.as_bytes());
+            self.0.write_all(&frame).unwrap();
+        }
+
+        fn recv(&mut self) -> JsonValue {
+            let mut header = [0; 2];
+            self.0.read_exact(&mut header).unwrap();
+            assert_eq!(header[0], 0x81, "expected a text frame");
+            assert!(header[1] < 126);
+            let mut payload = vec![0; header[1] as usize];
+            self.0.read_exact(&mut payload).unwrap();
+            JsonValue::parse(std::str::from_utf8(&payload).unwrap()).unwrap()
+        }
+    }
+
+    #[test]
+    fn ping_pong() {
+        let instance = TestInstance::start();
+        let mut socket = TestSocket::connect(instance.addr);
+        socket.send(r#"{"type":"input","player_id":1,"dir":"left"}"#);
+        let event = instance.server_receiver.recv_blocking().unwrap();
+        assert!(matches!(
+            event,
+            ServerEvent::Client(ClientEvent::Input { player_id: 1, .. })
+        ));
+
+        let ping = socket.recv();
+        assert_eq!(string(&ping, "type"), "ping");
+        let timestamp: u64 = curvefever_common::json::field(&ping, "timestamp").unwrap();
+        socket.send(&format!(r#"{{"type":"pong","timestamp":{timestamp}}}"#));
+        let latency = socket.recv();
+        assert_eq!(string(&latency, "type"), "latency");
+
+        let event = instance.server_receiver.recv_blocking().unwrap();
+        let ServerEvent::Latency { player_id, rtt } = event else {
+            panic!("expected latency event, found {event:?}");
+        };
+        assert_eq!(player_id, 1);
+        assert!(rtt.is_some());
+
+        drop(socket);
+        let event = instance.server_receiver.recv_blocking().unwrap();
+        let ServerEvent::Latency { player_id, rtt } = event else {
+            panic!("expected latency event, found {event:?}");
+        };
+        assert_eq!((player_id, rtt), (1, None));
+    }
+
+    #[test]
+    fn host_actions() {
+        let instance = TestInstance::start();
+        let (status, players) = instance.host_request("GET", "/api/host/players", "");
+        assert_eq!(status, 200);
+        let JsonValue::Array(players) = players else {
+            panic!("expected array");
+        };
+        assert_eq!(players.len(), 2);
+        let sessions: Vec<u64> = curvefever_common::json::field(&players[0], "sessions").unwrap();
+        assert!(sessions.is_empty());
+
+        let rename = r#"{"type":"rename","player_id":1,"name":"Host"}"#;
+        let (status, _) = instance.host_request("POST", "/api/host", rename);
+        assert_eq!(status, 202);
+        let event = instance.server_receiver.recv_blocking().unwrap();
+        let ServerEvent::Client(ClientEvent::Rename { player_id, name }) = event else {
+            panic!("expected rename client event, found {event:?}");
+        };
+        assert_eq!((player_id, name.as_str()), (1, "Host"));
+
+        let keys = r#"{"type":"set_keys","player_id":1,"left_key":"ArrowLeft","right_key":null}"#;
+        let (status, _) = instance.host_request("POST", "/api/host", keys);
+        assert_eq!(status, 202);
+        let event = instance.server_receiver.recv_blocking().unwrap();
+        let ServerEvent::Host(HostEvent::SetKeys {
+            left_key,
+            right_key,
+            ..
+        }) = event
+        else {
+            panic!("expected set keys host event, found {event:?}");
+        };
+        assert_eq!((left_key, right_key), (Some(egui::Key::ArrowLeft), None));
+
+        let keys = r#"{"type":"set_keys","player_id":1,"left_key":"NoSuchKey"}"#;
+        let (status, error) = instance.host_request("POST", "/api/host", keys);
+        assert_eq!(status, 400);
+        assert!(error.get("error").is_some());
+        assert!(instance.server_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn host_token() {
+        let instance = TestInstance::start();
+        for (method, path) in [
+            ("GET", "/host"),
+            ("GET", "/api/host/players"),
+            ("POST", "/api/host"),
+        ] {
+            let (status, _) = instance.raw_request(method, path, "", "");
+            assert_eq!(status, 401, "{path}");
+            let (status, _) = instance.raw_request(method, &format!("{path}?token=wrong"), "", "");
+            assert_eq!(status, 401, "{path}");
+            let header = format!("{HOST_TOKEN_HEADER}: wrong\r\n");
+            let (status, _) = instance.raw_request(method, path, &header, "");
+            assert_eq!(status, 401, "{path}");
+        }
+
+        // the dashboard is opened with the token in the url
+        let path = format!("/host?token={HOST_TOKEN}");
+        let (status, body) = instance.raw_request("GET", &path, "", "");
+        assert_eq!(status, 200);
+        assert!(body.contains("<html"));
+
+        let (status, _) = instance.host_request("GET", "/api/host/players", "");
+        assert_eq!(status, 200);
+        // the other routes don't need it
+        let (status, _) = instance.request("GET", "/api/state", "");
+        assert_eq!(status, 200);
+    }
+
+    /// Whether the server closes the websocket within `timeout`, skipping pings.
+    fn closed(socket: &mut TestSocket, timeout: Duration) -> bool {
+        socket.0.set_read_timeout(Some(timeout)).unwrap();
+        let closed = loop {
+            let mut header = [0; 2];
+            match socket.0.read_exact(&mut header) {
+                Ok(()) if header[0] == 0x88 => break true,
+                Ok(()) => {
+                    let mut payload = vec![0; header[1] as usize];
+                    socket.0.read_exact(&mut payload).unwrap();
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break true,
+                Err(_) => break false,
+            }
+        };
+        socket.0.set_read_timeout(None).unwrap();
+        closed
+    }
+
+    #[test]
+    fn kick() {
+        let instance = TestInstance::start();
+        let (_, player) = instance.request("POST", "/api/players", "");
+        let player_id: u16 = curvefever_common::json::field(&player, "id").unwrap();
+
+        let mut socket = TestSocket::connect(instance.addr);
+        socket.send(&format!(
+            r#"{{"type":"input","player_id":{player_id},"dir":"left"}}"#
+        ));
+        instance.server_receiver.recv_blocking().unwrap();
+
+        // rejected while a round is running, the remote stays connected
+        let (status, _) = instance.request("POST", "/api/restart", "");
+        assert_eq!(status, 204);
+        let kick = format!(r#"{{"type":"kick","player_id":{player_id}}}"#);
+        let (status, error) = instance.host_request("POST", "/api/host", &kick);
+        assert_eq!(status, 409);
+        assert!(error.get("error").is_some());
+        assert!(!closed(&mut socket, Duration::from_millis(200)));
+        let (_, players) = instance.request("GET", "/api/players", "");
+        let JsonValue::Array(players) = players else {
+            panic!("expected array");
+        };
+        assert_eq!(players.len(), 3);
+        assert!(instance.server_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn kick_between_rounds() {
+        let instance = TestInstance::start();
+        let kick = |player_id: u16| {
+            let kick = format!(r#"{{"type":"kick","player_id":{player_id}}}"#);
+            instance.host_request("POST", "/api/host", &kick).0
+        };
+
+        // there have to be at least two players
+        assert_eq!(kick(0), 409);
+        assert_eq!(kick(1000), 400);
+
+        let (_, player) = instance.request("POST", "/api/players", "");
+        let player_id: u16 = curvefever_common::json::field(&player, "id").unwrap();
+        let mut socket = TestSocket::connect(instance.addr);
+        socket.send(&format!(
+            r#"{{"type":"input","player_id":{player_id},"dir":"left"}}"#
+        ));
+        instance.server_receiver.recv_blocking().unwrap();
+
+        assert_eq!(kick(player_id), 204);
+        assert!(closed(&mut socket, Duration::from_secs(5)));
+        let (_, players) = instance.request("GET", "/api/players", "");
+        let JsonValue::Array(players) = players else {
+            panic!("expected array");
+        };
+        assert_eq!(players.len(), 2);
+    }
 }
@@ -1,13 +1,23 @@
 use std::f32::consts::{FRAC_PI_2, PI, TAU};
-use std::time::{Duration, SystemTime};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use curvefever_common::Direction;
-use egui::{Color32, Key, Pos2, Vec2};
+use egui::{Color32, Key, Pos2, Rect, Vec2};
 use rand::Rng;
 
-use curvefever_derive::EnumMembersArray;
+use curvefever_derive::{EnumMembersArray, FromJson, ToJson};
+
+use crate::audio::AudioSettings;
+use crate::export::ExportSettings;
+use crate::map::{Map, Wall};
+use crate::particles::VisualEffects;
+use crate::script::{self, GameMode, Marker};
 
 pub const UPDATE_TIME: Duration = Duration::from_nanos(1_000_000_000 / 240);
+/// The maximum number of updates simulated at once to catch up with real time, if the game falls
+/// further behind it will slow down instead.
+pub const MAX_SUB_STEPS: u32 = 8;
 
 pub const WORLD_SIZE: Vec2 = Vec2::new(1280.0, 720.0);
 pub const MIN_PLAYER_WALL_DIST: f32 = 150.0;
@@ -33,6 +43,13 @@ pub const MIN_THICKNESS: f32 = 1.0;
 pub const MAX_THICKNESS: f32 = 16.0;
 pub const BASE_TURNING_RADIUS: f32 = 50.0;
 pub const MIN_TURNING_RADIUS: f32 = 25.0;
+/// Analog steering is rounded to multiples of this, so small changes of e.g. a gamepad stick don't
+/// start a new trail section every update.
+const ANALOG_STEERING_STEP: f32 = 1.0 / 16.0;
+pub const SUDDEN_DEATH_DELAY: Duration = Duration::from_secs(60);
+pub const SUDDEN_DEATH_DURATION: Duration = Duration::from_secs(60);
+/// The number of trail sections in each shared chunk of a [`Trail`].
+const TRAIL_CHUNK_LEN: usize = 64;
 
 pub const ITEM_KINDS: &[ItemKind] = ItemKind::members();
 pub const SUM_OF_ITEM_SPAWN_RATES: u8 = {
@@ -45,20 +62,98 @@ pub const SUM_OF_ITEM_SPAWN_RATES: u8 = {
     sum
 };
 pub const PLAYER_COLORS: &[PlayerColor] = PlayerColor::members();
+pub const PALETTES: &[Palette] = Palette::members();
+pub const TRAIL_PATTERNS: &[TrailPattern] = TrailPattern::members();
+pub const SUDDEN_DEATH_MODES: &[SuddenDeathMode] = SuddenDeathMode::members();
 
+#[derive(Clone)]
 pub struct World {
     next_id: u16,
-    pub is_running: bool,
     pub clock: Clock,
     pub state: GameState,
     pub items: Vec<Item>,
     pub effects: Vec<Effect<WorldEffect>>,
     pub players: Vec<Player>,
     pub crash_feed: Vec<Crash>,
+    pub settings: Settings,
+    /// The map of the current round.
+    pub map: Arc<Map>,
+    /// All available maps.
+    pub maps: Vec<Arc<Map>>,
+    pub map_index: usize,
+    /// All available game modes.
+    pub modes: Arc<[GameMode]>,
+    pub mode_index: usize,
+    /// Shapes drawn by the script of the current game mode.
+    pub markers: Vec<Marker>,
+    /// Events that happened since the last [`World::update`].
+    events: Vec<WorldEvent>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    pub palette: Palette,
+    pub sudden_death: SuddenDeath,
+    pub export: ExportSettings,
+    pub audio: AudioSettings,
+    pub visual_effects: VisualEffects,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SuddenDeath {
+    pub mode: SuddenDeathMode,
+    /// The time after the start of a round at which sudden death begins.
+    pub delay: Duration,
+    /// The time it takes the arena to shrink to nothing, or the speed to double.
+    pub duration: Duration,
+}
+
+impl Default for SuddenDeath {
+    fn default() -> Self {
+        Self {
+            mode: SuddenDeathMode::Off,
+            delay: SUDDEN_DEATH_DELAY,
+            duration: SUDDEN_DEATH_DURATION,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumMembersArray, ToJson, FromJson)]
+pub enum SuddenDeathMode {
+    #[default]
+    Off = 0,
+    /// The playable boundary shrinks linearly towards the center.
+    Shrink = 1,
+    /// The base speed of all players increases linearly.
+    Speedup = 2,
+}
+
+impl SuddenDeathMode {
+    pub const fn name(&self) -> &str {
+        match self {
+            Self::Off => "off",
+            Self::Shrink => "shrinking arena",
+            Self::Speedup => "speedup",
+        }
+    }
+
+    pub fn next(&mut self) {
+        let idx = (*self as usize + 1) % SUDDEN_DEATH_MODES.len();
+        *self = SUDDEN_DEATH_MODES[idx];
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl World {
     pub fn new() -> Self {
+        let maps: Vec<Arc<Map>> = crate::map::load_maps().into_iter().map(Arc::new).collect();
+        let map = Arc::clone(&maps[0]);
+
         let mut next_id = 0;
         let mut players = Vec::with_capacity(2);
         let player1 = random_player(
@@ -67,6 +162,7 @@ impl World {
             Some(Key::ArrowLeft),
             Some(Key::ArrowRight),
             &players,
+            &map,
         );
         players.push(player1);
         next_id += 1;
@@ -77,6 +173,7 @@ impl World {
             Some(Key::A),
             Some(Key::D),
             &players,
+            &map,
         );
         players.push(player2);
         next_id += 1;
@@ -85,13 +182,20 @@ impl World {
         let now = clock.now;
         Self {
             next_id,
-            is_running: true,
             clock,
             state: GameState::Stopped(now),
             items: Vec::new(),
             effects: Vec::new(),
             crash_feed: Vec::new(),
             players,
+            settings: Settings::default(),
+            map,
+            maps,
+            map_index: 0,
+            modes: script::load_modes().into(),
+            mode_index: 0,
+            markers: Vec::new(),
+            events: Vec::new(),
         }
     }
 
@@ -100,27 +204,113 @@ impl World {
             .iter()
             .any(|e| e.kind == WorldEffect::WallTeleporting)
     }
+
+    /// How far sudden death has progressed, from `0.0` (not started) to `1.0`.
+    pub fn sudden_death_progress(&self) -> f32 {
+        let sudden_death = &self.settings.sudden_death;
+        if sudden_death.mode == SuddenDeathMode::Off {
+            return 0.0;
+        }
+
+        let start = match self.state {
+            GameState::Running(start) | GameState::Paused(start) | GameState::Stopped(start) => {
+                start
+            }
+            GameState::Starting(_) => return 0.0,
+        };
+        let elapsed = self.clock.now.saturating_sub(start);
+        let Some(sudden_death_elapsed) = elapsed.checked_sub(sudden_death.delay) else {
+            return 0.0;
+        };
+        let progress = sudden_death_elapsed.as_secs_f32() / sudden_death.duration.as_secs_f32();
+        progress.min(1.0)
+    }
+
+    /// The current playable area, which shrinks during sudden death.
+    pub fn boundary(&self) -> Rect {
+        let full = Rect::from_min_size(Pos2::ZERO, WORLD_SIZE);
+        if self.settings.sudden_death.mode != SuddenDeathMode::Shrink {
+            return full;
+        }
+
+        let scale = 1.0 - self.sudden_death_progress();
+        Rect::from_center_size(full.center(), scale * full.size())
+    }
+
+    /// The speed of players without any effects applied.
+    pub fn base_speed(&self) -> f32 {
+        if self.settings.sudden_death.mode != SuddenDeathMode::Speedup {
+            return BASE_SPEED;
+        }
+
+        (1.0 + self.sudden_death_progress()) * BASE_SPEED
+    }
 }
 
+#[derive(Clone)]
 pub struct Clock {
-    last_frame: SystemTime,
-    pub now: SystemTime,
+    /// When the last tick happened, this also timestamps the snapshots published for rendering.
+    last_frame: Instant,
+    /// Real time that has passed, but hasn't been simulated yet.
+    accumulator: Duration,
+    /// Whether updates are being dropped, so a stall is only logged once.
+    stalled: bool,
+    /// Simulated time since the world was created, only advances while the game is running.
+    pub now: Duration,
     pub frame_delta: Duration,
 }
 
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Clock {
     pub fn new() -> Self {
-        let now = SystemTime::now();
         Self {
-            last_frame: now,
-            now,
+            last_frame: Instant::now(),
+            accumulator: Duration::ZERO,
+            stalled: false,
+            now: Duration::ZERO,
             frame_delta: Duration::ZERO,
         }
     }
 
-    pub fn update(&mut self, state: &GameState) {
-        let now = SystemTime::now();
+    /// Accumulates the real time passed since the last tick and returns the number of fixed
+    /// updates that need to be simulated.
+    pub fn tick(&mut self) -> u32 {
+        let now = Instant::now();
+        self.accumulator += now - self.last_frame;
+        self.last_frame = now;
+
+        let steps = (self.accumulator.as_nanos() / UPDATE_TIME.as_nanos()) as u32;
+        let steps = if steps > MAX_SUB_STEPS {
+            if !self.stalled {
+                tracing::warn!("dropping {} updates", steps - MAX_SUB_STEPS);
+                self.stalled = true;
+            }
+            self.accumulator = Duration::ZERO;
+            MAX_SUB_STEPS
+        } else {
+            self.stalled = false;
+            self.accumulator -= steps * UPDATE_TIME;
+            steps
+        };
 
+        steps
+    }
+
+    /// How far the real time is between the last and the next update, from `0.0` to `1.0`.
+    ///
+    /// A snapshot is published after every tick, so on a snapshot this is measured from the time
+    /// it was taken, which is at most one update ago.
+    pub fn alpha(&self) -> f32 {
+        let pending = self.accumulator + self.last_frame.elapsed();
+        (pending.as_secs_f32() / UPDATE_TIME.as_secs_f32()).min(1.0)
+    }
+
+    fn step(&mut self, state: &GameState) {
         match state {
             GameState::Paused(_) | GameState::Stopped(_) => {
                 self.frame_delta = Duration::ZERO;
@@ -130,24 +320,25 @@ impl Clock {
                 self.now += self.frame_delta;
             }
         }
-        self.last_frame = now;
     }
 }
 
-#[derive(PartialEq, Eq)]
+/// The state of the current round, holding the [`Clock::now`] time at which it was entered.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum GameState {
-    Starting(SystemTime),
-    Running(SystemTime),
-    Paused(SystemTime),
-    Stopped(SystemTime),
+    Starting(Duration),
+    Running(Duration),
+    Paused(Duration),
+    Stopped(Duration),
 }
 
+#[derive(Clone)]
 pub struct Item {
     pub pos: Pos2,
     pub kind: ItemKind,
 }
 
-#[derive(Clone, Copy, EnumMembersArray)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumMembersArray, ToJson)]
 pub enum ItemKind {
     Speedup,
     Slowdown,
@@ -208,14 +399,14 @@ impl ItemKind {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Effect<T> {
-    pub start: SystemTime,
+    pub start: Duration,
     pub duration: Duration,
     pub kind: T,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum PlayerEffect {
     Size(f32),
     Speed(f32),
@@ -242,26 +433,94 @@ impl PlayerEffect {
     }
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum WorldEffect {
     WallTeleporting,
 }
 
-#[derive(Debug, PartialEq)]
+impl WorldEffect {
+    pub fn item_kind(&self) -> ItemKind {
+        match self {
+            WorldEffect::WallTeleporting => ItemKind::WallTeleporting,
+        }
+    }
+}
+
+/// Something that happened during an update of the world.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WorldEvent {
+    StateChanged {
+        from: GameState,
+        to: GameState,
+    },
+    /// A whole second of the countdown before a round starts has begun.
+    Countdown {
+        remaining_secs: u64,
+    },
+    ItemSpawned {
+        pos: Pos2,
+        kind: ItemKind,
+    },
+    ItemCollected {
+        player_id: u16,
+        pos: Pos2,
+        kind: ItemKind,
+    },
+    /// An effect of a collected item started, `player_id` is `None` for world effects.
+    EffectStarted {
+        player_id: Option<u16>,
+        kind: ItemKind,
+    },
+    /// An effect of a collected item ended, `player_id` is `None` for world effects.
+    EffectEnded {
+        player_id: Option<u16>,
+        kind: ItemKind,
+    },
+    GapStarted {
+        player_id: u16,
+    },
+    GapEnded {
+        player_id: u16,
+    },
+    TrailsCleared,
+    Crashed {
+        player_id: u16,
+        pos: Pos2,
+        message: CrashMessage,
+    },
+    ScoreChanged {
+        player_id: u16,
+        score: u16,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Player {
     pub id: u16,
     pub name: String,
-    pub trail: Vec<TrailSection>,
+    pub trail: Trail,
     pub pos: Pos2,
+    /// The position before the last update, used to interpolate between updates when drawing.
+    pub prev_pos: Pos2,
     pub angle: f32,
     pub color: PlayerColor,
+    pub pattern: TrailPattern,
+    /// Incremented whenever the trail is cleared, so cached drawings of it can be invalidated.
+    pub trail_generation: u32,
     pub effects: Vec<Effect<PlayerEffect>>,
     pub left_key: Option<Key>,
     pub right_key: Option<Key>,
     pub local_direction: Direction,
     pub remote_direction: Direction,
+    /// The fraction of the full turn rate the remote input turns with, `1.0` for digital input.
+    pub remote_turn_rate: f32,
+    /// The rolling round trip time of the remote controlling this player, `None` without one.
+    pub remote_rtt: Option<Duration>,
     pub just_crashed: bool,
     pub crashed: bool,
+    /// Whether the player is inside a portal they just exited, to prevent instantly teleporting
+    /// back.
+    pub in_portal: bool,
     pub score: u16,
 }
 
@@ -278,17 +537,23 @@ impl Player {
         Self {
             id,
             name,
-            trail: Vec::new(),
+            trail: Trail::default(),
             pos,
+            prev_pos: pos,
             angle,
             color,
+            pattern: TrailPattern::Solid,
+            trail_generation: 0,
             left_key,
             right_key,
             effects: Vec::new(),
             local_direction: Direction::Straight,
             remote_direction: Direction::Straight,
+            remote_turn_rate: 1.0,
+            remote_rtt: None,
             just_crashed: false,
             crashed: false,
+            in_portal: false,
             score: 0,
         }
     }
@@ -296,13 +561,22 @@ impl Player {
     pub fn reset(&mut self, pos: Pos2) {
         let mut rng = rand::thread_rng();
         self.pos = pos;
+        self.prev_pos = pos;
         self.angle = rng.gen_range(0.0..TAU);
         self.effects.clear();
         self.trail.clear();
+        self.trail_generation = self.trail_generation.wrapping_add(1);
         self.local_direction = Direction::Straight;
         self.remote_direction = Direction::Straight;
+        self.remote_turn_rate = 1.0;
         self.just_crashed = false;
         self.crashed = false;
+        self.in_portal = false;
+    }
+
+    /// The position between the last two updates.
+    pub fn interpolated_pos(&self, alpha: f32) -> Pos2 {
+        self.prev_pos.lerp(self.pos, alpha)
     }
 
     pub fn gap(&self) -> bool {
@@ -315,6 +589,31 @@ impl Player {
         self.effects.iter().any(|e| e.kind == PlayerEffect::NoGap)
     }
 
+    pub fn set_remote_direction(&mut self, dir: Direction) {
+        self.remote_direction = dir;
+        self.remote_turn_rate = 1.0;
+    }
+
+    /// Sets the remote input from an analog `steering` value, see [`ClientEvent::AnalogInput`].
+    ///
+    /// [`ClientEvent::AnalogInput`]: curvefever_common::ClientEvent::AnalogInput
+    pub fn set_remote_steering(&mut self, steering: f32) {
+        let steering = if steering.is_finite() {
+            steering.clamp(-1.0, 1.0)
+        } else {
+            0.0
+        };
+        let steering = (steering / ANALOG_STEERING_STEP).round() * ANALOG_STEERING_STEP;
+        self.remote_direction = if steering > 0.0 {
+            Direction::Right
+        } else if steering < 0.0 {
+            Direction::Left
+        } else {
+            Direction::Straight
+        };
+        self.remote_turn_rate = steering.abs();
+    }
+
     fn direction(&self) -> Direction {
         match self.local_direction {
             Direction::Straight => self.remote_direction,
@@ -322,8 +621,16 @@ impl Player {
         }
     }
 
-    fn speed(&self) -> f32 {
-        let speed = BASE_SPEED
+    /// The fraction of the full turn rate, local input always turns at the full rate.
+    fn turn_rate(&self) -> f32 {
+        match self.local_direction {
+            Direction::Straight => self.remote_turn_rate,
+            _ => 1.0,
+        }
+    }
+
+    fn speed(&self, base_speed: f32) -> f32 {
+        let speed = base_speed
             + self
                 .effects
                 .iter()
@@ -358,18 +665,19 @@ impl Player {
                     _ => None,
                 })
                 .sum::<f32>();
-        radius.max(MIN_TURNING_RADIUS)
+        // turning slower means a proportionally larger radius at the same speed
+        radius.max(MIN_TURNING_RADIUS) / self.turn_rate()
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Crash {
-    pub time: SystemTime,
+    pub time: Duration,
     pub message: CrashMessage,
 }
 
 impl Crash {
-    pub fn new(time: SystemTime, message: CrashMessage) -> Self {
+    pub fn new(time: Duration, message: CrashMessage) -> Self {
         Self { time, message }
     }
 }
@@ -378,20 +686,30 @@ impl Crash {
 pub enum CrashMessage {
     Own {
         name: String,
-        color: Color32,
+        color: PlayerColor,
     },
     Wall {
         name: String,
-        color: Color32,
+        color: PlayerColor,
     },
     Other {
         crashed_name: String,
-        crashed_color: Color32,
+        crashed_color: PlayerColor,
         other_name: String,
-        other_color: Color32,
+        other_color: PlayerColor,
     },
 }
 
+impl CrashMessage {
+    /// The color of the player that crashed.
+    pub fn crashed_color(&self) -> PlayerColor {
+        match self {
+            CrashMessage::Own { color, .. } | CrashMessage::Wall { color, .. } => *color,
+            CrashMessage::Other { crashed_color, .. } => *crashed_color,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, EnumMembersArray)]
 pub enum PlayerColor {
     Red = 0,
@@ -407,19 +725,8 @@ pub enum PlayerColor {
 }
 
 impl PlayerColor {
-    pub fn color32(&self) -> Color32 {
-        match self {
-            Self::Red => Color32::from_rgb(230, 0, 10),
-            Self::Orange => Color32::from_rgb(230, 90, 20),
-            Self::Yellow => Color32::from_rgb(230, 170, 20),
-            Self::Lime => Color32::from_rgb(160, 230, 30),
-            Self::Green => Color32::from_rgb(50, 230, 110),
-            Self::Cyan => Color32::from_rgb(30, 200, 200),
-            Self::Blue => Color32::from_rgb(50, 120, 220),
-            Self::Indigo => Color32::from_rgb(80, 50, 230),
-            Self::Purple => Color32::from_rgb(160, 100, 230),
-            Self::Magenta => Color32::from_rgb(230, 40, 180),
-        }
+    pub fn color32(&self, palette: Palette) -> Color32 {
+        palette.colors()[*self as usize]
     }
 
     pub fn prev(&mut self) {
@@ -433,6 +740,137 @@ impl PlayerColor {
     }
 }
 
+/// The set of colors used to display [`PlayerColor`]s.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumMembersArray, ToJson, FromJson)]
+pub enum Palette {
+    #[default]
+    Default = 0,
+    Deuteranopia = 1,
+    Protanopia = 2,
+    Tritanopia = 3,
+    HighContrast = 4,
+}
+
+impl Palette {
+    /// The colors of this palette, indexed by [`PlayerColor`].
+    pub const fn colors(&self) -> [Color32; PLAYER_COLORS.len()] {
+        match self {
+            Self::Default => [
+                Color32::from_rgb(230, 0, 10),
+                Color32::from_rgb(230, 90, 20),
+                Color32::from_rgb(230, 170, 20),
+                Color32::from_rgb(160, 230, 30),
+                Color32::from_rgb(50, 230, 110),
+                Color32::from_rgb(30, 200, 200),
+                Color32::from_rgb(50, 120, 220),
+                Color32::from_rgb(80, 50, 230),
+                Color32::from_rgb(160, 100, 230),
+                Color32::from_rgb(230, 40, 180),
+            ],
+            // chosen to stay apart under simulated deuteranopia, mostly along the blue-yellow axis
+            // and by lightness, without whites or greys that look like the HUD
+            Self::Deuteranopia => [
+                Color32::from_rgb(230, 23, 106),
+                Color32::from_rgb(255, 221, 140),
+                Color32::from_rgb(31, 166, 17),
+                Color32::from_rgb(25, 255, 48),
+                Color32::from_rgb(92, 230, 161),
+                Color32::from_rgb(0, 255, 255),
+                Color32::from_rgb(0, 204, 255),
+                Color32::from_rgb(102, 102, 255),
+                Color32::from_rgb(112, 112, 204),
+                Color32::from_rgb(204, 82, 180),
+            ],
+            // chosen under simulated protanopia, which also darkens reds, so the red and orange
+            // entries are bright
+            Self::Protanopia => [
+                Color32::from_rgb(255, 25, 25),
+                Color32::from_rgb(230, 92, 92),
+                Color32::from_rgb(255, 255, 0),
+                Color32::from_rgb(221, 255, 140),
+                Color32::from_rgb(66, 166, 41),
+                Color32::from_rgb(92, 230, 174),
+                Color32::from_rgb(64, 217, 255),
+                Color32::from_rgb(179, 64, 255),
+                Color32::from_rgb(221, 140, 255),
+                Color32::from_rgb(255, 0, 153),
+            ],
+            // chosen under simulated tritanopia, separated along the red-green axis and by
+            // lightness
+            Self::Tritanopia => [
+                Color32::from_rgb(255, 0, 26),
+                Color32::from_rgb(230, 115, 0),
+                Color32::from_rgb(230, 202, 92),
+                Color32::from_rgb(186, 255, 25),
+                Color32::from_rgb(102, 255, 133),
+                Color32::from_rgb(66, 166, 106),
+                Color32::from_rgb(112, 112, 204),
+                Color32::from_rgb(178, 102, 255),
+                Color32::from_rgb(255, 140, 198),
+                Color32::from_rgb(255, 0, 204),
+            ],
+            Self::HighContrast => [
+                Color32::from_rgb(255, 40, 40),
+                Color32::from_rgb(255, 150, 0),
+                Color32::from_rgb(255, 255, 0),
+                Color32::from_rgb(0, 255, 0),
+                Color32::from_rgb(0, 190, 100),
+                Color32::from_rgb(0, 255, 255),
+                Color32::from_rgb(70, 130, 255),
+                Color32::from_rgb(160, 100, 255),
+                Color32::from_rgb(255, 150, 220),
+                Color32::from_rgb(255, 0, 255),
+            ],
+        }
+    }
+
+    pub const fn name(&self) -> &str {
+        match self {
+            Self::Default => "Default",
+            Self::Deuteranopia => "Deuteranopia",
+            Self::Protanopia => "Protanopia",
+            Self::Tritanopia => "Tritanopia",
+            Self::HighContrast => "High contrast",
+        }
+    }
+
+    pub fn next(&mut self) {
+        let idx = (*self as usize + 1) % PALETTES.len();
+        *self = PALETTES[idx];
+    }
+}
+
+/// How the trail of a player is drawn, this doesn't affect collisions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumMembersArray, ToJson)]
+pub enum TrailPattern {
+    #[default]
+    Solid = 0,
+    Dashed = 1,
+    Dotted = 2,
+    Outlined = 3,
+}
+
+impl TrailPattern {
+    pub const fn name(&self) -> &str {
+        match self {
+            Self::Solid => "solid",
+            Self::Dashed => "dashed",
+            Self::Dotted => "dotted",
+            Self::Outlined => "outlined",
+        }
+    }
+
+    pub fn prev(&mut self) {
+        let idx = (*self as isize - 1).rem_euclid(TRAIL_PATTERNS.len() as isize);
+        *self = TRAIL_PATTERNS[idx as usize];
+    }
+
+    pub fn next(&mut self) {
+        let idx = (*self as usize + 1) % TRAIL_PATTERNS.len();
+        *self = TRAIL_PATTERNS[idx];
+    }
+}
+
 pub trait DirectionExt {
     fn turning_direction(&self) -> Option<TurnDirection>;
 }
@@ -447,7 +885,7 @@ impl DirectionExt for Direction {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum TurnDirection {
     Right,
     Left,
@@ -462,7 +900,83 @@ impl TurnDirection {
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// The trail sections of a player. Finished sections are stored in chunks that are shared between
+/// clones, so the world can be cloned for rendering without copying every trail.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trail {
+    /// Full chunks of [`TRAIL_CHUNK_LEN`] sections.
+    chunks: Vec<Arc<[TrailSection]>>,
+    /// The sections after the last chunk, only the last one of these is still growing.
+    tail: Vec<TrailSection>,
+}
+
+impl Trail {
+    pub fn len(&self) -> usize {
+        self.chunks.len() * TRAIL_CHUNK_LEN + self.tail.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tail.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&TrailSection> {
+        match self.chunks.get(index / TRAIL_CHUNK_LEN) {
+            Some(c) => Some(&c[index % TRAIL_CHUNK_LEN]),
+            None => self.tail.get(index - self.chunks.len() * TRAIL_CHUNK_LEN),
+        }
+    }
+
+    pub fn last(&self) -> Option<&TrailSection> {
+        self.tail.last()
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut TrailSection> {
+        self.tail.last_mut()
+    }
+
+    pub fn push(&mut self, section: TrailSection) {
+        // keep the last section in the tail, so it can still be changed
+        if self.tail.len() == TRAIL_CHUNK_LEN {
+            let chunk = std::mem::take(&mut self.tail);
+            self.chunks.push(chunk.into());
+        }
+        self.tail.push(section);
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.tail.clear();
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &TrailSection> + Clone {
+        self.iter_from(0)
+    }
+
+    /// The sections starting at `start`, without walking over the ones before it.
+    pub fn iter_from(
+        &self,
+        start: usize,
+    ) -> impl DoubleEndedIterator<Item = &TrailSection> + Clone {
+        let first = start / TRAIL_CHUNK_LEN;
+        let (head, chunks, tail): (&[TrailSection], _, &[TrailSection]) =
+            match self.chunks.get(first) {
+                Some(c) => (
+                    &c[start % TRAIL_CHUNK_LEN..],
+                    &self.chunks[first + 1..],
+                    &self.tail,
+                ),
+                None => {
+                    let start = start - self.chunks.len() * TRAIL_CHUNK_LEN;
+                    (self.tail.get(start..).unwrap_or(&[]), &[][..], &[])
+                }
+            };
+        head.iter()
+            .chain(chunks.iter().flat_map(|c| c.iter()))
+            .chain(tail.iter())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum TrailSection {
     Straight(StraightTrailSection),
     Arc(ArcTrailSection),
@@ -515,7 +1029,7 @@ impl TrailSection {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct StraightTrailSection {
     pub start: Pos2,
     pub gap: bool,
@@ -538,7 +1052,7 @@ impl StraightTrailSection {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ArcTrailSection {
     /// The position of the player at the start of this arc section.
     pub start_pos: Pos2,
@@ -601,6 +1115,27 @@ impl ArcTrailSection {
         self.player_end_angle - FRAC_PI_2 * self.dir.angle_sign()
     }
 
+    /// The signed angle swept from `Self::arc_start_angle()` to `Self::arc_end_angle()`.
+    pub fn angle_delta(&self) -> f32 {
+        match self.dir {
+            TurnDirection::Right => {
+                if self.player_end_angle < self.player_start_angle {
+                    self.player_end_angle.rem_euclid(TAU) - self.player_start_angle
+                } else {
+                    self.player_end_angle - self.player_start_angle
+                }
+            }
+            TurnDirection::Left => {
+                let angle_delta = if self.player_start_angle < self.player_end_angle {
+                    self.player_start_angle.rem_euclid(TAU) - self.player_end_angle
+                } else {
+                    self.player_start_angle - self.player_end_angle
+                };
+                -angle_delta
+            }
+        }
+    }
+
     pub fn length(&self) -> f32 {
         let start_angle = self.arc_start_angle().rem_euclid(TAU);
         let end_angle = self.arc_end_angle().rem_euclid(TAU);
@@ -615,9 +1150,28 @@ impl ArcTrailSection {
 }
 
 impl World {
-    pub fn update(&mut self) {
+    /// Simulates all fixed updates that are due since the last call, and returns everything
+    /// that happened since then.
+    pub fn update(&mut self) -> Vec<WorldEvent> {
+        let steps = self.clock.tick();
+        for _ in 0..steps {
+            self.step();
+        }
+        std::mem::take(&mut self.events)
+    }
+
+    fn set_state(&mut self, state: GameState) {
+        let from = std::mem::replace(&mut self.state, state.clone());
+        self.events
+            .push(WorldEvent::StateChanged { from, to: state });
+    }
+
+    fn step(&mut self) {
         let mut rng = rand::thread_rng();
-        self.clock.update(&self.state);
+        self.clock.step(&self.state);
+        for p in self.players.iter_mut() {
+            p.prev_pos = p.pos;
+        }
 
         match self.state {
             GameState::Starting(start_time) => {
@@ -631,14 +1185,31 @@ impl World {
                 }
 
                 let now = self.clock.now;
+                let elapsed = now.saturating_sub(start_time);
+                let prev_elapsed = elapsed.saturating_sub(self.clock.frame_delta);
+                if elapsed < START_DELAY && elapsed.as_secs() != prev_elapsed.as_secs() {
+                    let remaining_secs = START_DELAY.as_secs() - elapsed.as_secs();
+                    self.events.push(WorldEvent::Countdown { remaining_secs });
+                }
+
                 if now > start_time + START_DELAY {
-                    self.state = GameState::Running(self.clock.now);
+                    self.set_state(GameState::Running(now));
                 }
             }
             GameState::Running(start_time) => {
+                let base_speed = self.base_speed();
+                let boundary = self.boundary();
+
                 // remove effects
-                self.effects
-                    .retain(|e| e.start + e.duration > self.clock.now);
+                let now = self.clock.now;
+                for e in self.effects.iter().filter(|e| e.start + e.duration <= now) {
+                    let kind = e.kind.item_kind();
+                    self.events.push(WorldEvent::EffectEnded {
+                        player_id: None,
+                        kind,
+                    });
+                }
+                self.effects.retain(|e| e.start + e.duration > now);
 
                 // spawn items
                 if self.items.len() < MAX_ITEMS {
@@ -654,11 +1225,17 @@ impl World {
                                 break;
                             }
                         }
-                        if let Some(pos) = gen_item_position(&self.players, &self.items) {
+                        if let Some(pos) =
+                            gen_item_position(&self.players, &self.items, &self.map, boundary)
+                        {
                             let item = Item {
                                 pos,
                                 kind: item_kind.expect("item kind should match one item"),
                             };
+                            self.events.push(WorldEvent::ItemSpawned {
+                                pos: item.pos,
+                                kind: item.kind,
+                            });
                             self.items.push(item);
                         }
                     }
@@ -666,7 +1243,10 @@ impl World {
 
                 // remove effects
                 for p in self.players.iter_mut() {
-                    p.effects.retain(|e| e.start + e.duration > self.clock.now);
+                    for e in p.effects.iter().filter(|e| e.start + e.duration <= now) {
+                        self.events.push(effect_ended_event(p.id, &e.kind));
+                    }
+                    p.effects.retain(|e| e.start + e.duration > now);
 
                     if p.crashed {
                         continue;
@@ -675,9 +1255,10 @@ impl World {
                     let weighted_range = self.clock.frame_delta.as_secs_f32() * GAP_RATE;
                     if !p.gap() && !p.no_gap() && rng.gen_range(0.0..=1.0) < weighted_range {
                         p.effects.push(gap_effect(&self.clock));
+                        self.events.push(WorldEvent::GapStarted { player_id: p.id });
                     }
 
-                    move_player(&self.clock, p);
+                    move_player(&self.clock, base_speed, p);
                 }
 
                 let wall_teleporting = self.wall_teleporting();
@@ -686,73 +1267,121 @@ impl World {
                         continue;
                     }
 
-                    // wall collisions
-                    if wall_teleporting {
-                        let p = &mut self.players[pi];
-                        if p.pos.x < 0.0 {
-                            p.pos.x = WORLD_SIZE.x;
+                    // wall collisions, once the arena has shrunk below the size of a player
+                    // there is nothing left to wrap around to
+                    let p = &mut self.players[pi];
+                    let thickness = p.thickness();
+                    if wall_teleporting
+                        && boundary.width() > thickness
+                        && boundary.height() > thickness
+                    {
+                        if p.pos.x < boundary.min.x {
+                            p.pos.x = boundary.max.x;
+                            p.prev_pos = p.pos;
                             add_trail_section(p);
-                        } else if p.pos.x > WORLD_SIZE.x {
-                            p.pos.x = 0.0;
+                        } else if p.pos.x > boundary.max.x {
+                            p.pos.x = boundary.min.x;
+                            p.prev_pos = p.pos;
                             add_trail_section(p);
                         }
 
-                        if p.pos.y < 0.0 {
-                            p.pos.y = WORLD_SIZE.y;
+                        if p.pos.y < boundary.min.y {
+                            p.pos.y = boundary.max.y;
+                            p.prev_pos = p.pos;
                             add_trail_section(p);
-                        } else if p.pos.y > WORLD_SIZE.y {
-                            p.pos.y = 0.0;
+                        } else if p.pos.y > boundary.max.y {
+                            p.pos.y = boundary.min.y;
+                            p.prev_pos = p.pos;
                             add_trail_section(p);
                         }
-                    } else {
+                    } else if p.pos.x < boundary.min.x + 0.5 * thickness
+                        || p.pos.x > boundary.max.x - 0.5 * thickness
+                        || p.pos.y < boundary.min.y + 0.5 * thickness
+                        || p.pos.y > boundary.max.y - 0.5 * thickness
+                    {
+                        push_crash(
+                            &mut self.crash_feed,
+                            &mut self.events,
+                            self.clock.now,
+                            p,
+                            CrashMessage::Wall {
+                                name: p.name.clone(),
+                                color: p.color,
+                            },
+                        );
+                        p.just_crashed = true;
+                    }
+
+                    // obstacle collisions
+                    {
                         let p = &mut self.players[pi];
-                        let thickness = p.thickness();
-                        if p.pos.x < 0.5 * thickness
-                            || p.pos.x > WORLD_SIZE.x - 0.5 * thickness
-                            || p.pos.y < 0.5 * thickness
-                            || p.pos.y > WORLD_SIZE.y - 0.5 * thickness
-                        {
-                            self.crash_feed.push(Crash::new(
+                        let extend = 0.5 * p.thickness();
+                        if !p.just_crashed && intersects_walls(&self.map.walls, p.pos, extend) {
+                            push_crash(
+                                &mut self.crash_feed,
+                                &mut self.events,
                                 self.clock.now,
+                                p,
                                 CrashMessage::Wall {
                                     name: p.name.clone(),
-                                    color: p.color.color32(),
+                                    color: p.color,
                                 },
-                            ));
+                            );
                             p.just_crashed = true;
                         }
                     }
 
+                    // portals
+                    {
+                        let p = &mut self.players[pi];
+                        match self.map.portal_exit(p.pos) {
+                            Some(exit) if !p.in_portal => {
+                                p.pos = exit;
+                                p.prev_pos = exit;
+                                p.in_portal = true;
+                                add_trail_section(p);
+                            }
+                            Some(_) => (),
+                            None => p.in_portal = false,
+                        }
+                    }
+
                     // player collisions
                     if !self.players[pi].gap() {
                         let p = &mut self.players[pi];
                         if intersects_own_trail(p) {
-                            self.crash_feed.push(Crash::new(
+                            push_crash(
+                                &mut self.crash_feed,
+                                &mut self.events,
                                 self.clock.now,
+                                p,
                                 CrashMessage::Own {
                                     name: p.name.clone(),
-                                    color: p.color.color32(),
+                                    color: p.color,
                                 },
-                            ));
+                            );
                             p.just_crashed = true;
                         }
 
                         let others = self.players.iter().enumerate().filter(|(i, _)| pi != *i);
                         for (_, o) in others {
                             let p = &self.players[pi];
-                            if intersects_trail(p.pos, 0.5 * p.thickness(), &o.trail) {
+                            if intersects_trail(p.pos, 0.5 * p.thickness(), o.trail.iter()) {
                                 let other_name = o.name.clone();
-                                let other_color = o.color.color32();
+                                let other_color = o.color;
                                 let p = &mut self.players[pi];
-                                self.crash_feed.push(Crash::new(
+                                push_crash(
+                                    &mut self.crash_feed,
+                                    &mut self.events,
                                     self.clock.now,
+                                    p,
                                     CrashMessage::Other {
                                         crashed_name: p.name.clone(),
-                                        crashed_color: p.color.color32(),
+                                        crashed_color: p.color,
                                         other_name,
                                         other_color,
                                     },
-                                ));
+                                );
                                 p.just_crashed = true;
                                 break;
                             }
@@ -801,11 +1430,20 @@ impl World {
                                         .push(player_effect(&self.clock, PlayerEffect::Size(-2.0)));
                                 }
                                 ItemKind::Ghost => {
+                                    if p.no_gap() {
+                                        self.events.push(WorldEvent::EffectEnded {
+                                            player_id: Some(p.id),
+                                            kind: ItemKind::NoGap,
+                                        });
+                                    }
                                     p.effects.retain(|e| e.kind != PlayerEffect::NoGap);
                                     p.effects
                                         .push(player_effect(&self.clock, PlayerEffect::Ghost));
                                 }
                                 ItemKind::NoGap => {
+                                    if p.effects.iter().any(|e| e.kind == PlayerEffect::Gap) {
+                                        self.events.push(WorldEvent::GapEnded { player_id: p.id });
+                                    }
                                     p.effects.retain(|e| e.kind != PlayerEffect::Gap);
                                     p.effects
                                         .push(player_effect(&self.clock, PlayerEffect::NoGap));
@@ -819,6 +1457,26 @@ impl World {
                                 ItemKind::Clear => clear_trails = true,
                             }
 
+                            self.events.push(WorldEvent::ItemCollected {
+                                player_id: p.id,
+                                pos: item.pos,
+                                kind: item.kind,
+                            });
+                            match item.kind {
+                                ItemKind::WallTeleporting => {
+                                    self.events.push(WorldEvent::EffectStarted {
+                                        player_id: None,
+                                        kind: item.kind,
+                                    });
+                                }
+                                ItemKind::Clear => (),
+                                _ => {
+                                    self.events.push(WorldEvent::EffectStarted {
+                                        player_id: Some(p.id),
+                                        kind: item.kind,
+                                    });
+                                }
+                            }
                             self.items.remove(i);
                             continue;
                         }
@@ -828,7 +1486,9 @@ impl World {
                     if clear_trails {
                         for p in self.players.iter_mut() {
                             p.trail.clear();
+                            p.trail_generation = p.trail_generation.wrapping_add(1);
                         }
+                        self.events.push(WorldEvent::TrailsCleared);
                     }
                 }
 
@@ -845,16 +1505,18 @@ impl World {
                     for p in self.players.iter_mut() {
                         if p.just_crashed {
                             p.score += 1;
+                            self.events.push(score_changed_event(p));
                         }
                     }
-                    self.state = GameState::Stopped(start_time);
+                    self.set_state(GameState::Stopped(start_time));
                 } else if num_alive_players == 1 {
                     for p in self.players.iter_mut() {
                         if !p.crashed {
                             p.score += 1;
+                            self.events.push(score_changed_event(p));
                         }
                     }
-                    self.state = GameState::Stopped(start_time);
+                    self.set_state(GameState::Stopped(start_time));
                 }
             }
             GameState::Paused(_) => (),
@@ -862,28 +1524,100 @@ impl World {
         }
     }
 
+    /// The only player that didn't crash, `None` if the last players crashed simultaneously or
+    /// the round is still running.
+    pub fn round_winner(&self) -> Option<u16> {
+        let mut alive = self.players.iter().filter(|p| !p.crashed);
+        match (alive.next(), alive.next()) {
+            (Some(p), None) => Some(p.id),
+            _ => None,
+        }
+    }
+
     pub fn toggle_pause(&mut self) {
         match self.state {
-            GameState::Running(s) => self.state = GameState::Paused(s),
-            GameState::Paused(s) => self.state = GameState::Running(s),
+            GameState::Running(s) => self.set_state(GameState::Paused(s)),
+            GameState::Paused(s) => self.set_state(GameState::Running(s)),
             _ => (),
         }
     }
 
     pub fn restart(&mut self) {
         if matches!(self.state, GameState::Stopped(_)) {
-            self.state = GameState::Starting(self.clock.now);
+            self.set_state(GameState::Starting(self.clock.now));
+            self.events.push(WorldEvent::Countdown {
+                remaining_secs: START_DELAY.as_secs(),
+            });
             self.items.clear();
             self.effects.clear();
             self.crash_feed.clear();
+            self.markers.clear();
 
             for i in 0..self.players.len() {
-                let pos = gen_player_position(&self.players[0..i]);
+                let pos = gen_player_position(&self.players[0..i], &self.map);
                 self.players[i].reset(pos);
             }
         }
     }
 
+    /// Adds a new map or replaces the one with the same name.
+    pub fn add_map(&mut self, map: Map) {
+        let map = Arc::new(map);
+        match self.maps.iter_mut().find(|m| m.name == map.name) {
+            Some(m) => *m = map,
+            None => self.maps.push(map),
+        }
+    }
+
+    /// Selects the next map, which will be used starting with the next round.
+    pub fn next_map(&mut self) {
+        if matches!(self.state, GameState::Stopped(_)) {
+            self.map_index = (self.map_index + 1) % self.maps.len();
+            self.map = Arc::clone(&self.maps[self.map_index]);
+        }
+    }
+
+    /// Selects the next game mode, which will be used starting with the next round.
+    pub fn next_mode(&mut self) {
+        if matches!(self.state, GameState::Stopped(_)) {
+            self.mode_index = (self.mode_index + 1) % self.modes.len();
+        }
+    }
+
+    pub fn mode(&self) -> &GameMode {
+        &self.modes[self.mode_index]
+    }
+
+    pub fn add_player_effect(&mut self, player_id: u16, kind: PlayerEffect, duration: Duration) {
+        let Some(p) = self.players.iter_mut().find(|p| p.id == player_id) else {
+            return;
+        };
+        if let Some(item_kind) = kind.item_kind() {
+            self.events.push(WorldEvent::EffectStarted {
+                player_id: Some(player_id),
+                kind: item_kind,
+            });
+        }
+        p.effects.push(Effect {
+            start: self.clock.now,
+            duration,
+            kind,
+        });
+    }
+
+    pub fn spawn_item(&mut self, kind: ItemKind, pos: Pos2) {
+        self.events.push(WorldEvent::ItemSpawned { pos, kind });
+        self.items.push(Item { pos, kind });
+    }
+
+    pub fn award_score(&mut self, player_id: u16, points: i32) {
+        let Some(p) = self.players.iter_mut().find(|p| p.id == player_id) else {
+            return;
+        };
+        p.score = (p.score as i32 + points).clamp(0, u16::MAX as i32) as u16;
+        self.events.push(score_changed_event(p));
+    }
+
     pub fn next_id(&mut self) -> u16 {
         let id = self.next_id;
         self.next_id += 1;
@@ -896,7 +1630,7 @@ impl World {
         }
         let id = self.next_id();
         let name = format!("Player{}", self.players.len() + 1);
-        let player = random_player(id, name, None, None, &self.players);
+        let player = random_player(id, name, None, None, &self.players, &self.map);
         self.players.push(player);
         Some(id)
     }
@@ -908,7 +1642,7 @@ impl World {
     }
 }
 
-pub fn move_player(clock: &Clock, player: &mut Player) {
+pub fn move_player(clock: &Clock, base_speed: f32, player: &mut Player) {
     if player.trail.is_empty() {
         add_trail_section(player);
         return;
@@ -929,12 +1663,12 @@ pub fn move_player(clock: &Clock, player: &mut Player) {
         }
     }
 
-    update_trail_section(clock, player);
+    update_trail_section(clock, base_speed, player);
 }
 
-fn update_trail_section(clock: &Clock, player: &mut Player) {
+fn update_trail_section(clock: &Clock, base_speed: f32, player: &mut Player) {
     let delta_time = clock.frame_delta.as_secs_f32();
-    let speed = player.speed();
+    let speed = player.speed(base_speed);
     let last_trail = player
         .trail
         .last_mut()
@@ -983,9 +1717,10 @@ fn random_player(
     left_key: Option<Key>,
     right_key: Option<Key>,
     others: &[Player],
+    map: &Map,
 ) -> Player {
     let mut rng = rand::thread_rng();
-    let pos = gen_player_position(others);
+    let pos = gen_player_position(others, map);
     let angle = rng.gen_range(0.0..TAU);
     let color_idx = rng.gen_range(0..PLAYER_COLORS.len() - others.len());
     let color = PLAYER_COLORS
@@ -997,7 +1732,7 @@ fn random_player(
     Player::new(id, name, pos, angle, *color, left_key, right_key)
 }
 
-fn gen_player_position(others: &[Player]) -> Pos2 {
+fn gen_player_position(others: &[Player], map: &Map) -> Pos2 {
     let mut rng = rand::thread_rng();
     let mut pos = Pos2::ZERO;
 
@@ -1007,6 +1742,14 @@ fn gen_player_position(others: &[Player]) -> Pos2 {
             y: rng.gen_range(MIN_PLAYER_WALL_DIST..(WORLD_SIZE.y - MIN_PLAYER_WALL_DIST)),
         };
 
+        if intersects_walls(&map.walls, pos, MIN_PLAYER_WALL_DIST) {
+            continue 'outer;
+        }
+
+        if map.portal_exit(pos).is_some() {
+            continue 'outer;
+        }
+
         for o in others.iter() {
             if intersects(pos, o.pos, MIN_PLAYER_DIST) {
                 continue 'outer;
@@ -1019,17 +1762,34 @@ fn gen_player_position(others: &[Player]) -> Pos2 {
     pos
 }
 
-fn gen_item_position(players: &[Player], items: &[Item]) -> Option<Pos2> {
+fn gen_item_position(
+    players: &[Player],
+    items: &[Item],
+    map: &Map,
+    boundary: Rect,
+) -> Option<Pos2> {
     let mut rng = rand::thread_rng();
+    let area = boundary.shrink(MIN_ITEM_WALL_DIST);
+    if area.width() <= 0.0 || area.height() <= 0.0 {
+        return None;
+    }
 
     'outer: for _ in 0..10_000 {
         let pos = Pos2 {
-            x: rng.gen_range(MIN_ITEM_WALL_DIST..(WORLD_SIZE.x - MIN_ITEM_WALL_DIST)),
-            y: rng.gen_range(MIN_ITEM_WALL_DIST..(WORLD_SIZE.y - MIN_ITEM_WALL_DIST)),
+            x: rng.gen_range(area.min.x..area.max.x),
+            y: rng.gen_range(area.min.y..area.max.y),
         };
 
+        if intersects_walls(&map.walls, pos, MIN_ITEM_WALL_DIST) {
+            continue 'outer;
+        }
+
+        if map.portal_exit(pos).is_some() {
+            continue 'outer;
+        }
+
         for p in players.iter() {
-            if intersects_trail(pos, MIN_ITEM_DIST, &p.trail) {
+            if intersects_trail(pos, MIN_ITEM_DIST, p.trail.iter()) {
                 continue 'outer;
             }
         }
@@ -1053,7 +1813,7 @@ fn intersects_own_trail(player: &Player) -> bool {
     let player_extend = 0.5 * player.thickness();
 
     // check trail in proximity to the current position
-    while let Some(s) = trail_iter.next() {
+    for s in trail_iter.by_ref() {
         let min_dist = player_extend + 0.5 * s.thickness();
         let end_dist = player.pos.distance(s.end_pos());
 
@@ -1111,6 +1871,38 @@ fn intersects_trail<'a>(
     false
 }
 
+pub fn intersects_walls(walls: &[Wall], pos: Pos2, extend: f32) -> bool {
+    walls.iter().any(|w| match w {
+        Wall::Segment {
+            start,
+            end,
+            thickness,
+        } => {
+            let s = StraightTrailSection::new(*start, false, *thickness, *end);
+            intersects_straight_trailsection(&s, pos, extend)
+        }
+        Wall::Arc {
+            center,
+            radius,
+            start_angle,
+            end_angle,
+            thickness,
+        } => {
+            let s = ArcTrailSection {
+                start_pos: *center + *radius * Vec2::new(start_angle.cos(), start_angle.sin()),
+                gap: false,
+                thickness: *thickness,
+                dir: TurnDirection::Right,
+                radius: *radius,
+                player_start_angle: start_angle + FRAC_PI_2,
+                player_end_angle: end_angle + FRAC_PI_2,
+            };
+            intersects_arc_trailsection(&s, pos, extend)
+        }
+        Wall::Circle { center, radius } => intersects(pos, *center, radius + extend),
+    })
+}
+
 fn intersects_straight_trailsection(s: &StraightTrailSection, pos: Pos2, extend: f32) -> bool {
     let start_dist = s.start.distance(pos);
     let end_dist = s.end.distance(pos);
@@ -1219,6 +2011,38 @@ fn angle(a: Pos2, b: Pos2) -> f32 {
     f32::atan2(diff.y, diff.x)
 }
 
+fn push_crash(
+    crash_feed: &mut Vec<Crash>,
+    events: &mut Vec<WorldEvent>,
+    now: Duration,
+    player: &Player,
+    message: CrashMessage,
+) {
+    events.push(WorldEvent::Crashed {
+        player_id: player.id,
+        pos: player.pos,
+        message: message.clone(),
+    });
+    crash_feed.push(Crash::new(now, message));
+}
+
+fn effect_ended_event(player_id: u16, effect: &PlayerEffect) -> WorldEvent {
+    match effect.item_kind() {
+        Some(kind) => WorldEvent::EffectEnded {
+            player_id: Some(player_id),
+            kind,
+        },
+        None => WorldEvent::GapEnded { player_id },
+    }
+}
+
+fn score_changed_event(player: &Player) -> WorldEvent {
+    WorldEvent::ScoreChanged {
+        player_id: player.id,
+        score: player.score,
+    }
+}
+
 fn gap_effect(clock: &Clock) -> Effect<PlayerEffect> {
     let mut rng = rand::thread_rng();
     Effect {
@@ -1245,3 +2069,404 @@ fn world_effect(clock: &Clock, kind: WorldEffect) -> Effect<WorldEffect> {
         kind,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::pos2;
+
+    fn section(i: usize) -> TrailSection {
+        let x = i as f32;
+        TrailSection::Straight(StraightTrailSection::new(
+            pos2(x, 0.0),
+            false,
+            BASE_THICKNESS,
+            pos2(x + 1.0, 0.0),
+        ))
+    }
+
+    fn trail(len: usize) -> Trail {
+        let mut trail = Trail::default();
+        for i in 0..len {
+            trail.push(section(i));
+        }
+        trail
+    }
+
+    fn clock_behind(behind: Duration) -> Clock {
+        Clock {
+            last_frame: Instant::now() - behind,
+            ..Clock::new()
+        }
+    }
+
+    /// A running round with both players heading away from each other and the walls.
+    fn running_world() -> World {
+        let mut world = World::new();
+        world.state = GameState::Running(world.clock.now);
+        world.players[0].reset(pos2(400.0, 360.0));
+        world.players[0].angle = PI;
+        world.players[1].reset(pos2(800.0, 360.0));
+        world.players[1].angle = 0.0;
+        world
+    }
+
+    /// Simulates `steps` fixed updates and returns the events that happened.
+    fn step(world: &mut World, steps: usize) -> Vec<WorldEvent> {
+        for _ in 0..steps {
+            world.step();
+        }
+        std::mem::take(&mut world.events)
+    }
+
+    #[test]
+    fn clock_steps() {
+        let mut clock = clock_behind(3 * UPDATE_TIME + UPDATE_TIME / 2);
+        assert_eq!(clock.tick(), 3);
+        assert!(!clock.stalled);
+        // the half update that's left over is part of the interpolation
+        assert!(clock.alpha() >= 0.5);
+    }
+
+    #[test]
+    fn clock_stall_is_logged_once() {
+        let mut clock = clock_behind(4 * MAX_SUB_STEPS * UPDATE_TIME);
+        assert_eq!(clock.tick(), MAX_SUB_STEPS);
+        assert!(clock.stalled);
+        assert_eq!(clock.accumulator, Duration::ZERO);
+
+        clock.last_frame -= 4 * MAX_SUB_STEPS * UPDATE_TIME;
+        assert_eq!(clock.tick(), MAX_SUB_STEPS);
+        assert!(clock.stalled);
+
+        clock.last_frame -= UPDATE_TIME;
+        assert!(clock.tick() <= 2);
+        assert!(!clock.stalled);
+    }
+
+    #[test]
+    fn trail_indexing() {
+        let len = 3 * TRAIL_CHUNK_LEN + 5;
+        let trail = trail(len);
+        assert_eq!(trail.len(), len);
+        for i in 0..len {
+            assert_eq!(trail.get(i), Some(&section(i)));
+        }
+        assert_eq!(trail.get(len), None);
+        assert_eq!(trail.last(), Some(&section(len - 1)));
+    }
+
+    #[test]
+    fn trail_iter_from() {
+        for len in [
+            0,
+            1,
+            TRAIL_CHUNK_LEN,
+            TRAIL_CHUNK_LEN + 1,
+            2 * TRAIL_CHUNK_LEN + 3,
+        ] {
+            let trail = trail(len);
+            for start in 0..=len + 1 {
+                let expected: Vec<_> = (start..len).map(section).collect();
+                let actual: Vec<_> = trail.iter_from(start).cloned().collect();
+                assert_eq!(actual, expected, "len {len} start {start}");
+            }
+            let reversed: Vec<_> = (0..len).rev().map(section).collect();
+            assert_eq!(trail.iter().rev().cloned().collect::<Vec<_>>(), reversed);
+        }
+    }
+
+    #[test]
+    fn trail_last_section_stays_mutable() {
+        let mut trail = trail(TRAIL_CHUNK_LEN);
+        let snapshot = trail.clone();
+        trail.push(section(TRAIL_CHUNK_LEN));
+        *trail.last_mut().unwrap() = section(1000);
+
+        assert_eq!(trail.get(TRAIL_CHUNK_LEN), Some(&section(1000)));
+        assert_eq!(
+            trail.get(TRAIL_CHUNK_LEN - 1),
+            Some(&section(TRAIL_CHUNK_LEN - 1))
+        );
+        assert_eq!(snapshot.len(), TRAIL_CHUNK_LEN);
+        // finished chunks are shared with earlier clones
+        let shared = trail.clone();
+        assert!(Arc::ptr_eq(&trail.chunks[0], &shared.chunks[0]));
+
+        trail.clear();
+        assert!(trail.is_empty());
+        assert_eq!(trail.len(), 0);
+    }
+
+    #[test]
+    fn wall_teleporting_keeps_interpolation() {
+        let mut world = running_world();
+        world
+            .effects
+            .push(world_effect(&world.clock, WorldEffect::WallTeleporting));
+        world.players[0].reset(pos2(0.1, 360.0));
+        world.players[0].angle = PI;
+        step(&mut world, 2);
+
+        let wrapped = &world.players[0];
+        assert_eq!(wrapped.pos.x, WORLD_SIZE.x);
+        assert_eq!(wrapped.prev_pos, wrapped.pos);
+
+        // players that didn't wrap are still interpolated
+        let other = &world.players[1];
+        assert!(other.prev_pos.x < other.pos.x);
+    }
+
+    #[test]
+    fn shrunk_arena_crashes_teleporting_players() {
+        let mut world = running_world();
+        world.settings.sudden_death.mode = SuddenDeathMode::Shrink;
+        world.clock.now = SUDDEN_DEATH_DELAY + SUDDEN_DEATH_DURATION;
+        world
+            .effects
+            .push(world_effect(&world.clock, WorldEffect::WallTeleporting));
+        let events = step(&mut world, 2);
+
+        assert!(world.players.iter().all(|p| p.crashed));
+        assert!(world.players.iter().all(|p| p.trail.len() <= 1));
+        let crashes = events
+            .iter()
+            .filter(|e| matches!(e, WorldEvent::Crashed { .. }))
+            .count();
+        assert_eq!(crashes, 2);
+        assert!(matches!(world.state, GameState::Stopped(_)));
+    }
+
+    fn sudden_death_world(mode: SuddenDeathMode, elapsed: Duration) -> World {
+        let mut world = running_world();
+        world.settings.sudden_death.mode = mode;
+        world.clock.now = elapsed;
+        world
+    }
+
+    #[test]
+    fn sudden_death_progress() {
+        let halfway = SUDDEN_DEATH_DELAY + SUDDEN_DEATH_DURATION / 2;
+        let end = SUDDEN_DEATH_DELAY + SUDDEN_DEATH_DURATION;
+
+        let world = sudden_death_world(SuddenDeathMode::Off, halfway);
+        assert_eq!(world.sudden_death_progress(), 0.0);
+
+        for mode in [SuddenDeathMode::Shrink, SuddenDeathMode::Speedup] {
+            let progress = |elapsed| sudden_death_world(mode, elapsed).sudden_death_progress();
+            assert_eq!(progress(Duration::ZERO), 0.0);
+            assert_eq!(progress(SUDDEN_DEATH_DELAY - UPDATE_TIME), 0.0);
+            assert_eq!(progress(SUDDEN_DEATH_DELAY), 0.0);
+            assert!((progress(halfway) - 0.5).abs() < 1e-4);
+            assert_eq!(progress(end), 1.0);
+            assert_eq!(progress(2 * end), 1.0);
+        }
+
+        // the countdown before a round doesn't count
+        let mut world = sudden_death_world(SuddenDeathMode::Shrink, end);
+        world.state = GameState::Starting(Duration::ZERO);
+        assert_eq!(world.sudden_death_progress(), 0.0);
+    }
+
+    #[test]
+    fn sudden_death_modes() {
+        let full = Rect::from_min_size(Pos2::ZERO, WORLD_SIZE);
+        let halfway = SUDDEN_DEATH_DELAY + SUDDEN_DEATH_DURATION / 2;
+
+        let world = sudden_death_world(SuddenDeathMode::Off, halfway);
+        assert_eq!(world.boundary(), full);
+        assert_eq!(world.base_speed(), BASE_SPEED);
+
+        let world = sudden_death_world(SuddenDeathMode::Shrink, halfway);
+        let boundary = world.boundary();
+        assert_eq!(boundary.center(), full.center());
+        assert!((boundary.width() - 0.5 * WORLD_SIZE.x).abs() < 0.1);
+        assert!((boundary.height() - 0.5 * WORLD_SIZE.y).abs() < 0.1);
+        assert_eq!(world.base_speed(), BASE_SPEED);
+
+        let world = sudden_death_world(SuddenDeathMode::Speedup, halfway);
+        assert_eq!(world.boundary(), full);
+        assert!((world.base_speed() - 1.5 * BASE_SPEED).abs() < 0.1);
+
+        let end = SUDDEN_DEATH_DELAY + SUDDEN_DEATH_DURATION;
+        let world = sudden_death_world(SuddenDeathMode::Shrink, end);
+        assert_eq!(world.boundary().size(), Vec2::ZERO);
+        let world = sudden_death_world(SuddenDeathMode::Speedup, 2 * end);
+        assert_eq!(world.base_speed(), 2.0 * BASE_SPEED);
+    }
+
+    #[test]
+    fn no_items_spawn_in_a_shrunk_arena() {
+        let world = running_world();
+        let center = Rect::from_min_size(Pos2::ZERO, WORLD_SIZE).center();
+        let spawn = |size| {
+            let boundary = Rect::from_center_size(center, size);
+            gen_item_position(&world.players, &world.items, &world.map, boundary)
+        };
+
+        let small = Vec2::splat(2.0 * MIN_ITEM_WALL_DIST);
+        assert_eq!(spawn(small), None);
+        assert_eq!(spawn(Vec2::ZERO), None);
+
+        let boundary = Rect::from_center_size(center, Vec2::splat(4.0 * MIN_ITEM_WALL_DIST));
+        let pos = spawn(boundary.size()).unwrap();
+        assert!(boundary.shrink(MIN_ITEM_WALL_DIST).contains(pos));
+    }
+
+    #[test]
+    fn restart_events() {
+        let mut world = World::new();
+        world.restart();
+        let events = std::mem::take(&mut world.events);
+        assert_eq!(
+            events,
+            [
+                WorldEvent::StateChanged {
+                    from: GameState::Stopped(Duration::ZERO),
+                    to: GameState::Starting(Duration::ZERO),
+                },
+                WorldEvent::Countdown { remaining_secs: 2 },
+            ]
+        );
+
+        let steps = (START_DELAY.as_nanos() / UPDATE_TIME.as_nanos()) as usize + 1;
+        let events = step(&mut world, steps);
+        assert_eq!(
+            events,
+            [
+                WorldEvent::Countdown { remaining_secs: 1 },
+                WorldEvent::StateChanged {
+                    from: GameState::Starting(Duration::ZERO),
+                    to: GameState::Running(world.clock.now),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn item_events() {
+        let mut world = running_world();
+        let player_id = world.players[0].id;
+        let pos = pos2(395.0, 360.0);
+        world.spawn_item(ItemKind::Speedup, pos);
+        let events = step(&mut world, 1);
+        assert!(events.contains(&WorldEvent::ItemCollected {
+            player_id,
+            pos,
+            kind: ItemKind::Speedup,
+        }));
+        assert!(events.contains(&WorldEvent::EffectStarted {
+            player_id: Some(player_id),
+            kind: ItemKind::Speedup,
+        }));
+
+        let ended = WorldEvent::EffectEnded {
+            player_id: Some(player_id),
+            kind: ItemKind::Speedup,
+        };
+        assert!(!step(&mut world, 1).contains(&ended));
+        world.clock.now += PLAYER_EFFECT_DURATION + PLAYER_EFFECT_DEVIATION_DURATION;
+        assert!(step(&mut world, 1).contains(&ended));
+        assert!(world.players[0].effects.is_empty());
+    }
+
+    #[test]
+    fn wall_crash_events() {
+        let mut world = running_world();
+        world.players[0].reset(pos2(1.0, 360.0));
+        world.players[0].angle = PI;
+        let (crashed, winner) = (&world.players[0], &world.players[1]);
+        let crashed_id = crashed.id;
+        let message = CrashMessage::Wall {
+            name: crashed.name.clone(),
+            color: crashed.color,
+        };
+        let score = WorldEvent::ScoreChanged {
+            player_id: winner.id,
+            score: 1,
+        };
+        let events = step(&mut world, 1);
+
+        assert!(events.contains(&WorldEvent::Crashed {
+            player_id: crashed_id,
+            pos: pos2(1.0, 360.0),
+            message,
+        }));
+        assert!(events.contains(&score));
+        assert!(events.contains(&WorldEvent::StateChanged {
+            from: GameState::Running(Duration::ZERO),
+            to: GameState::Stopped(Duration::ZERO),
+        }));
+        assert_eq!(world.round_winner(), Some(world.players[1].id));
+    }
+
+    fn last_arc(player: &Player) -> &ArcTrailSection {
+        match player.trail.last() {
+            Some(TrailSection::Arc(s)) => s,
+            s => panic!("expected an arc section, got {s:?}"),
+        }
+    }
+
+    #[test]
+    fn analog_steering_radius() {
+        let mut world = running_world();
+        let p = &mut world.players[0];
+        // without random gaps only steering changes start new sections
+        p.effects
+            .push(player_effect(&world.clock, PlayerEffect::NoGap));
+        p.set_remote_steering(0.5);
+        step(&mut world, 10);
+
+        let arc = last_arc(&world.players[0]);
+        assert_eq!(arc.dir, TurnDirection::Right);
+        assert_eq!(arc.radius, 2.0 * BASE_TURNING_RADIUS);
+        let num_sections = world.players[0].trail.len();
+
+        // changing the steering mid-turn starts a new section
+        world.players[0].set_remote_steering(-1.0);
+        step(&mut world, 10);
+        let arc = last_arc(&world.players[0]);
+        assert_eq!(arc.dir, TurnDirection::Left);
+        assert_eq!(arc.radius, BASE_TURNING_RADIUS);
+        assert_eq!(world.players[0].trail.len(), num_sections + 1);
+
+        world.players[0].set_remote_steering(-0.25);
+        step(&mut world, 10);
+        assert_eq!(
+            last_arc(&world.players[0]).radius,
+            4.0 * BASE_TURNING_RADIUS
+        );
+        assert_eq!(world.players[0].trail.len(), num_sections + 2);
+
+        // digital input turns at the full rate again
+        world.players[0].set_remote_direction(Direction::Right);
+        step(&mut world, 10);
+        let arc = last_arc(&world.players[0]);
+        assert_eq!(arc.dir, TurnDirection::Right);
+        assert_eq!(arc.radius, BASE_TURNING_RADIUS);
+        assert_eq!(world.players[0].trail.len(), num_sections + 3);
+    }
+
+    #[test]
+    fn analog_steering_is_clamped() {
+        let mut player = running_world().players.remove(0);
+        let mut steer = |steering| {
+            player.set_remote_steering(steering);
+            (player.remote_direction, player.remote_turn_rate)
+        };
+        assert_eq!(steer(0.5), (Direction::Right, 0.5));
+        assert_eq!(steer(-0.5), (Direction::Left, 0.5));
+        assert_eq!(steer(2.0), (Direction::Right, 1.0));
+        assert_eq!(steer(-2.0), (Direction::Left, 1.0));
+        assert_eq!(steer(f32::INFINITY), (Direction::Straight, 0.0));
+        assert_eq!(steer(f32::NEG_INFINITY), (Direction::Straight, 0.0));
+        assert_eq!(steer(f32::NAN), (Direction::Straight, 0.0));
+        // tiny values are rounded to going straight
+        assert_eq!(steer(0.01), (Direction::Straight, 0.0));
+
+        player.set_remote_steering(0.5);
+        player.set_remote_direction(Direction::Left);
+        assert_eq!(player.remote_direction, Direction::Left);
+        assert_eq!(player.remote_turn_rate, 1.0);
+    }
+}
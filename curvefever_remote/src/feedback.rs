@@ -0,0 +1,139 @@
+use curvefever_common::Feedback;
+use wasm_bindgen::JsValue;
+use web_sys::{AudioContext, OscillatorType};
+
+/// Signals [`Feedback`] for the controlled player by vibrating the phone and playing short sounds.
+pub struct PhoneFeedback {
+    pub vibration: bool,
+    pub sounds: bool,
+    /// Created when sounds are first enabled, since browsers only allow starting audio from a
+    /// user interaction.
+    audio: Option<AudioContext>,
+}
+
+impl Default for PhoneFeedback {
+    fn default() -> Self {
+        Self {
+            vibration: true,
+            sounds: false,
+            audio: None,
+        }
+    }
+}
+
+impl PhoneFeedback {
+    pub fn toggle_vibration(&mut self) {
+        self.vibration = !self.vibration;
+    }
+
+    /// Has to be called from a user interaction, since browsers only allow starting audio then.
+    pub fn toggle_sounds(&mut self) {
+        self.sounds = !self.sounds;
+        if self.sounds && self.audio.is_none() {
+            match AudioContext::new() {
+                Ok(a) => self.audio = Some(a),
+                Err(e) => log::error!("Error creating audio context: {e:?}"),
+            }
+        }
+    }
+
+    pub fn play(&self, feedback: Feedback) {
+        if self.vibration {
+            vibrate(vibration_pattern(feedback));
+        }
+        if let Some(audio) = self.audio.as_ref().filter(|_| self.sounds) {
+            if let Err(e) = play_tones(audio, tones(feedback)) {
+                log::error!("Error playing feedback sound: {e:?}");
+            }
+        }
+    }
+}
+
+/// Alternating vibration and pause durations in milliseconds.
+fn vibration_pattern(feedback: Feedback) -> &'static [u32] {
+    match feedback {
+        Feedback::Crash => &[300],
+        Feedback::ItemPickup => &[40],
+        Feedback::RoundWon => &[80, 60, 80, 60, 250],
+    }
+}
+
+fn vibrate(pattern: &[u32]) {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let pattern: js_sys::Array = pattern.iter().map(|&ms| JsValue::from(ms)).collect();
+    // returns false if vibration isn't supported, e.g. on iOS
+    if !window.navigator().vibrate_with_pattern(&pattern) {
+        log::debug!("Vibration isn't supported");
+    }
+}
+
+/// A single note, with the frequency sliding from `start_freq` to `end_freq`.
+struct Tone {
+    wave: OscillatorType,
+    start_freq: f32,
+    end_freq: f32,
+    /// The length in seconds.
+    duration: f64,
+    gain: f32,
+}
+
+const fn tone(
+    wave: OscillatorType,
+    start_freq: f32,
+    end_freq: f32,
+    duration: f64,
+    gain: f32,
+) -> Tone {
+    Tone {
+        wave,
+        start_freq,
+        end_freq,
+        duration,
+        gain,
+    }
+}
+
+/// The tones played one after another, similar to the sounds of the game.
+fn tones(feedback: Feedback) -> Vec<Tone> {
+    use OscillatorType::*;
+
+    match feedback {
+        Feedback::Crash => vec![tone(Square, 220.0, 110.0, 0.25, 0.2)],
+        Feedback::ItemPickup => vec![tone(Square, 400.0, 900.0, 0.12, 0.15)],
+        Feedback::RoundWon => vec![
+            tone(Triangle, 523.3, 523.3, 0.12, 0.4),
+            tone(Triangle, 659.3, 659.3, 0.12, 0.4),
+            tone(Triangle, 784.0, 784.0, 0.12, 0.4),
+            tone(Triangle, 1046.5, 1046.5, 0.35, 0.4),
+        ],
+    }
+}
+
+fn play_tones(audio: &AudioContext, tones: Vec<Tone>) -> Result<(), JsValue> {
+    // the context may be suspended, e.g. after the page was in the background
+    _ = audio.resume()?;
+
+    let mut start = audio.current_time();
+    for t in tones {
+        let end = start + t.duration;
+        let oscillator = audio.create_oscillator()?;
+        oscillator.set_type(t.wave);
+        let freq = oscillator.frequency();
+        freq.set_value_at_time(t.start_freq, start)?;
+        freq.linear_ramp_to_value_at_time(t.end_freq, end)?;
+
+        // fade out to avoid clicks
+        let gain = audio.create_gain()?;
+        gain.gain().set_value_at_time(t.gain, start)?;
+        gain.gain().linear_ramp_to_value_at_time(0.0, end)?;
+
+        oscillator.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&audio.destination())?;
+        oscillator.start_with_when(start)?;
+        oscillator.stop_with_when(end)?;
+        start = end;
+    }
+    Ok(())
+}
@@ -1,14 +1,22 @@
 use async_channel::{Receiver, Sender};
-use curvefever_common::{ClientEvent, Direction, GameEvent, Player};
+use curvefever_common::tilt::MAX_TILT;
+use curvefever_common::{ClientEvent, Decode, Direction, Encode, GameEvent, Player, POOR_RTT_MS};
 use eframe::CreationContext;
 use egui::{
-    Align, Align2, Button, CentralPanel, Color32, FontFamily, FontId, Frame, Key, Margin, Rect,
-    RichText, Rounding, ScrollArea, Sense, TextEdit, Vec2, WidgetText,
+    Align, Align2, Button, CentralPanel, Color32, FontFamily, FontId, Frame, Key, Margin, Pos2,
+    Rect, RichText, Rounding, ScrollArea, Sense, Stroke, TextEdit, Vec2, WidgetText,
 };
 use web_sys::{CloseEvent, ErrorEvent, Event, MessageEvent, OrientationType, WebSocket};
 
+use feedback::PhoneFeedback;
+use tilt::TiltSteering;
+
+mod feedback;
+mod tilt;
+
 const TEXT_SIZE: f32 = 20.0;
 const BUTTON_SPACE: f32 = 8.0;
+const POOR_LATENCY_COLOR: Color32 = Color32::from_rgb(250, 110, 40);
 
 fn main() {
     eframe::WebLogger::init(log::LevelFilter::Debug).ok();
@@ -48,6 +56,10 @@ struct CurvefeverRemoteApp {
     add_request_id: Option<u64>,
     player: Option<Player>,
     players: Vec<Player>,
+    tilt: TiltSteering,
+    feedback: PhoneFeedback,
+    /// The round trip time to the game in milliseconds, `None` until it was first measured.
+    latency: Option<u32>,
     client_sender: ClientSender,
     game_receiver: Receiver<GameEvent>,
 }
@@ -68,6 +80,9 @@ impl CurvefeverRemoteApp {
             add_request_id: None,
             player: None,
             players: Vec::new(),
+            tilt: TiltSteering::default(),
+            feedback: PhoneFeedback::default(),
+            latency: None,
             client_sender,
             game_receiver,
         }
@@ -96,6 +111,19 @@ impl eframe::App for CurvefeverRemoteApp {
                         request_fullscreen();
                     }
                 }
+                GameEvent::PlayerFeedback {
+                    player_id,
+                    feedback,
+                } => {
+                    if self.player.as_ref().is_some_and(|p| p.id == player_id) {
+                        self.feedback.play(feedback);
+                    }
+                }
+                GameEvent::Latency { rtt_ms } => {
+                    self.latency = Some(rtt_ms);
+                }
+                // answered by the websocket callback
+                GameEvent::Ping { .. } => (),
             }
         }
 
@@ -111,7 +139,15 @@ impl eframe::App for CurvefeverRemoteApp {
         };
 
         if let Some(player) = &mut self.player {
-            let left = draw_controls(ctx, orientation, &self.client_sender, player);
+            let left = draw_controls(
+                ctx,
+                orientation,
+                &self.client_sender,
+                &mut self.tilt,
+                &mut self.feedback,
+                self.latency,
+                player,
+            );
             if left {
                 self.player = None;
             }
@@ -176,6 +212,9 @@ fn draw_controls(
     ctx: &egui::Context,
     orientation: Orientation,
     client_sender: &ClientSender,
+    tilt: &mut TiltSteering,
+    feedback: &mut PhoneFeedback,
+    latency: Option<u32>,
     player: &mut Player,
 ) -> bool {
     let mut actions = Actions::default();
@@ -200,12 +239,28 @@ fn draw_controls(
         Orientation::Landscape => {
             ui.columns(3, |uis| {
                 actions.left_down |= touch_pad(&mut uis[0], "left");
-                draw_controls_menu(&mut uis[1], client_sender, &mut actions, player);
+                draw_controls_menu(
+                    &mut uis[1],
+                    client_sender,
+                    &mut actions,
+                    tilt,
+                    feedback,
+                    latency,
+                    player,
+                );
                 actions.right_down |= touch_pad(&mut uis[2], "right");
             });
         }
         Orientation::Portrait => {
-            draw_controls_menu(ui, client_sender, &mut actions, player);
+            draw_controls_menu(
+                ui,
+                client_sender,
+                &mut actions,
+                tilt,
+                feedback,
+                latency,
+                player,
+            );
             ui.columns(2, |uis| {
                 actions.left_down |= touch_pad(&mut uis[0], "left");
                 actions.right_down |= touch_pad(&mut uis[1], "right");
@@ -213,11 +268,20 @@ fn draw_controls(
         }
     });
 
-    let dir = Direction::from_left_right_down(actions.left_down, actions.right_down);
-    client_sender.send(ClientEvent::Input {
-        player_id: player.id,
-        dir,
-    });
+    // touch pads and keys take precedence over tilting
+    let touched = actions.left_down || actions.right_down;
+    if tilt.enabled && !touched {
+        client_sender.send(ClientEvent::AnalogInput {
+            player_id: player.id,
+            steering: tilt.steering(),
+        });
+    } else {
+        let dir = Direction::from_left_right_down(actions.left_down, actions.right_down);
+        client_sender.send(ClientEvent::Input {
+            player_id: player.id,
+            dir,
+        });
+    }
 
     if let Some(event) = actions.input_event {
         client_sender.send(event);
@@ -230,75 +294,170 @@ fn draw_controls_menu(
     ui: &mut egui::Ui,
     client_sender: &ClientSender,
     actions: &mut Actions,
+    tilt: &mut TiltSteering,
+    feedback: &mut PhoneFeedback,
+    latency: Option<u32>,
     player: &mut Player,
 ) {
-    ui.vertical_centered(|ui| {
-        Frame::none()
-            .outer_margin(Margin::symmetric(0.0, 16.0))
-            .show(ui, |ui| {
-                let color = player_color(player);
-                let resp = TextEdit::singleline(&mut player.name)
-                    .frame(false)
-                    .horizontal_align(Align::Center)
-                    .font(FontId::new(1.5 * TEXT_SIZE, FontFamily::Proportional))
-                    .text_color(color)
-                    .show(ui);
-                if resp.response.changed() {
-                    println!("changed: {}", player.name);
-                    client_sender.send(ClientEvent::Rename {
-                        player_id: player.id,
-                        name: player.name.clone(),
-                    });
-                }
+    // the menu doesn't fit on small screens in landscape orientation
+    ScrollArea::vertical().show(ui, |ui| {
+        ui.vertical_centered(|ui| {
+            Frame::none()
+                .outer_margin(Margin::symmetric(0.0, 16.0))
+                .show(ui, |ui| {
+                    let color = player_color(player);
+                    let resp = TextEdit::singleline(&mut player.name)
+                        .frame(false)
+                        .horizontal_align(Align::Center)
+                        .font(FontId::new(1.5 * TEXT_SIZE, FontFamily::Proportional))
+                        .text_color(color)
+                        .show(ui);
+                    if resp.response.changed() {
+                        curvefever_common::truncate_name(&mut player.name);
+                        client_sender.send(ClientEvent::Rename {
+                            player_id: player.id,
+                            name: player.name.clone(),
+                        });
+                    }
+
+                    if resp.response.has_focus() {
+                        ui.input(|i| {
+                            if i.key_pressed(Key::Enter) {
+                                resp.response.surrender_focus();
+                            }
+                        });
+                    }
+
+                    if let Some(rtt_ms) = latency {
+                        let text = if rtt_ms > POOR_RTT_MS {
+                            RichText::new(format!("latency: {rtt_ms} ms, poor connection"))
+                                .color(POOR_LATENCY_COLOR)
+                        } else {
+                            RichText::new(format!("latency: {rtt_ms} ms"))
+                        };
+                        ui.label(text.size(0.75 * TEXT_SIZE));
+                    }
 
-                if resp.response.has_focus() {
-                    ui.input(|i| {
-                        if i.key_pressed(Key::Enter) {
-                            resp.response.surrender_focus();
+                    ui.add_space(2.0 * BUTTON_SPACE);
+
+                    if button(ui, RichText::new("back").size(TEXT_SIZE)) {
+                        actions.back = true;
+                    }
+                    ui.add_space(BUTTON_SPACE);
+                    if button(ui, RichText::new("restart").size(TEXT_SIZE)) {
+                        actions.input_event = Some(ClientEvent::Restart);
+                    }
+                    ui.add_space(BUTTON_SPACE);
+                    if button(ui, RichText::new("pause").size(TEXT_SIZE)) {
+                        actions.input_event = Some(ClientEvent::Pause);
+                    }
+                    ui.add_space(BUTTON_SPACE);
+                    if button(ui, RichText::new("share").size(TEXT_SIZE)) {
+                        actions.input_event = Some(ClientEvent::Share);
+                    }
+                    ui.add_space(BUTTON_SPACE);
+                    if button(ui, RichText::new("help").size(TEXT_SIZE)) {
+                        actions.input_event = Some(ClientEvent::Help);
+                    }
+                    ui.add_space(BUTTON_SPACE);
+                    ui.columns(2, |uis| {
+                        let ui = &mut uis[0];
+                        if button(ui, RichText::new("prev color").size(TEXT_SIZE)) {
+                            client_sender.send(ClientEvent::PrevColor {
+                                player_id: player.id,
+                            });
+                        }
+                        let ui = &mut uis[1];
+                        if button(ui, RichText::new("next color").size(TEXT_SIZE)) {
+                            client_sender.send(ClientEvent::NextColor {
+                                player_id: player.id,
+                            });
                         }
                     });
-                }
 
-                ui.add_space(2.0 * BUTTON_SPACE);
+                    ui.add_space(BUTTON_SPACE);
+                    ui.columns(2, |uis| {
+                        let ui = &mut uis[0];
+                        let text = match feedback.vibration {
+                            true => "vibration: on",
+                            false => "vibration: off",
+                        };
+                        if button(ui, RichText::new(text).size(TEXT_SIZE)) {
+                            feedback.toggle_vibration();
+                        }
+                        let ui = &mut uis[1];
+                        let text = match feedback.sounds {
+                            true => "sounds: on",
+                            false => "sounds: off",
+                        };
+                        if button(ui, RichText::new(text).size(TEXT_SIZE)) {
+                            feedback.toggle_sounds();
+                        }
+                    });
 
-                if button(ui, RichText::new("back").size(TEXT_SIZE)) {
-                    actions.back = true;
-                }
-                ui.add_space(BUTTON_SPACE);
-                if button(ui, RichText::new("restart").size(TEXT_SIZE)) {
-                    actions.input_event = Some(ClientEvent::Restart);
-                }
-                ui.add_space(BUTTON_SPACE);
-                if button(ui, RichText::new("pause").size(TEXT_SIZE)) {
-                    actions.input_event = Some(ClientEvent::Pause);
-                }
-                ui.add_space(BUTTON_SPACE);
-                if button(ui, RichText::new("share").size(TEXT_SIZE)) {
-                    actions.input_event = Some(ClientEvent::Share);
-                }
-                ui.add_space(BUTTON_SPACE);
-                if button(ui, RichText::new("help").size(TEXT_SIZE)) {
-                    actions.input_event = Some(ClientEvent::Help);
-                }
-                ui.add_space(BUTTON_SPACE);
-                ui.columns(2, |uis| {
-                    let ui = &mut uis[0];
-                    if button(ui, RichText::new("prev color").size(TEXT_SIZE)) {
-                        client_sender.send(ClientEvent::PrevColor {
-                            player_id: player.id,
-                        });
+                    ui.add_space(BUTTON_SPACE);
+                    let text = match tilt.enabled {
+                        true => "tilt steering: on",
+                        false => "tilt steering: off",
+                    };
+                    if button(ui, RichText::new(text).size(TEXT_SIZE)) {
+                        tilt.toggle();
                     }
-                    let ui = &mut uis[1];
-                    if button(ui, RichText::new("next color").size(TEXT_SIZE)) {
-                        client_sender.send(ClientEvent::NextColor {
-                            player_id: player.id,
+                    if tilt.enabled {
+                        ui.add_space(BUTTON_SPACE);
+                        tilt_indicator(ui, tilt);
+                        ui.add_space(BUTTON_SPACE);
+                        ui.columns(2, |uis| {
+                            let ui = &mut uis[0];
+                            if button(ui, RichText::new("dead zone -").size(TEXT_SIZE)) {
+                                tilt.dead_zone_down();
+                            }
+                            let ui = &mut uis[1];
+                            if button(ui, RichText::new("dead zone +").size(TEXT_SIZE)) {
+                                tilt.dead_zone_up();
+                            }
                         });
                     }
-                });
-            })
+                })
+        });
     });
 }
 
+/// A bar showing the current tilt, with the dead zone in the center.
+fn tilt_indicator(ui: &mut egui::Ui, tilt: &TiltSteering) {
+    let size = Vec2::new(ui.available_width(), 2.0 * TEXT_SIZE);
+    let (rect, painter) = ui.allocate_painter(size, Sense::hover());
+    let rect = rect.rect;
+    painter.rect_filled(rect, Rounding::same(8.0), Color32::from_gray(0x20));
+
+    let x = |angle: f32| {
+        let offset = (angle / MAX_TILT).clamp(-1.0, 1.0);
+        rect.center().x + offset * rect.width() / 2.0
+    };
+    let dead_zone = Rect::from_x_y_ranges(x(-tilt.dead_zone)..=x(tilt.dead_zone), rect.y_range());
+    painter.rect_filled(dead_zone, Rounding::ZERO, Color32::from_gray(0x30));
+
+    let font = FontId::new(TEXT_SIZE, FontFamily::Proportional);
+    let text_color = Color32::from_gray(0xa0);
+    match tilt.tilt() {
+        Some(angle) => {
+            let color = match tilt.direction() {
+                Direction::Straight => Color32::from_gray(0xa0),
+                Direction::Right | Direction::Left => Color32::from_rgb(0x30, 0x60, 0xff),
+            };
+            let top = Pos2::new(x(angle), rect.top());
+            let bottom = Pos2::new(x(angle), rect.bottom());
+            painter.line_segment([top, bottom], Stroke::new(4.0_f32, color));
+            let text = format!("{angle:.0}° (dead zone {:.0}°)", tilt.dead_zone);
+            painter.text(rect.center(), Align2::CENTER_CENTER, text, font, text_color);
+        }
+        None => {
+            let text = "no orientation data";
+            painter.text(rect.center(), Align2::CENTER_CENTER, text, font, text_color);
+        }
+    }
+}
+
 fn request_fullscreen() {
     let Some(window) = web_sys::window() else {
         return;
@@ -372,7 +531,10 @@ struct ClientSender {
 impl ClientSender {
     fn send(&self, event: ClientEvent) {
         let mut buf = Vec::new();
-        event.encode(&mut buf).expect("should always succeed");
+        if let Err(e) = event.encode(&mut buf) {
+            log::error!("Error encoding message `{event:?}`:\n{e}");
+            return;
+        }
         let res = self.inner.send_with_u8_array(&buf);
         if let Err(e) = res {
             log::error!("Error sending message `{event:?}`:\n{e:?}");
@@ -389,12 +551,16 @@ fn start_websocket(
     let ws = WebSocket::new(url)?;
     ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
 
+    let pong_sender = ClientSender { inner: ws.clone() };
     let onmessage_callback = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
         if let Ok(buf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
             let array = js_sys::Uint8Array::new(&buf);
             let bytes = array.to_vec();
-            let mut cursor = std::io::Cursor::new(&bytes);
-            match GameEvent::decode(&mut cursor) {
+            match GameEvent::decode_exact(&bytes) {
+                // answer right away, so the round trip time doesn't depend on the frame rate
+                Ok(GameEvent::Ping { timestamp }) => {
+                    pong_sender.send(ClientEvent::Pong { timestamp });
+                }
                 Ok(event) => {
                     log::debug!("Received game event: {event:?}");
                     sender.try_send(event).unwrap();
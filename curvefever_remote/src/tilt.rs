@@ -0,0 +1,125 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use curvefever_common::tilt::{tilt_angle, tilt_direction, tilt_steering};
+use curvefever_common::Direction;
+use wasm_bindgen::prelude::*;
+use web_sys::DeviceOrientationEvent;
+
+const DEFAULT_DEAD_ZONE: f32 = 10.0;
+const MIN_DEAD_ZONE: f32 = 2.0;
+const MAX_DEAD_ZONE: f32 = 40.0;
+const DEAD_ZONE_STEP: f32 = 2.0;
+
+/// Steering by tilting the phone like a steering wheel, using `deviceorientation` events.
+///
+/// Orientation events can also be dispatched manually for testing, e.g. from the browser console:
+/// `dispatchEvent(new DeviceOrientationEvent("deviceorientation", { beta: 0, gamma: -20 }))`
+pub struct TiltSteering {
+    pub enabled: bool,
+    /// The tilt in degrees, in both directions, within which the player goes straight.
+    pub dead_zone: f32,
+    /// The latest tilt in degrees, `None` until the first orientation event is received.
+    tilt: Rc<Cell<Option<f32>>>,
+    listening: bool,
+}
+
+impl Default for TiltSteering {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dead_zone: DEFAULT_DEAD_ZONE,
+            tilt: Rc::new(Cell::new(None)),
+            listening: false,
+        }
+    }
+}
+
+impl TiltSteering {
+    /// Has to be called from a user interaction, since some browsers only deliver orientation
+    /// events after asking for permission.
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        if self.enabled && !self.listening {
+            request_permission();
+            match listen(Rc::clone(&self.tilt)) {
+                Ok(()) => self.listening = true,
+                Err(e) => log::error!("Error listening for orientation events: {e:?}"),
+            }
+        }
+    }
+
+    /// The tilt in degrees, see [`tilt_angle`].
+    pub fn tilt(&self) -> Option<f32> {
+        self.tilt.get()
+    }
+
+    /// See [`tilt_direction`].
+    pub fn direction(&self) -> Direction {
+        self.tilt()
+            .map_or(Direction::Straight, |t| tilt_direction(t, self.dead_zone))
+    }
+
+    /// See [`tilt_steering`].
+    pub fn steering(&self) -> f32 {
+        self.tilt()
+            .map_or(0.0, |t| tilt_steering(t, self.dead_zone))
+    }
+
+    pub fn dead_zone_down(&mut self) {
+        self.dead_zone = (self.dead_zone - DEAD_ZONE_STEP).max(MIN_DEAD_ZONE);
+    }
+
+    pub fn dead_zone_up(&mut self) {
+        self.dead_zone = (self.dead_zone + DEAD_ZONE_STEP).min(MAX_DEAD_ZONE);
+    }
+}
+
+fn listen(tilt: Rc<Cell<Option<f32>>>) -> Result<(), JsValue> {
+    let window = web_sys::window().ok_or("missing window")?;
+    let screen = window.screen()?;
+    let callback = Closure::<dyn FnMut(_)>::new(move |e: DeviceOrientationEvent| {
+        let (Some(beta), Some(gamma)) = (e.beta(), e.gamma()) else {
+            return;
+        };
+        let screen_angle = screen.orientation().angle().unwrap_or(0);
+        tilt.set(Some(tilt_angle(
+            beta as f32,
+            gamma as f32,
+            screen_angle as f32,
+        )));
+    });
+    window
+        .add_event_listener_with_callback("deviceorientation", callback.as_ref().unchecked_ref())?;
+    callback.forget();
+    Ok(())
+}
+
+/// Safari only delivers orientation events after `DeviceOrientationEvent.requestPermission()` was
+/// granted, other browsers don't have this function.
+fn request_permission() {
+    let global = js_sys::global();
+    let Ok(class) = js_sys::Reflect::get(&global, &"DeviceOrientationEvent".into()) else {
+        return;
+    };
+    let Ok(request) = js_sys::Reflect::get(&class, &"requestPermission".into()) else {
+        return;
+    };
+    let Some(request) = request.dyn_ref::<js_sys::Function>() else {
+        return;
+    };
+
+    let promise = match request.call0(&class) {
+        Ok(p) => js_sys::Promise::from(p),
+        Err(e) => {
+            log::error!("Error requesting orientation permission: {e:?}");
+            return;
+        }
+    };
+    wasm_bindgen_futures::spawn_local(async move {
+        match wasm_bindgen_futures::JsFuture::from(promise).await {
+            Ok(state) => log::debug!("Orientation permission: {state:?}"),
+            Err(e) => log::error!("Error requesting orientation permission: {e:?}"),
+        }
+    });
+}
@@ -0,0 +1,17 @@
+#![no_main]
+
+use curvefever_common::{ClientEvent, Decode, Encode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(event) = ClientEvent::decode_exact(data) else {
+        return;
+    };
+
+    // the encoding is canonical, so accepted messages must encode to the exact same bytes
+    let mut buf = Vec::new();
+    event
+        .encode(&mut buf)
+        .expect("decoded events should be encodable");
+    assert_eq!(buf, data);
+});
@@ -0,0 +1,19 @@
+#![no_main]
+
+use curvefever_common::json::{FromJson, ToJson};
+use curvefever_common::{ClientEvent, Encode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    let Ok(event) = ClientEvent::from_json_str(data) else {
+        return;
+    };
+
+    // the json encoding isn't canonical, so compare the binary encodings after a round trip
+    let json = event.to_json().to_string();
+    let round_trip = ClientEvent::from_json_str(&json).expect("encoded events should decode");
+    let (mut expected, mut actual) = (Vec::new(), Vec::new());
+    event.encode(&mut expected).expect("decoded events should be encodable");
+    round_trip.encode(&mut actual).expect("decoded events should be encodable");
+    assert_eq!(expected, actual);
+});